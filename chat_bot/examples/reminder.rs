@@ -0,0 +1,111 @@
+//! Echoes text and reminds people of things, run it with
+//! `cargo run -p chat_bot --example reminder [endpoint]`.
+
+use async_trait::async_trait;
+use chat_bot::{
+    Bot, BotCommand, BotRunner, CommandError, Config, Context, DurationArg, Invocation,
+};
+
+struct ReminderBot;
+
+#[async_trait]
+impl Bot for ReminderBot {
+    async fn on_ready(&self, context: &Context) {
+        log::info!("Ready in #{}", context.room().unwrap_or_default());
+    }
+
+    async fn on_join(&self, context: &Context, username: &str, _room: &str) {
+        if username != context.name() {
+            context.say(format!("Welcome {username}! Try !help"));
+        }
+    }
+}
+
+struct Echo;
+
+#[async_trait]
+impl BotCommand for Echo {
+    fn name(&self) -> &'static str {
+        "echo"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["say"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "<text>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Repeats the text"
+    }
+
+    async fn execute(
+        &self,
+        context: &Context,
+        mut invocation: Invocation,
+    ) -> Result<(), CommandError> {
+        context.say(invocation.arguments.rest("text")?);
+        Ok(())
+    }
+}
+
+struct Remind;
+
+#[async_trait]
+impl BotCommand for Remind {
+    fn name(&self) -> &'static str {
+        "remind"
+    }
+
+    fn usage(&self) -> &'static str {
+        "<duration> <reminder>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reminds you of something, e.g. !remind 10m Stretch your legs"
+    }
+
+    async fn execute(
+        &self,
+        context: &Context,
+        mut invocation: Invocation,
+    ) -> Result<(), CommandError> {
+        let DurationArg(delay) = invocation.arguments.required("duration")?;
+        let reminder = invocation.arguments.rest("reminder")?;
+
+        let message = invocation.message;
+        context.reply(&message, "I will remind you");
+        context.after(delay, move |context| async move {
+            context.reply(&message, format!("Reminder: {reminder}"));
+        });
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+
+    let mut config = Config {
+        name: "ReminderBot".to_string(),
+        ..Default::default()
+    };
+    if let Some(endpoint) = std::env::args().nth(1) {
+        config.endpoint = endpoint.parse().expect("Invalid endpoint");
+    }
+
+    let runner = BotRunner::new(config, ReminderBot)
+        .command(Echo)
+        .command(Remind);
+
+    tokio::select! {
+        result = runner.run() => {
+            if let Err(why) = result {
+                log::error!("{why}");
+            }
+        }
+        _ = tokio::signal::ctrl_c() => log::info!("Shutting down"),
+    }
+}
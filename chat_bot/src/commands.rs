@@ -0,0 +1,218 @@
+use crate::{Bot, Context, Message};
+use async_trait::async_trait;
+use chat_shared::arguments::{ArgumentError, Arguments};
+use std::{collections::HashMap, sync::Arc};
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("Unknown command {0}")]
+    UnknownCommand(String),
+    #[error(transparent)]
+    Argument(#[from] ArgumentError),
+    #[error("{0}")]
+    Failed(String),
+}
+
+/// A command someone sent to the bot, like `!remind 10m Stretch your legs`.
+#[derive(Debug)]
+pub struct Invocation {
+    /// The name of the command, without the prefix
+    pub name: String,
+    pub arguments: Arguments,
+    /// The message which invoked the command, to reply to
+    pub message: Message,
+}
+
+/// A command the bot responds to.
+#[async_trait]
+pub trait BotCommand: Send + Sync {
+    /// The name the command is invoked with, without the prefix
+    fn name(&self) -> &'static str;
+
+    /// Other names the command can be invoked with
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Describes the arguments, e.g. `<duration> <reminder>`
+    fn usage(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    async fn execute(&self, context: &Context, invocation: Invocation) -> Result<(), CommandError>;
+}
+
+/// Routes commands to their handlers.
+pub(crate) struct Commands {
+    /// Messages starting with it are commands
+    pub(crate) prefix: String,
+    commands: Vec<Arc<dyn BotCommand>>,
+    /// Maps names and aliases to their index in `commands`
+    lookup: HashMap<String, usize>,
+}
+
+impl Commands {
+    pub(crate) fn new(prefix: String) -> Self {
+        Self {
+            prefix,
+            commands: vec![],
+            lookup: HashMap::new(),
+        }
+    }
+
+    /// Adds a command, replacing any command which uses the same name or alias.
+    pub(crate) fn register(&mut self, command: impl BotCommand + 'static) {
+        let index = self.commands.len();
+        for name in std::iter::once(command.name()).chain(command.aliases().iter().copied()) {
+            self.lookup.insert(name.to_lowercase(), index);
+        }
+        self.commands.push(Arc::new(command));
+    }
+
+    fn get(&self, name: &str) -> Option<&Arc<dyn BotCommand>> {
+        self.lookup
+            .get(&name.to_lowercase())
+            .map(|index| &self.commands[*index])
+    }
+
+    /// Splits a message into a command, or returns `None` if it is none.
+    pub(crate) fn parse(&self, message: &Message) -> Option<Invocation> {
+        let input = message.content.strip_prefix(&self.prefix)?;
+        let (name, arguments) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(Invocation {
+            name: name.to_lowercase(),
+            arguments: Arguments::new(arguments),
+            message: message.clone(),
+        })
+    }
+
+    fn help(&self) -> String {
+        let mut lines = self
+            .commands
+            .iter()
+            .enumerate()
+            .filter(|(index, command)| self.lookup.get(command.name()) == Some(index))
+            .map(|(_, command)| {
+                format!(
+                    "{}{} {} - {}",
+                    self.prefix,
+                    command.name(),
+                    command.usage(),
+                    command.description()
+                )
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines.join(" | ")
+    }
+
+    /// Executes the command and replies with the error, if any.
+    pub(crate) async fn dispatch(&self, bot: &dyn Bot, context: &Context, invocation: Invocation) {
+        let message = invocation.message.clone();
+        let name = invocation.name.clone();
+        log::info!("{} used {}{}", message.username, self.prefix, name);
+
+        let result = match self.get(&name) {
+            Some(command) => command.execute(context, invocation).await,
+            None if name.eq("help") => {
+                context.reply(&message, self.help());
+                Ok(())
+            }
+            None => bot.on_command(context, invocation).await,
+        };
+
+        match result {
+            Ok(()) => {}
+            Err(why @ CommandError::Argument(_)) => {
+                let usage = self.get(&name).map(|c| c.usage()).unwrap_or_default();
+                let usage = format!("{}{name} {usage}", self.prefix);
+                context.reply(&message, format!("{why}. Usage: {}", usage.trim_end()));
+            }
+            Err(CommandError::UnknownCommand(name)) => {
+                let content = format!(
+                    "Unknown command {0}{name}, see {0}help for a list of commands",
+                    self.prefix
+                );
+                context.reply(&message, content);
+            }
+            Err(why) => context.reply(&message, why.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BotCommand, CommandError, Commands, Invocation};
+    use crate::{Bot, Context, Message};
+    use async_trait::async_trait;
+    use tokio::sync::mpsc;
+
+    struct Echo;
+
+    #[async_trait]
+    impl BotCommand for Echo {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn usage(&self) -> &'static str {
+            "<text>"
+        }
+
+        fn description(&self) -> &'static str {
+            "Repeats the text"
+        }
+
+        async fn execute(
+            &self,
+            context: &Context,
+            mut invocation: Invocation,
+        ) -> Result<(), CommandError> {
+            let text = invocation.arguments.rest("text")?;
+            context.reply(&invocation.message, text);
+            Ok(())
+        }
+    }
+
+    struct Silent;
+    impl Bot for Silent {}
+
+    fn message(content: &str, private: bool) -> Message {
+        Message {
+            id: None,
+            username: "Alice".to_string(),
+            content: content.to_string(),
+            private,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_routing() {
+        let (outgoing, mut lines) = mpsc::unbounded_channel();
+        let context = Context::new("Bot".to_string(), outgoing);
+        let mut commands = Commands::new("!".to_string());
+        commands.register(Echo);
+
+        assert!(commands.parse(&message("echo hi", false)).is_none());
+        assert!(commands.parse(&message("! echo", false)).is_none());
+
+        for (content, private) in [("!ECHO hi there", false), ("!echo", true), ("!nope", false)] {
+            let invocation = commands.parse(&message(content, private)).unwrap();
+            commands.dispatch(&Silent, &context, invocation).await;
+        }
+
+        assert_eq!(lines.try_recv().unwrap(), "Alice: hi there");
+        assert_eq!(
+            lines.try_recv().unwrap(),
+            "/msg Alice Missing argument <text>. Usage: !echo <text>"
+        );
+        assert_eq!(
+            lines.try_recv().unwrap(),
+            "Alice: Unknown command !nope, see !help for a list of commands"
+        );
+    }
+}
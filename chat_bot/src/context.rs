@@ -0,0 +1,177 @@
+use crate::Message;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::mpsc,
+    task::{AbortHandle, JoinHandle},
+    time::{interval_at, sleep, Instant, MissedTickBehavior},
+};
+
+#[derive(Debug, Default)]
+struct Identity {
+    name: String,
+    room: Option<String>,
+}
+
+/// What the hooks of a bot use to talk to the server. It is cheap to clone and stays valid
+/// across reconnects.
+#[derive(Clone)]
+pub struct Context {
+    /// Lines for the client, as if they were typed
+    outgoing: mpsc::UnboundedSender<String>,
+    identity: Arc<Mutex<Identity>>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+/// A scheduled task, which can be cancelled.
+#[derive(Debug)]
+pub struct TaskHandle(AbortHandle);
+
+impl TaskHandle {
+    pub fn cancel(&self) {
+        self.0.abort();
+    }
+}
+
+impl Context {
+    pub(crate) fn new(name: String, outgoing: mpsc::UnboundedSender<String>) -> Self {
+        Self {
+            outgoing,
+            identity: Arc::new(Mutex::new(Identity { name, room: None })),
+            tasks: Arc::default(),
+        }
+    }
+
+    pub(crate) fn set_identity(&self, name: String, room: Option<String>) {
+        *self.identity.lock().unwrap() = Identity { name, room };
+    }
+
+    /// The name the server knows the bot by.
+    pub fn name(&self) -> String {
+        self.identity.lock().unwrap().name.clone()
+    }
+
+    /// The room the bot is in, if any.
+    pub fn room(&self) -> Option<String> {
+        self.identity.lock().unwrap().room.clone()
+    }
+
+    /// Sends a line as if it was typed, so it may also be a command for the server.
+    pub fn send(&self, line: impl Into<String>) {
+        // The runner only stops once the bot is done, so this can't fail while hooks run
+        let _ = self.outgoing.send(line.into());
+    }
+
+    /// Sends a message to the room the bot is in. It is never run as a command, even if it
+    /// starts with a slash.
+    pub fn say(&self, content: impl AsRef<str>) {
+        let content = content.as_ref();
+        match content.starts_with('/') {
+            true => self.send(format!("/{content}")),
+            false => self.send(content),
+        }
+    }
+
+    /// Answers in the same place the message came from.
+    pub fn reply(&self, message: &Message, content: impl AsRef<str>) {
+        match message.private {
+            true => self.direct_message(&message.username, content),
+            false => self.say(format!("{}: {}", message.username, content.as_ref())),
+        }
+    }
+
+    pub fn direct_message(&self, username: &str, content: impl AsRef<str>) {
+        self.send(format!("/msg {username} {}", content.as_ref()));
+    }
+
+    pub fn join(&self, room: &str) {
+        self.send(format!("/join {room}"));
+    }
+
+    pub fn rename(&self, name: &str) {
+        self.send(format!("/nick {name}"));
+    }
+
+    fn spawn<F>(&self, task: F) -> TaskHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(task);
+        let abort = handle.abort_handle();
+
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| !task.is_finished());
+        tasks.push(handle);
+        TaskHandle(abort)
+    }
+
+    /// Runs the task once after the delay.
+    pub fn after<F, Fut>(&self, delay: Duration, task: F) -> TaskHandle
+    where
+        F: FnOnce(Context) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let context = self.clone();
+        self.spawn(async move {
+            sleep(delay).await;
+            task(context).await;
+        })
+    }
+
+    /// Runs the task every period, starting one period from now.
+    pub fn every<F, Fut>(&self, period: Duration, task: F) -> TaskHandle
+    where
+        F: Fn(Context) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let context = self.clone();
+        self.spawn(async move {
+            let mut interval = interval_at(Instant::now() + period, period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                task(context.clone()).await;
+            }
+        })
+    }
+
+    /// Cancels every scheduled task.
+    pub(crate) fn cancel_tasks(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Context;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scheduled_tasks() {
+        let (outgoing, mut lines) = mpsc::unbounded_channel();
+        let context = Context::new("Bot".to_string(), outgoing);
+
+        context.after(Duration::from_secs(5), |context| async move {
+            context.say("/not a command");
+        });
+        let repeated = context.every(Duration::from_secs(2), |context| async move {
+            context.say("tick");
+        });
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        repeated.cancel();
+        tokio::time::sleep(Duration::from_secs(5)).await;
+
+        let mut sent = vec![];
+        while let Ok(line) = lines.try_recv() {
+            sent.push(line);
+        }
+        assert_eq!(sent, vec!["tick", "tick", "//not a command"]);
+    }
+}
@@ -0,0 +1,55 @@
+//! A framework for bots, built on top of the [`chat_client`] library.
+//!
+//! Implement [`Bot`] for the hooks you need, register commands implementing [`BotCommand`]
+//! and hand both to a [`BotRunner`], which takes care of connecting and reconnecting.
+
+mod commands;
+mod context;
+mod runner;
+
+pub use chat_client::{Config, ServerEvent};
+pub use chat_shared::arguments::{ArgumentError, Arguments, DurationArg};
+pub use commands::{BotCommand, CommandError, Invocation};
+pub use context::{Context, TaskHandle};
+pub use runner::BotRunner;
+
+use async_trait::async_trait;
+
+/// A chat message the bot received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Only messages sent to a room have an ID
+    pub id: Option<String>,
+    pub username: String,
+    pub content: String,
+    /// Whether the message was sent directly to the bot
+    pub private: bool,
+}
+
+/// The hooks of a bot, all of them do nothing by default.
+///
+/// Events are handled one after another, so long running work should be spawned or
+/// scheduled with [`Context::after`].
+#[async_trait]
+pub trait Bot: Send + Sync + 'static {
+    /// Called whenever the bot (re)joined a room after connecting
+    async fn on_ready(&self, _context: &Context) {}
+
+    /// Called for every message which is not a command
+    async fn on_message(&self, _context: &Context, _message: &Message) {}
+
+    /// Called when someone, including the bot itself, joins a room
+    async fn on_join(&self, _context: &Context, _username: &str, _room: &str) {}
+
+    /// Called for commands which no registered [`BotCommand`] handles
+    async fn on_command(
+        &self,
+        _context: &Context,
+        invocation: Invocation,
+    ) -> Result<(), CommandError> {
+        Err(CommandError::UnknownCommand(invocation.name))
+    }
+
+    /// Called for every event before any of the other hooks
+    async fn on_event(&self, _context: &Context, _event: &ServerEvent) {}
+}
@@ -0,0 +1,163 @@
+use crate::{
+    commands::{BotCommand, Commands},
+    Bot, Context, Message,
+};
+use chat_client::{
+    client::backoff, utils::construct_hwid, ChatClient, ClientError, Config, ConnectionState,
+    ServerEvent,
+};
+use futures::StreamExt;
+use std::sync::Arc;
+use tokio::{sync::mpsc, time::sleep};
+
+/// Connects a bot to the server and keeps it connected.
+pub struct BotRunner<B: Bot> {
+    bot: Arc<B>,
+    config: Config,
+    hwid: Option<String>,
+    commands: Commands,
+}
+
+/// Cancels the scheduled tasks once the runner stops.
+struct CancelTasks(Context);
+
+impl Drop for CancelTasks {
+    fn drop(&mut self) {
+        self.0.cancel_tasks();
+    }
+}
+
+impl<B: Bot> BotRunner<B> {
+    pub fn new(config: Config, bot: B) -> Self {
+        Self {
+            bot: Arc::new(bot),
+            config,
+            hwid: None,
+            commands: Commands::new("!".to_string()),
+        }
+    }
+
+    /// The HWID the server knows the bot by. By default it is derived from the machine and the
+    /// name of the bot, so it does not clash with other clients on the same machine.
+    pub fn hwid(mut self, hwid: impl Into<String>) -> Self {
+        self.hwid = Some(hwid.into());
+        self
+    }
+
+    /// Messages starting with the prefix are commands, `!` by default.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.commands.prefix = prefix.into();
+        self
+    }
+
+    pub fn command(mut self, command: impl BotCommand + 'static) -> Self {
+        self.commands.register(command);
+        self
+    }
+
+    /// Runs the bot until the future is dropped. Only fails if the client can't be set up at
+    /// all, connection problems are retried forever.
+    pub async fn run(self) -> Result<(), ClientError> {
+        let hwid = match self.hwid.clone() {
+            Some(hwid) => hwid,
+            None => format!("{}-{}", construct_hwid()?, self.config.name),
+        };
+
+        let (outgoing, mut lines) = mpsc::unbounded_channel();
+        let context = Context::new(self.config.name.clone(), outgoing);
+        let _cancel_tasks = CancelTasks(context.clone());
+
+        let mut attempt = 0;
+        loop {
+            let (client, mut events) =
+                match ChatClient::connect_as(self.config.clone(), hwid.clone()).await {
+                    Ok(connected) => connected,
+                    Err(ClientError::Connect(endpoint)) => {
+                        attempt += 1;
+                        let delay = backoff(
+                            attempt,
+                            self.config.reconnect_delay,
+                            self.config.max_reconnect_delay,
+                        );
+                        log::warn!(
+                            "Unable to connect to {endpoint}, retrying in {:.1}s",
+                            delay.as_secs_f64()
+                        );
+                        sleep(delay).await;
+                        continue;
+                    }
+                    Err(why) => return Err(why),
+                };
+            attempt = 0;
+
+            // Set once connected, so the bot is told when it is in a room again
+            let mut connected = false;
+            loop {
+                tokio::select! {
+                    event = events.next() => match event {
+                        Some(event) => {
+                            if event == ServerEvent::Status(ConnectionState::Connected) {
+                                connected = true;
+                            }
+                            self.handle_event(&context, event, &mut connected).await;
+                        }
+                        None => break,
+                    },
+                    Some(line) = lines.recv() => {
+                        if client.send(line).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            // The client gave up reconnecting, so start over
+            log::warn!("The bot lost its connection, reconnecting");
+        }
+    }
+
+    async fn handle_event(&self, context: &Context, event: ServerEvent, connected: &mut bool) {
+        let bot = self.bot.as_ref();
+        bot.on_event(context, &event).await;
+
+        let message = match event {
+            ServerEvent::Identity { name, room } => {
+                let ready = room.is_some() && std::mem::take(connected);
+                context.set_identity(name, room);
+                if ready {
+                    bot.on_ready(context).await;
+                }
+                return;
+            }
+            ServerEvent::UserJoined { username, room } => {
+                bot.on_join(context, &username, &room).await;
+                return;
+            }
+            ServerEvent::Message {
+                id,
+                username,
+                content,
+                ..
+            } => Message {
+                id: Some(id),
+                username,
+                content,
+                private: false,
+            },
+            ServerEvent::DirectMessage {
+                username, content, ..
+            } => Message {
+                id: None,
+                username,
+                content,
+                private: true,
+            },
+            _ => return,
+        };
+
+        match self.commands.parse(&message) {
+            Some(invocation) => self.commands.dispatch(bot, context, invocation).await,
+            None => bot.on_message(context, &message).await,
+        }
+    }
+}
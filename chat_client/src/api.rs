@@ -0,0 +1,360 @@
+use crate::{
+    client::Client,
+    types::{Config, ConnectionState, ServerEvent},
+    utils::construct_hwid,
+    ClientError,
+};
+use futures::Stream;
+use std::{
+    collections::VecDeque,
+    io,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+/// A handle to a client running in the background. It reconnects whenever the connection is
+/// lost, until it is dropped or [`ChatClient::quit`] is called.
+pub struct ChatClient {
+    input: mpsc::UnboundedSender<String>,
+    task: JoinHandle<io::Result<()>>,
+}
+
+/// The events of a [`ChatClient`], the stream ends once the client stopped.
+pub struct ServerEvents {
+    /// Events which arrived while connecting
+    pending: VecDeque<ServerEvent>,
+    events: mpsc::UnboundedReceiver<ServerEvent>,
+}
+
+impl ChatClient {
+    /// Connects with the HWID of this machine, see [`ChatClient::connect_as`].
+    pub async fn connect(config: Config) -> Result<(Self, ServerEvents), ClientError> {
+        Self::connect_as(config, construct_hwid()?).await
+    }
+
+    /// Connects to the server in the config, fails if the first attempt does.
+    pub async fn connect_as(
+        config: Config,
+        hwid: String,
+    ) -> Result<(Self, ServerEvents), ClientError> {
+        let endpoint = config.endpoint;
+        let (input, inbox) = mpsc::unbounded_channel();
+        let (events, mut receiver) = mpsc::unbounded_channel();
+        let task = tokio::spawn(Client::new(config, hwid, events).run(inbox));
+
+        let mut pending = VecDeque::new();
+        loop {
+            match receiver.recv().await {
+                Some(ServerEvent::Status(
+                    ConnectionState::Reconnecting { .. } | ConnectionState::Disconnected,
+                ))
+                | None => {
+                    task.abort();
+                    return Err(ClientError::Connect(endpoint));
+                }
+                Some(event) => {
+                    let connected = event == ServerEvent::Status(ConnectionState::Connected);
+                    pending.push_back(event);
+                    if connected {
+                        break;
+                    }
+                }
+            }
+        }
+
+        let events = ServerEvents {
+            pending,
+            events: receiver,
+        };
+        Ok((Self { input, task }, events))
+    }
+
+    /// Sends a line as if it was typed, so it may also be a command like `/who`.
+    /// Lines sent while reconnecting are queued.
+    pub fn send(&self, line: impl Into<String>) -> Result<(), ClientError> {
+        self.input
+            .send(line.into())
+            .map_err(|_| ClientError::Stopped)
+    }
+
+    /// Leaves the current room, the room may be given with or without its leading `#`.
+    pub fn join(&self, room: &str) -> Result<(), ClientError> {
+        let room = room.strip_prefix('#').unwrap_or(room);
+        self.send(format!("/join {}", validate(room)?))
+    }
+
+    /// Answered with a [`ServerEvent::UsernameChanged`] unless the name is taken.
+    pub fn rename(&self, name: &str) -> Result<(), ClientError> {
+        self.send(format!("/nick {}", validate(name)?))
+    }
+
+    /// Kept for the user until their next login if they are offline.
+    pub fn direct_message(&self, username: &str, content: &str) -> Result<(), ClientError> {
+        self.send(format!("/msg {} {content}", validate(username)?))
+    }
+
+    /// Only works for our own messages, unless we are a moderator.
+    pub fn edit(&self, id: &str, content: &str) -> Result<(), ClientError> {
+        self.send(format!("/edit {} {content}", validate(id)?))
+    }
+
+    /// Like [`ChatClient::edit`], only works for our own messages unless we are a moderator.
+    pub fn delete(&self, id: &str) -> Result<(), ClientError> {
+        self.send(format!("/delete {}", validate(id)?))
+    }
+
+    /// The reply is posted in the room of the message it replies to.
+    pub fn reply(&self, id: &str, content: &str) -> Result<(), ClientError> {
+        self.send(format!("/reply {} {content}", validate(id)?))
+    }
+
+    /// The messages of the thread arrive as [`ServerEvent::ThreadMessage`]s.
+    pub fn fetch_thread(&self, id: &str) -> Result<(), ClientError> {
+        self.send(format!("/thread {}", validate(id)?))
+    }
+
+    /// Replies in other rooms arrive as [`ServerEvent::ThreadMessage`]s, also after reconnecting.
+    pub fn follow(&self, id: &str) -> Result<(), ClientError> {
+        self.send(format!("/follow {}", validate(id)?))
+    }
+
+    /// Stops the replies in other rooms, replies in the current room still arrive.
+    pub fn unfollow(&self, id: &str) -> Result<(), ClientError> {
+        self.send(format!("/unfollow {}", validate(id)?))
+    }
+
+    /// Takes an emoji or a shortcode like `:tada:`, the new counts arrive as
+    /// [`ServerEvent::ReactionsUpdated`].
+    pub fn react(&self, id: &str, reaction: &str) -> Result<(), ClientError> {
+        self.send(format!("/react {} {}", validate(id)?, validate(reaction)?))
+    }
+
+    /// Takes back our own reaction.
+    pub fn unreact(&self, id: &str, reaction: &str) -> Result<(), ClientError> {
+        self.send(format!(
+            "/unreact {} {}",
+            validate(id)?,
+            validate(reaction)?
+        ))
+    }
+
+    /// Shares the file in the current room, everyone there receives a
+    /// [`ServerEvent::FileShared`] once the upload is complete.
+    pub fn upload(&self, path: &Path) -> Result<(), ClientError> {
+        self.send(format!("/upload {}", path.display()))
+    }
+
+    /// Saves a shared file to the downloads directory of the config, announced with a
+    /// [`ServerEvent::FileDownloaded`].
+    pub fn download(&self, id: &str) -> Result<(), ClientError> {
+        self.send(format!("/download {}", validate(id)?))
+    }
+
+    /// Marks the room as read up to the message, or up to its latest message without one. The
+    /// counts of unread messages arrive in the [`ServerEvent::Welcome`] after the next login.
+    pub fn mark_read(&self, room: &str, id: Option<&str>) -> Result<(), ClientError> {
+        let room = validate(room.strip_prefix('#').unwrap_or(room))?;
+        match id {
+            Some(id) => self.send(format!("/read {room} {}", validate(id)?)),
+            None => self.send(format!("/read {room}")),
+        }
+    }
+
+    /// Disconnects once everything which was sent before left the client.
+    pub async fn quit(self) -> Result<(), ClientError> {
+        drop(self.input);
+        self.task.await.map_err(|_| ClientError::Stopped)??;
+        Ok(())
+    }
+}
+
+/// Names and rooms are single words.
+fn validate(name: &str) -> Result<&str, ClientError> {
+    match name.is_empty() || name.contains(char::is_whitespace) {
+        true => Err(ClientError::InvalidName(name.to_string())),
+        false => Ok(name),
+    }
+}
+
+impl Stream for ServerEvents {
+    type Item = ServerEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Poll::Ready(Some(event));
+        }
+
+        self.events.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChatClient;
+    use crate::{
+        types::{Config, ConnectionState, ServerEvent},
+        utils::write_to_stream,
+        ClientError,
+    };
+    use chat_shared::{
+        protocols::{
+            client::{ChangeUsername, ChatMessage, ClientMessageType, MarkRead},
+            server::{AuthenticateToken, UserJoined},
+        },
+        types::Deserialize,
+        utils::read_frame,
+    };
+    use futures::StreamExt;
+    use std::time::Duration;
+    use tokio::{
+        net::{TcpListener, TcpStream},
+        time::timeout,
+    };
+
+    async fn next_frame(stream: &mut TcpStream) -> Vec<u8> {
+        read_frame(stream, 2048).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_chat_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            endpoint: listener.local_addr().unwrap(),
+            name: "Alice".to_string(),
+            ..Default::default()
+        };
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let authentication = next_frame(&mut stream).await;
+            assert_eq!(
+                ClientMessageType::from(authentication[0]),
+                ClientMessageType::RequestAuthentication
+            );
+
+            let token = AuthenticateToken {
+                token: "token".to_string(),
+            };
+            let joined = UserJoined {
+                username: "Alice".to_string(),
+                room: "general".to_string(),
+            };
+            write_to_stream(&mut stream, &token).await.unwrap();
+            write_to_stream(&mut stream, &joined).await.unwrap();
+
+            let message = ChatMessage::deserialize(&next_frame(&mut stream).await)
+                .await
+                .unwrap();
+            let rename = ChangeUsername::deserialize(&next_frame(&mut stream).await)
+                .await
+                .unwrap();
+            let read = MarkRead::deserialize(&next_frame(&mut stream).await)
+                .await
+                .unwrap();
+            (message.content, rename.new_username, read.room)
+        });
+
+        let (client, mut events) = ChatClient::connect_as(config, "hwid".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            events.next().await,
+            Some(ServerEvent::Status(ConnectionState::Connecting))
+        );
+        assert_eq!(
+            events.next().await,
+            Some(ServerEvent::Status(ConnectionState::Connected))
+        );
+        assert_eq!(
+            events.next().await,
+            Some(ServerEvent::Identity {
+                name: "Alice".to_string(),
+                room: Some("general".to_string()),
+            })
+        );
+
+        client.send("Hello").unwrap();
+        client.rename("Bob").unwrap();
+        client.mark_read("#general", None).unwrap();
+        assert!(matches!(
+            client.join("two words"),
+            Err(ClientError::InvalidName(_))
+        ));
+
+        let (content, new_username, room) = server.await.unwrap();
+        assert_eq!(content, "Hello");
+        assert_eq!(new_username, "Bob");
+        assert_eq!(room, "general");
+
+        client.quit().await.unwrap();
+        let last = events.collect::<Vec<_>>().await.pop();
+        assert_eq!(
+            last,
+            Some(ServerEvent::Status(ConnectionState::Disconnected))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unresponsive_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Config {
+            endpoint: listener.local_addr().unwrap(),
+            name: "Alice".to_string(),
+            heartbeat_interval: Duration::from_millis(50),
+            heartbeat_timeout: Duration::from_millis(300),
+            ..Default::default()
+        };
+
+        // Accepts the client and reads its pings, but never answers them
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            next_frame(&mut stream).await;
+            let token = AuthenticateToken {
+                token: "token".to_string(),
+            };
+            write_to_stream(&mut stream, &token).await.unwrap();
+            let mut pings = 0;
+            while let Ok(Some(frame)) = read_frame(&mut stream, 2048).await {
+                if ClientMessageType::from(frame[0]) == ClientMessageType::Ping {
+                    pings += 1;
+                }
+            }
+            pings
+        });
+
+        let (client, mut events) = ChatClient::connect_as(config, "hwid".to_string())
+            .await
+            .unwrap();
+        let reconnecting = timeout(Duration::from_secs(5), async {
+            while let Some(event) = events.next().await {
+                if let ServerEvent::Status(ConnectionState::Reconnecting { .. }) = event {
+                    return true;
+                }
+            }
+            false
+        });
+        assert!(reconnecting.await.unwrap());
+        // The deadline is not pushed back by our own pings
+        assert!(server.await.unwrap() > 1);
+        client.quit().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_fails() {
+        // Nothing is listening on this port once the listener is dropped
+        let endpoint = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let config = Config {
+            endpoint,
+            ..Default::default()
+        };
+
+        let result = ChatClient::connect_as(config, "hwid".to_string()).await;
+        assert!(matches!(result, Err(ClientError::Connect(e)) if e == endpoint));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::ui::headless::Options;
+use chat_client::Config;
+use chat_shared::config;
+use clap::Parser;
+use std::{env, net::SocketAddr, path::PathBuf, time::Duration};
+
+/// The chat client. Flags and CHAT_* environment variables take precedence over the config file.
+///
+/// Without any of the headless flags the interactive interface is started. Otherwise commands
+/// are read from the script (`-` for stdin), the --send flags or stdin and incoming events are
+/// printed as JSON lines. Empty lines and lines starting with # are skipped.
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about,
+    after_help = "Exit codes: 0 success, 2 invalid usage or config, 3 unable to connect, \
+                  4 no acknowledgement in time, 5 connection lost"
+)]
+pub struct Cli {
+    /// The config file, ./client_config.toml if it exists or else
+    /// $XDG_CONFIG_HOME/rust_chat/client_config.toml. The defaults are used if it does not exist
+    #[arg(long, env = "CHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Write the default config to the config file and exit
+    #[arg(long)]
+    pub write_config: bool,
+
+    /// The address of the server, e.g. 127.0.0.1:7878
+    #[arg(long, env = "CHAT_ENDPOINT")]
+    pub endpoint: Option<SocketAddr>,
+
+    /// The name to chat as
+    #[arg(long, env = "CHAT_NAME")]
+    pub name: Option<String>,
+
+    /// A level like `debug` or filters like `chat_client=trace`, takes precedence over RUST_LOG
+    /// and the `log.level` of the config
+    #[arg(long, env = "CHAT_LOG")]
+    pub log_level: Option<String>,
+
+    /// Run without an interface, even if no other headless flag is given
+    #[arg(long, help_heading = "Headless")]
+    pub headless: bool,
+
+    /// Read the commands from the file
+    #[arg(long, conflicts_with = "send", help_heading = "Headless")]
+    pub script: Option<PathBuf>,
+
+    /// Send the text, may be given multiple times
+    #[arg(long, help_heading = "Headless")]
+    pub send: Vec<String>,
+
+    /// Join the room before sending anything
+    #[arg(long, help_heading = "Headless")]
+    pub room: Option<String>,
+
+    /// Wait until the server handled everything before exiting
+    #[arg(long, help_heading = "Headless")]
+    pub wait_for_ack: bool,
+
+    /// How long to wait for the acknowledgement in seconds, the connect timeout by default
+    #[arg(long, value_name = "SECONDS", value_parser = parse_seconds, help_heading = "Headless")]
+    pub timeout: Option<Duration>,
+}
+
+fn parse_seconds(value: &str) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("'{value}' is not a number of seconds"))
+}
+
+impl Cli {
+    pub fn config_path(&self) -> PathBuf {
+        self.config
+            .clone()
+            .unwrap_or_else(|| config::default_path("client_config.toml"))
+    }
+
+    /// Overrides the settings of the config file.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(endpoint) = self.endpoint {
+            config.endpoint = endpoint;
+        }
+        if let Some(name) = &self.name {
+            config.name = name.clone();
+        }
+    }
+
+    /// Returns `None` if no flag asks for headless mode.
+    pub fn headless(&self) -> Option<Options> {
+        let headless = self.headless
+            || self.script.is_some()
+            || !self.send.is_empty()
+            || self.room.is_some()
+            || self.wait_for_ack
+            || self.timeout.is_some();
+
+        headless.then(|| Options {
+            script: self.script.clone(),
+            send: self.send.clone(),
+            room: self.room.clone(),
+            wait_for_ack: self.wait_for_ack,
+            timeout: self.timeout,
+        })
+    }
+
+    /// The filters which override the config.
+    pub fn log_filters(&self) -> Option<String> {
+        self.log_level.clone().or_else(|| env::var("RUST_LOG").ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cli;
+    use crate::ui::headless::Options;
+    use chat_client::Config;
+    use clap::Parser;
+
+    fn parse(arguments: &[&str]) -> Result<Cli, clap::Error> {
+        Cli::try_parse_from(["chat_client"].iter().chain(arguments))
+    }
+
+    #[test]
+    fn test_parse_options() {
+        assert_eq!(parse(&[]).unwrap().headless(), None);
+        assert_eq!(
+            parse(&["--headless"]).unwrap().headless(),
+            Some(Options::default())
+        );
+
+        let cli = parse(&[
+            "--send",
+            "hi",
+            "--send",
+            "/who",
+            "--room",
+            "rust",
+            "--wait-for-ack",
+            "--name",
+            "Tester",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.headless(),
+            Some(Options {
+                send: vec!["hi".to_string(), "/who".to_string()],
+                room: Some("rust".to_string()),
+                wait_for_ack: true,
+                ..Default::default()
+            })
+        );
+
+        let mut config = Config::default();
+        cli.apply(&mut config);
+        assert_eq!(config.name, "Tester");
+
+        assert!(parse(&["--send"]).is_err());
+        assert!(parse(&["--timeout", "soon"]).is_err());
+        assert!(parse(&["--script", "-", "--send", "hi"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+    }
+}
@@ -1,99 +1,906 @@
 use std::{
-    io::{self, Read},
-    net::TcpStream,
-    process,
+    collections::{BTreeSet, HashMap, VecDeque},
+    time::Duration,
 };
 
 use chat_shared::{
-    protocols::client::{ChatMessage, RequestAuthentication},
-    protocols::server::{AuthenticateToken, BroadcastMessage, ServerMessageType},
+    error::DeserializerError,
+    protocols::client::{
+        self, AddReaction, ChangeUsername, ChatMessage, DeleteMessage, DownloadChunk, EditMessage,
+        FetchThread, FollowThread, MarkRead, RemoveReaction, RequestAuthentication, ResumeSession,
+        StartUpload, UnfollowThread, UploadChunk,
+    },
+    protocols::server::{
+        AuthenticateToken, BroadcastMessage, DirectMessage, FileChunk, FileShared, Mention,
+        MessageDeleted, MessageEdited, MessageSent, Ping, Pong, ReactionsUpdated, RoomMembers,
+        ServerMessageType, ServerShutdown, SystemMessage, ThreadMessage, UploadOffset, UserJoined,
+        UserLeft, UsernameChanged, Welcome,
+    },
     types::Deserialize,
+    utils::{chunk_size, read_frame},
+};
+use rand::Rng;
+use tokio::{
+    io,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::mpsc,
+    time::{interval_at, sleep, sleep_until, timeout, Instant, MissedTickBehavior},
+};
+use tracing::Instrument;
+
+use crate::{
+    commands::{Command, SETTINGS},
+    transfer::{Received, Transfers},
+    types::{Config, ConnectionState, ServerEvent},
+    utils::write_to_stream,
 };
 
-use crate::{types::Config, utils::write_to_stream};
+/// Messages typed while disconnected are kept, up to this many
+const MAX_QUEUED_MESSAGES: usize = 100;
 
-#[derive(Clone, Debug)]
-pub struct Client;
+type Frame = Result<Option<Vec<u8>>, DeserializerError>;
+
+/// Why a connection ended.
+enum Disconnect {
+    /// The connection was lost and should be reestablished
+    Lost,
+    /// The user is done chatting
+    Quit,
+    /// The user asked to reconnect right away
+    Reconnect,
+}
+
+pub struct Client {
+    config: Config,
+    hwid: String,
+    /// The name the server knows us by, so the same one is requested after reconnecting
+    name: String,
+    /// Used to resume the session after reconnecting
+    session_token: Option<String>,
+    /// The room we are in
+    room: Option<String>,
+    /// The room to get back into, in case the server could not resume the session
+    rejoin: Option<String>,
+    /// The threads to follow again after reconnecting, the server forgets them with the connection
+    following: BTreeSet<String>,
+    /// Uploads and downloads, which are continued after reconnecting as well
+    transfers: Transfers,
+    /// The members of a room received so far, long lists are split over several frames
+    members: Option<(String, Vec<String>)>,
+    /// Messages which have not been sent yet
+    queue: VecDeque<Command>,
+    /// When the pings the user asked for were sent, by their token
+    pings: HashMap<String, Instant>,
+    /// How long the server asked us to wait before reconnecting, when it shut down
+    reconnect_after: Option<Duration>,
+    /// Where everything the user should see goes
+    events: mpsc::UnboundedSender<ServerEvent>,
+}
 
 impl Client {
-    pub async fn start(stream: &TcpStream, config: &Config, hwid: &String) -> io::Result<()> {
-        // It is required to send the HWID to the server to authorize with it
-        Self::request_authentication(&stream, &config, hwid.to_string()).await;
+    pub fn new(config: Config, hwid: String, events: mpsc::UnboundedSender<ServerEvent>) -> Self {
+        Self {
+            name: config.name.clone(),
+            transfers: Transfers::new(config.downloads.clone()),
+            config,
+            hwid,
+            session_token: None,
+            room: None,
+            rejoin: None,
+            following: BTreeSet::new(),
+            members: None,
+            queue: VecDeque::new(),
+            pings: HashMap::new(),
+            reconnect_after: None,
+            events,
+        }
+    }
 
-        let read_stream = stream.try_clone()?;
-        let cloned_config = config.clone();
-        tokio::spawn(async move {
-            Self::read_messages(read_stream, &cloned_config).await;
+    fn emit(&self, event: ServerEvent) {
+        // The interface might already be closed while we are shutting down
+        let _ = self.events.send(event);
+    }
+
+    fn emit_identity(&self) {
+        self.emit(ServerEvent::Identity {
+            name: self.name.clone(),
+            room: self.room.clone(),
         });
+    }
 
+    /// Sends every line of the inbox until it is closed or the user quits, reconnecting whenever
+    /// the connection is lost. Lines starting with a slash are commands, see [`Command`].
+    pub async fn run(mut self, mut inbox: mpsc::UnboundedReceiver<String>) -> io::Result<()> {
+        let mut attempt = 0;
         loop {
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
+            self.emit(ServerEvent::Status(ConnectionState::Connecting));
+            let endpoint = self.config.endpoint;
+            match timeout(self.config.timeout, TcpStream::connect(endpoint)).await {
+                Ok(Ok(stream)) => {
+                    tracing::info!("Connected to server");
+                    self.emit(ServerEvent::Status(ConnectionState::Connected));
+                    attempt = 0;
 
-            let trimmed_input = input.trim();
-            if trimmed_input.is_empty() {
-                continue;
+                    let span = tracing::info_span!("connection", %endpoint, name = self.name);
+                    match self
+                        .handle_connection(stream, &mut inbox)
+                        .instrument(span)
+                        .await
+                    {
+                        Disconnect::Quit => {
+                            self.emit(ServerEvent::Status(ConnectionState::Disconnected));
+                            return Ok(());
+                        }
+                        Disconnect::Reconnect => {
+                            tracing::info!("Reconnecting...");
+                            continue;
+                        }
+                        Disconnect::Lost => tracing::warn!("Lost connection to the server"),
+                    }
+                }
+                Ok(Err(why)) => tracing::error!("Can't connect to endpoint! {why}"),
+                Err(_) => tracing::error!("Can't connect to endpoint! Timed out"),
             }
 
-            let message = ChatMessage {
-                hwid: hwid.to_string(),
-                content: trimmed_input.to_string(),
-            };
+            attempt += 1;
+            let max_attempts = self.config.max_reconnect_attempts;
+            if max_attempts != 0 && attempt > max_attempts {
+                tracing::error!("Giving up after {max_attempts} attempts to reconnect");
+                self.emit(ServerEvent::System {
+                    content: format!("Giving up after {max_attempts} attempts to reconnect"),
+                });
+                self.emit(ServerEvent::Status(ConnectionState::Disconnected));
+                return Ok(());
+            }
 
-            write_to_stream(&stream, &message).await.unwrap();
+            let delay = self.reconnect_after.take().unwrap_or_else(|| {
+                backoff(
+                    attempt,
+                    self.config.reconnect_delay,
+                    self.config.max_reconnect_delay,
+                )
+            });
+            tracing::info!("Reconnecting in {:.1}s...", delay.as_secs_f64());
+            self.emit(ServerEvent::Status(ConnectionState::Reconnecting {
+                attempt,
+                delay,
+            }));
+
+            // Everything typed in the meantime is sent once we are back
+            let wait = sleep(delay);
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    line = inbox.recv() => match line.map(|line| self.accept(&line)) {
+                        Some(None) => {}
+                        Some(Some(Disconnect::Reconnect)) => break,
+                        Some(Some(_)) | None => {
+                            self.emit(ServerEvent::Status(ConnectionState::Disconnected));
+                            return Ok(());
+                        }
+                    },
+                }
+            }
         }
     }
 
-    async fn read_messages(mut stream: TcpStream, config: &Config) {
-        let mut buffer = vec![0; config.buffer_size];
+    async fn handle_connection(
+        &mut self,
+        stream: TcpStream,
+        inbox: &mut mpsc::UnboundedReceiver<String>,
+    ) -> Disconnect {
+        let (read_stream, mut write_stream) = stream.into_split();
 
-        loop {
-            match stream.read(&mut buffer) {
-                Ok(bytes_read) => {
-                    if bytes_read == 0 {
-                        log::warn!("Server disconnected");
-                        process::exit(0);
-                    }
+        // It is required to send the HWID to the server to authorize with it
+        if !self.request_authentication(&mut write_stream).await {
+            tracing::error!("Error authenticating");
+            return Disconnect::Lost;
+        }
+        self.rejoin = self.room.take();
+        // Their answers got lost with the previous connection
+        self.pings.clear();
 
-                    match ServerMessageType::from(buffer[0]) {
-                        ServerMessageType::AuthenticateToken => {
-                            let message = AuthenticateToken::deserialize(&buffer).await.unwrap();
+        // Reading a frame can't be cancelled halfway, so it is done in a separate task
+        let (frames, mut frame_inbox) = mpsc::unbounded_channel();
+        let reader = tokio::spawn(Self::read_frames(
+            read_stream,
+            frames,
+            self.config.buffer_size,
+        ));
 
-                            log::info!("Session-Token: {}", message.token);
+        let period = self.config.heartbeat_interval;
+        let mut heartbeat = interval_at(Instant::now() + period, period);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut authenticated = false;
+        let mut ready = false;
+        self.members = None;
+        // Only frames from the server prove that it is alive, our own pings and input do not
+        let mut alive_until = Instant::now() + self.config.heartbeat_timeout;
+
+        let disconnect = loop {
+            tokio::select! {
+                frame = frame_inbox.recv() => {
+                    let buffer = match frame {
+                        Some(Ok(Some(buffer))) => buffer,
+                        Some(Ok(None)) | None => {
+                            tracing::warn!("Server disconnected");
+                            break Disconnect::Lost;
+                        }
+                        Some(Err(why)) => {
+                            tracing::error!("Error reading from server! {why}");
+                            break Disconnect::Lost;
                         }
-                        ServerMessageType::BroadcastMessage => {
-                            let message = BroadcastMessage::deserialize(&buffer).await.unwrap();
+                    };
+                    alive_until = Instant::now() + self.config.heartbeat_timeout;
 
-                            log::info!("{} --> {}", message.username, message.content);
+                    if !self.handle_frame(&buffer, &mut write_stream).await {
+                        break Disconnect::Lost;
+                    }
+
+                    if ServerMessageType::from(buffer[0]) == ServerMessageType::AuthenticateToken {
+                        authenticated = true;
+                    }
+
+                    // Queued messages are sent once the server accepted us and we are back in our room
+                    if !ready && authenticated && self.rejoin.is_none() {
+                        ready = true;
+                        if !self.refollow(&mut write_stream).await
+                            || !self.resume_transfers(&mut write_stream).await
+                            || !self.flush_queue(&mut write_stream).await
+                        {
+                            break Disconnect::Lost;
+                        }
+                    }
+                }
+                _ = sleep_until(alive_until) => {
+                    tracing::warn!("Server stopped responding");
+                    break Disconnect::Lost;
+                }
+                _ = heartbeat.tick() => {
+                    let ping = client::Ping {
+                        token: rand::random::<u32>().to_string(),
+                    };
+                    if !write_to_stream(&mut write_stream, &ping).await.is_ok_and(|x| x) {
+                        break Disconnect::Lost;
+                    }
+                }
+                line = inbox.recv(), if ready => match line {
+                    Some(line) => {
+                        if let Some(disconnect) = self.accept(&line) {
+                            break disconnect;
                         }
-                        ServerMessageType::InvalidEvent => {
-                            log::warn!("Received unknown message from server");
+                        if !self.flush_queue(&mut write_stream).await {
+                            break Disconnect::Lost;
                         }
                     }
+                    None => break Disconnect::Quit,
+                },
+            }
+        };
+
+        reader.abort();
+        // Nothing is known about the room until the server told us again
+        if self.room.is_none() {
+            self.room = self.rejoin.take();
+        }
+        disconnect
+    }
+
+    async fn read_frames(
+        mut stream: OwnedReadHalf,
+        frames: mpsc::UnboundedSender<Frame>,
+        buffer_size: usize,
+    ) {
+        loop {
+            let frame = read_frame(&mut stream, buffer_size).await;
+            let is_last = !matches!(frame, Ok(Some(_)));
+            if frames.send(frame).is_err() || is_last {
+                break;
+            }
+        }
+    }
+
+    /// Handles a line the user typed. Commands which are meant for the server are queued,
+    /// returns how to disconnect if the line asks for that.
+    fn accept(&mut self, line: &str) -> Option<Disconnect> {
+        match Command::parse(line) {
+            Ok(Command::Quit) => return Some(Disconnect::Quit),
+            Ok(Command::Reconnect) => return Some(Disconnect::Reconnect),
+            Ok(Command::Set { setting, value }) => self.set(setting, value),
+            Ok(command) => self.enqueue(command),
+            Err(why) => self.emit(ServerEvent::System {
+                content: why.to_string(),
+            }),
+        }
+
+        None
+    }
+
+    fn set(&mut self, setting: Option<String>, value: Option<String>) {
+        let settings = match &setting {
+            Some(setting) => vec![setting.as_str()],
+            None => SETTINGS.to_vec(),
+        };
+
+        let result = match value {
+            Some(value) => self.config.set(settings[0], &value),
+            None => Ok(()),
+        };
+        let content = result.and_then(|_| {
+            let values = settings
+                .iter()
+                .map(|s| Ok(format!("{s} = {}", self.config.get(s)?)))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(values.join(", "))
+        });
+
+        self.emit(ServerEvent::System {
+            content: content.unwrap_or_else(|why| why.to_string()),
+        });
+    }
+
+    fn enqueue(&mut self, command: Command) {
+        if self.queue.len() >= MAX_QUEUED_MESSAGES {
+            tracing::warn!("Too many unsent messages, dropping the oldest one");
+            self.queue.pop_front();
+        }
+        self.queue.push_back(command);
+    }
 
-                    // Clear the buffer
-                    buffer = vec![0; config.buffer_size];
+    async fn refollow(&mut self, stream: &mut OwnedWriteHalf) -> bool {
+        for id in &self.following {
+            let message = FollowThread {
+                hwid: self.hwid.clone(),
+                id: id.clone(),
+            };
+            if !write_to_stream(stream, &message).await.is_ok_and(|x| x) {
+                return false;
+            }
+        }
+        true
+    }
+
+    async fn resume_transfers(&mut self, stream: &mut OwnedWriteHalf) -> bool {
+        let uploads = self.transfers.uploads().cloned().collect::<Vec<_>>();
+        for upload in uploads {
+            let message = StartUpload {
+                hwid: self.hwid.clone(),
+                name: upload.name,
+                size: upload.size.to_string(),
+                sha256: upload.sha256,
+            };
+            if !write_to_stream(stream, &message).await.is_ok_and(|x| x) {
+                return false;
+            }
+        }
+
+        let downloads = self.transfers.downloads().cloned().collect::<Vec<_>>();
+        for id in downloads {
+            let offset = self.transfers.offset(&id).await;
+            if !self.request_chunk(stream, id, offset).await {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Asks for the chunk of a download at `offset`, as large as our buffer allows.
+    async fn request_chunk(&self, stream: &mut OwnedWriteHalf, id: String, offset: u64) -> bool {
+        let message = DownloadChunk {
+            hwid: self.hwid.clone(),
+            id,
+            offset: offset.to_string(),
+            length: chunk_size(self.config.buffer_size).to_string(),
+        };
+        write_to_stream(stream, &message).await.is_ok_and(|x| x)
+    }
+
+    /// Sends all queued messages, the ones which could not be sent stay queued.
+    async fn flush_queue(&mut self, stream: &mut OwnedWriteHalf) -> bool {
+        while let Some(command) = self.queue.front() {
+            let hwid = self.hwid.clone();
+            let written = match command.clone() {
+                Command::Nick(new_username) => {
+                    write_to_stream(stream, &ChangeUsername { hwid, new_username }).await
+                }
+                Command::Chat(content) => {
+                    let reply_to = String::new();
+                    write_to_stream(
+                        stream,
+                        &ChatMessage {
+                            hwid,
+                            content,
+                            reply_to,
+                        },
+                    )
+                    .await
+                }
+                Command::Reply { id, content } => {
+                    let message = ChatMessage {
+                        hwid,
+                        content,
+                        reply_to: id,
+                    };
+                    write_to_stream(stream, &message).await
+                }
+                Command::Thread(id) => write_to_stream(stream, &FetchThread { hwid, id }).await,
+                Command::Follow(id) => {
+                    self.following.insert(id.clone());
+                    write_to_stream(stream, &FollowThread { hwid, id }).await
+                }
+                Command::Unfollow(id) => {
+                    self.following.remove(&id);
+                    write_to_stream(stream, &UnfollowThread { hwid, id }).await
+                }
+                Command::React { id, reaction } => {
+                    write_to_stream(stream, &AddReaction { hwid, id, reaction }).await
                 }
-                Err(why) => {
-                    println!("Error reading from server! {why}");
-                    process::exit(0);
+                Command::Unreact { id, reaction } => {
+                    write_to_stream(stream, &RemoveReaction { hwid, id, reaction }).await
                 }
+                Command::Edit { id, content } => {
+                    write_to_stream(stream, &EditMessage { hwid, id, content }).await
+                }
+                Command::Delete(id) => write_to_stream(stream, &DeleteMessage { hwid, id }).await,
+                Command::Upload(path) => match self.transfers.upload(&path).await {
+                    Ok(upload) => {
+                        self.emit(ServerEvent::System {
+                            content: format!("Uploading {} ({} bytes)", upload.name, upload.size),
+                        });
+                        let message = StartUpload {
+                            hwid,
+                            name: upload.name,
+                            size: upload.size.to_string(),
+                            sha256: upload.sha256,
+                        };
+                        write_to_stream(stream, &message).await
+                    }
+                    Err(why) => {
+                        self.emit(ServerEvent::System {
+                            content: format!("Unable to upload {}: {why}", path.display()),
+                        });
+                        Ok(true)
+                    }
+                },
+                Command::Download(_) if chunk_size(self.config.buffer_size) == 0 => {
+                    self.emit(ServerEvent::System {
+                        content: "The buffer_size is too small for downloads".to_string(),
+                    });
+                    Ok(true)
+                }
+                Command::Download(id) => match self.transfers.download(&id).await {
+                    Ok(offset) => Ok(self.request_chunk(stream, id, offset).await),
+                    Err(why) => {
+                        self.emit(ServerEvent::System {
+                            content: format!("Unable to download {id}: {why}"),
+                        });
+                        Ok(true)
+                    }
+                },
+                Command::MarkRead { room, id } => {
+                    let id = id.unwrap_or_default();
+                    write_to_stream(stream, &MarkRead { hwid, room, id }).await
+                }
+                Command::Ping => {
+                    let token = rand::random::<u32>().to_string();
+                    self.pings.insert(token.clone(), Instant::now());
+                    write_to_stream(stream, &client::Ping { token }).await
+                }
+                // Everything else is handled before queueing
+                _ => Ok(true),
+            };
+
+            if !written.is_ok_and(|x| x) {
+                return false;
             }
+            self.queue.pop_front();
         }
+
+        true
     }
 
-    async fn request_authentication(stream: &TcpStream, config: &Config, hwid: String) {
-        let message = RequestAuthentication {
-            hwid,
-            name: config.name.to_string(),
+    /// Returns `false` if the connection should be closed.
+    /// Frames which can't be decoded are logged and dropped.
+    async fn decode<T: Deserialize>(buffer: &[u8]) -> Option<T> {
+        match T::deserialize(buffer).await {
+            Ok(message) => Some(message),
+            Err(why) => {
+                tracing::warn!("Received an invalid message! {why}");
+                None
+            }
+        }
+    }
+
+    async fn handle_frame(&mut self, buffer: &[u8], stream: &mut OwnedWriteHalf) -> bool {
+        match ServerMessageType::from(buffer[0]) {
+            ServerMessageType::AuthenticateToken => {
+                let Some(message) = Self::decode::<AuthenticateToken>(buffer).await else {
+                    return true;
+                };
+
+                tracing::info!(session = message.token, "Authenticated");
+                self.session_token = Some(message.token);
+            }
+            ServerMessageType::BroadcastMessage => {
+                let Some(message) = Self::decode::<BroadcastMessage>(buffer).await else {
+                    return true;
+                };
+
+                self.emit(ServerEvent::Message {
+                    id: message.id,
+                    username: message.username,
+                    content: message.content,
+                    timestamp: message.timestamp.parse().unwrap_or_default(),
+                    reply_to: Some(message.reply_to).filter(|id| !id.is_empty()),
+                });
+            }
+            ServerMessageType::ThreadMessage => {
+                let Some(message) = Self::decode::<ThreadMessage>(buffer).await else {
+                    return true;
+                };
+
+                self.emit(ServerEvent::ThreadMessage {
+                    thread: message.thread,
+                    room: message.room,
+                    id: message.id,
+                    username: message.username,
+                    content: message.content,
+                    timestamp: message.timestamp.parse().unwrap_or_default(),
+                    reply_to: Some(message.reply_to).filter(|id| !id.is_empty()),
+                });
+            }
+            ServerMessageType::Mention => {
+                let Some(message) = Self::decode::<Mention>(buffer).await else {
+                    return true;
+                };
+
+                self.emit(ServerEvent::Mention {
+                    id: message.id,
+                    room: message.room,
+                    username: message.username,
+                    content: message.content,
+                    mention: message.mention,
+                    timestamp: message.timestamp.parse().unwrap_or_default(),
+                });
+            }
+            ServerMessageType::MessageSent => {
+                let Some(message) = Self::decode::<MessageSent>(buffer).await else {
+                    return true;
+                };
+
+                self.emit(ServerEvent::MessageSent {
+                    id: message.id,
+                    room: message.room,
+                    timestamp: message.timestamp.parse().unwrap_or_default(),
+                });
+            }
+            ServerMessageType::MessageEdited => {
+                let Some(message) = Self::decode::<MessageEdited>(buffer).await else {
+                    return true;
+                };
+
+                self.emit(ServerEvent::MessageEdited {
+                    id: message.id,
+                    room: message.room,
+                    content: message.content,
+                    edited_at: message.edited_at.parse().unwrap_or_default(),
+                });
+            }
+            ServerMessageType::ReactionsUpdated => {
+                let Some(message) = Self::decode::<ReactionsUpdated>(buffer).await else {
+                    return true;
+                };
+                let reactions = message
+                    .reactions
+                    .split_whitespace()
+                    .filter_map(|pair| pair.rsplit_once('='))
+                    .map(|(reaction, count)| (reaction.to_string(), count.parse().unwrap_or(0)))
+                    .collect();
+
+                self.emit(ServerEvent::ReactionsUpdated {
+                    id: message.id,
+                    room: message.room,
+                    reactions,
+                });
+            }
+            ServerMessageType::UserJoined => {
+                let Some(message) = Self::decode::<UserJoined>(buffer).await else {
+                    return true;
+                };
+
+                if message.username.eq(&self.name) {
+                    // The server put us into another room than we were in before the reconnect
+                    if let Some(room) = self.rejoin.take().filter(|r| !r.eq(&message.room)) {
+                        let message = ChatMessage {
+                            hwid: self.hwid.clone(),
+                            content: format!("/join {room}"),
+                            reply_to: String::new(),
+                        };
+                        if !write_to_stream(stream, &message).await.is_ok_and(|x| x) {
+                            return false;
+                        }
+                    }
+                    self.room = Some(message.room.clone());
+                    self.emit_identity();
+                }
+                self.emit(ServerEvent::UserJoined {
+                    username: message.username,
+                    room: message.room,
+                });
+            }
+            ServerMessageType::UserLeft => {
+                let Some(message) = Self::decode::<UserLeft>(buffer).await else {
+                    return true;
+                };
+
+                if message.username.eq(&self.name) && self.room.as_ref() == Some(&message.room) {
+                    self.room = None;
+                    self.emit_identity();
+                }
+                self.emit(ServerEvent::UserLeft {
+                    username: message.username,
+                    room: message.room,
+                });
+            }
+            ServerMessageType::SystemMessage => {
+                let Some(message) = Self::decode::<SystemMessage>(buffer).await else {
+                    return true;
+                };
+
+                self.emit(ServerEvent::System {
+                    content: message.content,
+                });
+            }
+            ServerMessageType::DirectMessage => {
+                let Some(message) = Self::decode::<DirectMessage>(buffer).await else {
+                    return true;
+                };
+
+                self.emit(ServerEvent::DirectMessage {
+                    username: message.username,
+                    content: message.content,
+                    timestamp: message.timestamp.parse().unwrap_or_default(),
+                });
+            }
+            ServerMessageType::Welcome => {
+                let Some(message) = Self::decode::<Welcome>(buffer).await else {
+                    return true;
+                };
+                let unread = message
+                    .unread
+                    .split_whitespace()
+                    .filter_map(|pair| pair.rsplit_once('='))
+                    .map(|(room, count)| (room.to_string(), count.parse().unwrap_or(0)))
+                    .collect();
+
+                self.emit(ServerEvent::Welcome {
+                    unread,
+                    pending: message.pending.parse().unwrap_or(0),
+                });
+            }
+            ServerMessageType::UsernameChanged => {
+                let Some(message) = Self::decode::<UsernameChanged>(buffer).await else {
+                    return true;
+                };
+
+                if message.old_username.eq(&self.name) {
+                    self.name = message.new_username.clone();
+                    self.emit_identity();
+                }
+                self.emit(ServerEvent::UsernameChanged {
+                    old_username: message.old_username,
+                    new_username: message.new_username,
+                });
+            }
+            ServerMessageType::MessageDeleted => {
+                let Some(message) = Self::decode::<MessageDeleted>(buffer).await else {
+                    return true;
+                };
+
+                self.emit(ServerEvent::MessageDeleted {
+                    id: message.id,
+                    room: message.room,
+                });
+            }
+            ServerMessageType::RoomMembers => {
+                let Some(message) = Self::decode::<RoomMembers>(buffer).await else {
+                    return true;
+                };
+
+                let (room, mut usernames) = match self.members.take() {
+                    Some((room, usernames)) if room.eq(&message.room) => (room, usernames),
+                    _ => (message.room, Vec::new()),
+                };
+                usernames.extend(message.usernames.split_whitespace().map(str::to_string));
+                // The list is complete once all members arrived
+                match message.total.parse::<usize>() {
+                    Ok(total) if usernames.len() < total => {
+                        self.members = Some((room, usernames));
+                    }
+                    _ => self.emit(ServerEvent::RoomMembers { room, usernames }),
+                }
+            }
+            ServerMessageType::Ping => {
+                let Some(ping) = Self::decode::<Ping>(buffer).await else {
+                    return true;
+                };
+                let pong = client::Pong { token: ping.token };
+
+                return write_to_stream(stream, &pong).await.is_ok_and(|x| x);
+            }
+            ServerMessageType::Pong => {
+                // Receiving anything is enough to know the server is alive, only the pings the
+                // user asked for are reported
+                let Some(pong) = Self::decode::<Pong>(buffer).await else {
+                    return true;
+                };
+                if let Some(sent) = self.pings.remove(&pong.token) {
+                    self.emit(ServerEvent::Pong {
+                        latency: sent.elapsed(),
+                    });
+                }
+            }
+            ServerMessageType::ServerShutdown => {
+                let Some(message) = Self::decode::<ServerShutdown>(buffer).await else {
+                    return true;
+                };
+                let reconnect_after =
+                    Duration::from_secs(message.reconnect_after.parse().unwrap_or_default());
+
+                tracing::warn!("The server is shutting down: {}", message.reason);
+                self.reconnect_after = Some(reconnect_after);
+                self.emit(ServerEvent::ServerShutdown {
+                    reason: message.reason,
+                    reconnect_after,
+                });
+                return false;
+            }
+            ServerMessageType::UploadOffset => {
+                let Some(message) = Self::decode::<UploadOffset>(buffer).await else {
+                    return true;
+                };
+                let offset = message.offset.parse().unwrap_or_default();
+                let length = message.chunk_size.parse().unwrap_or_default();
+
+                match self
+                    .transfers
+                    .read_chunk(&message.sha256, offset, length)
+                    .await
+                {
+                    Some(Ok(data)) => {
+                        let chunk = UploadChunk {
+                            hwid: self.hwid.clone(),
+                            id: message.id,
+                            offset: message.offset,
+                            data: hex::encode(data),
+                        };
+                        return write_to_stream(stream, &chunk).await.is_ok_and(|x| x);
+                    }
+                    Some(Err(why)) => {
+                        self.transfers.finish_upload(&message.sha256);
+                        self.emit(ServerEvent::System {
+                            content: format!("Upload failed: {why}"),
+                        });
+                    }
+                    None => tracing::warn!(id = message.id, "Asked for an unknown upload"),
+                }
+            }
+            ServerMessageType::FileShared => {
+                let Some(message) = Self::decode::<FileShared>(buffer).await else {
+                    return true;
+                };
+
+                if message.username.eq(&self.name) {
+                    self.transfers.finish_upload(&message.sha256);
+                }
+                self.emit(ServerEvent::FileShared {
+                    id: message.id,
+                    room: message.room,
+                    username: message.username,
+                    name: message.name,
+                    size: message.size.parse().unwrap_or_default(),
+                    sha256: message.sha256,
+                    timestamp: message.timestamp.parse().unwrap_or_default(),
+                });
+            }
+            ServerMessageType::FileChunk => {
+                let Some(message) = Self::decode::<FileChunk>(buffer).await else {
+                    return true;
+                };
+                let (Ok(offset), Ok(size), Ok(data)) = (
+                    message.offset.parse(),
+                    message.size.parse(),
+                    hex::decode(&message.data),
+                ) else {
+                    tracing::warn!(id = message.id, "Received a malformed file chunk");
+                    return true;
+                };
+
+                let transfers = &mut self.transfers;
+                let written = transfers
+                    .write_chunk(
+                        &message.id,
+                        &message.name,
+                        size,
+                        &message.sha256,
+                        offset,
+                        &data,
+                    )
+                    .await;
+                match written {
+                    Some(Ok(Received::Next(offset))) => {
+                        return self.request_chunk(stream, message.id, offset).await;
+                    }
+                    Some(Ok(Received::Done(path))) => self.emit(ServerEvent::FileDownloaded {
+                        id: message.id,
+                        name: message.name,
+                        path,
+                    }),
+                    Some(Err(why)) => self.emit(ServerEvent::System {
+                        content: format!("Download failed: {why}"),
+                    }),
+                    None => tracing::warn!(id = message.id, "Received a chunk of an unknown file"),
+                }
+            }
+            ServerMessageType::InvalidEvent => {
+                tracing::warn!("Received unknown message from server");
+            }
+        }
+
+        true
+    }
+
+    async fn request_authentication(&self, stream: &mut OwnedWriteHalf) -> bool {
+        let written = match &self.session_token {
+            Some(token) => {
+                let message = ResumeSession {
+                    hwid: self.hwid.clone(),
+                    name: self.name.clone(),
+                    token: token.clone(),
+                };
+                write_to_stream(stream, &message).await
+            }
+            None => {
+                let message = RequestAuthentication {
+                    hwid: self.hwid.clone(),
+                    name: self.name.clone(),
+                };
+                write_to_stream(stream, &message).await
+            }
         };
 
-        if !write_to_stream(stream, &message)
-            .await
-            .is_ok_and(|x| x == true)
-        {
-            log::error!("Error authenticating");
-            process::exit(0);
+        written.is_ok_and(|x| x)
+    }
+}
+
+/// Exponential backoff with jitter, so clients don't reconnect all at once after an outage.
+pub fn backoff(attempt: u32, base: Duration, max: Duration) -> Duration {
+    let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+    let delay = base.saturating_mul(factor).min(max);
+
+    // Somewhere between half and the full delay
+    let jitter = rand::thread_rng().gen_range(0.5..=1.0);
+    delay.mul_f64(jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::backoff;
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_secs(1);
+        let max = Duration::from_secs(30);
+
+        for attempt in 1..10 {
+            let expected = Duration::from_secs(2u64.pow(attempt - 1)).min(max);
+            let delay = backoff(attempt, base, max);
+            assert!(delay >= expected / 2 && delay <= expected);
         }
     }
 }
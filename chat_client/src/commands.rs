@@ -0,0 +1,345 @@
+use crate::types::Config;
+use std::{path::PathBuf, time::Duration};
+
+/// Commands the client knows about, other commands are left to the server.
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("nick", "/nick <username>"),
+    ("quit", "/quit"),
+    ("msg", "/msg <username> <message>"),
+    ("join", "/join <room>"),
+    ("me", "/me <action>"),
+    ("edit", "/edit <message id> <message>"),
+    ("delete", "/delete <message id>"),
+    ("reply", "/reply <message id> <message>"),
+    ("thread", "/thread <message id>"),
+    ("follow", "/follow <message id>"),
+    ("unfollow", "/unfollow <message id>"),
+    ("react", "/react <message id> <emoji or :shortcode:>"),
+    ("unreact", "/unreact <message id> <emoji or :shortcode:>"),
+    ("upload", "/upload <path>"),
+    ("download", "/download <file id>"),
+    ("read", "/read <room> [message id]"),
+    ("reconnect", "/reconnect"),
+    ("ping", "/ping"),
+    ("set", "/set [setting] [value]"),
+    ("who", "/who [room]"),
+    ("help", "/help [command]"),
+];
+
+/// Settings which can be changed with `/set` while the client is running, the heartbeat
+/// settings take effect on the next connection.
+pub const SETTINGS: &[&str] = &[
+    "timeout",
+    "heartbeat_interval",
+    "heartbeat_timeout",
+    "reconnect_delay",
+    "max_reconnect_delay",
+    "max_reconnect_attempts",
+];
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("Unknown setting '{0}', available are: {settings}", settings = SETTINGS.join(", "))]
+    UnknownSetting(String),
+    #[error("Invalid value '{value}' for {setting}")]
+    InvalidValue { setting: String, value: String },
+}
+
+/// A line the user typed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Anything which is not handled by the client itself is sent as a chat message,
+    /// including commands for the server
+    Chat(String),
+    Nick(String),
+    /// Only the author and moderators may edit or delete a message
+    Edit {
+        id: String,
+        content: String,
+    },
+    Delete(String),
+    /// Sent to the room of the message which is replied to
+    Reply {
+        id: String,
+        content: String,
+    },
+    /// Fetches the thread the message belongs to
+    Thread(String),
+    /// Followed threads are followed again after reconnecting
+    Follow(String),
+    Unfollow(String),
+    React {
+        id: String,
+        reaction: String,
+    },
+    Unreact {
+        id: String,
+        reaction: String,
+    },
+    /// Shares the file in the current room, the upload continues after reconnecting
+    Upload(PathBuf),
+    /// Saves a shared file to the downloads directory
+    Download(String),
+    /// Marks the room as read up to the message, or up to its latest message without one
+    MarkRead {
+        room: String,
+        id: Option<String>,
+    },
+    Quit,
+    Reconnect,
+    /// Measures the round trip to the server, once the answer arrives everything sent before
+    /// was handled by the server
+    Ping,
+    /// Shows the settings without a setting, or a single one without a value
+    Set {
+        setting: Option<String>,
+        value: Option<String>,
+    },
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, CommandError> {
+        let line = line.trim();
+
+        // A double slash escapes the command, just like on the server
+        let Some(input) = line.strip_prefix('/').filter(|_| !line.starts_with("//")) else {
+            return Ok(Command::Chat(line.to_string()));
+        };
+
+        let (name, arguments) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let arguments = arguments.trim();
+        let mut words = arguments.split_whitespace();
+        let usage = |name: &str| {
+            let (_, usage) = COMMANDS.iter().find(|(n, _)| n.eq(&name)).unwrap();
+            CommandError::Usage(usage)
+        };
+
+        let name = name.to_lowercase();
+        let command = match name.as_str() {
+            "nick" => match (words.next(), words.next()) {
+                (Some(name), None) => Command::Nick(name.to_string()),
+                _ => return Err(usage("nick")),
+            },
+            "edit" => match arguments.split_once(char::is_whitespace) {
+                Some((id, content)) if !content.trim().is_empty() => Command::Edit {
+                    id: id.to_string(),
+                    content: content.trim().to_string(),
+                },
+                _ => return Err(usage("edit")),
+            },
+            "upload" if !arguments.is_empty() => Command::Upload(PathBuf::from(arguments)),
+            "upload" => return Err(usage("upload")),
+            "delete" | "thread" | "follow" | "unfollow" | "download" => {
+                let (Some(id), None) = (words.next(), words.next()) else {
+                    return Err(usage(&name));
+                };
+                let id = id.to_string();
+                match name.as_str() {
+                    "delete" => Command::Delete(id),
+                    "thread" => Command::Thread(id),
+                    "follow" => Command::Follow(id),
+                    "download" => Command::Download(id),
+                    _ => Command::Unfollow(id),
+                }
+            }
+            "react" | "unreact" => {
+                let (Some(id), Some(reaction), None) = (words.next(), words.next(), words.next())
+                else {
+                    return Err(usage(&name));
+                };
+                let (id, reaction) = (id.to_string(), reaction.to_string());
+                match name.as_str() {
+                    "react" => Command::React { id, reaction },
+                    _ => Command::Unreact { id, reaction },
+                }
+            }
+            "reply" => match arguments.split_once(char::is_whitespace) {
+                Some((id, content)) if !content.trim().is_empty() => Command::Reply {
+                    id: id.to_string(),
+                    content: content.trim().to_string(),
+                },
+                _ => return Err(usage("reply")),
+            },
+            "read" => match (words.next(), words.next(), words.next()) {
+                (Some(room), id, None) => Command::MarkRead {
+                    room: room.trim_start_matches('#').to_string(),
+                    id: id.map(str::to_string),
+                },
+                _ => return Err(usage("read")),
+            },
+            "quit" | "exit" => Command::Quit,
+            "reconnect" => Command::Reconnect,
+            "ping" => Command::Ping,
+            "set" => Command::Set {
+                setting: words.next().map(str::to_string),
+                value: words.next().map(str::to_string),
+            },
+            // The server does the work, but obviously wrong input is caught early
+            "msg" if words.clone().count() < 2 => return Err(usage("msg")),
+            "join" if words.clone().count() != 1 => return Err(usage("join")),
+            "me" if arguments.is_empty() => return Err(usage("me")),
+            _ => Command::Chat(line.to_string()),
+        };
+
+        Ok(command)
+    }
+}
+
+impl Config {
+    /// Changes a setting, durations are given in seconds.
+    pub fn set(&mut self, setting: &str, value: &str) -> Result<(), CommandError> {
+        let invalid = || CommandError::InvalidValue {
+            setting: setting.to_string(),
+            value: value.to_string(),
+        };
+        let duration = || {
+            value
+                .parse::<f64>()
+                .ok()
+                .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                .filter(|d| !d.is_zero())
+                .ok_or_else(invalid)
+        };
+
+        match setting {
+            "timeout" => self.timeout = duration()?,
+            "heartbeat_interval" => self.heartbeat_interval = duration()?,
+            "heartbeat_timeout" => self.heartbeat_timeout = duration()?,
+            "reconnect_delay" => self.reconnect_delay = duration()?,
+            "max_reconnect_delay" => self.max_reconnect_delay = duration()?,
+            "max_reconnect_attempts" => {
+                self.max_reconnect_attempts = value.parse().map_err(|_| invalid())?
+            }
+            _ => return Err(CommandError::UnknownSetting(setting.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// The current value of a setting, as it would be given to `set`.
+    pub fn get(&self, setting: &str) -> Result<String, CommandError> {
+        let duration = match setting {
+            "timeout" => self.timeout,
+            "heartbeat_interval" => self.heartbeat_interval,
+            "heartbeat_timeout" => self.heartbeat_timeout,
+            "reconnect_delay" => self.reconnect_delay,
+            "max_reconnect_delay" => self.max_reconnect_delay,
+            "max_reconnect_attempts" => return Ok(self.max_reconnect_attempts.to_string()),
+            _ => return Err(CommandError::UnknownSetting(setting.to_string())),
+        };
+
+        Ok(format!("{}", duration.as_secs_f64()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, CommandError};
+    use crate::types::Config;
+    use std::{path::PathBuf, time::Duration};
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(
+            Command::parse("/nick Bob"),
+            Ok(Command::Nick("Bob".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/nick"),
+            Err(CommandError::Usage("/nick <username>"))
+        );
+        assert_eq!(Command::parse("/QUIT"), Ok(Command::Quit));
+        assert_eq!(
+            Command::parse("/edit 4 fixed  typo"),
+            Ok(Command::Edit {
+                id: "4".to_string(),
+                content: "fixed  typo".to_string(),
+            })
+        );
+        assert!(Command::parse("/edit 4").is_err());
+        assert_eq!(
+            Command::parse("/delete 4"),
+            Ok(Command::Delete("4".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/reply 4 me too"),
+            Ok(Command::Reply {
+                id: "4".to_string(),
+                content: "me too".to_string(),
+            })
+        );
+        assert_eq!(
+            Command::parse("/follow 4"),
+            Ok(Command::Follow("4".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/react 4 :tada:"),
+            Ok(Command::React {
+                id: "4".to_string(),
+                reaction: ":tada:".to_string(),
+            })
+        );
+        assert_eq!(
+            Command::parse("/unreact 4"),
+            Err(CommandError::Usage(
+                "/unreact <message id> <emoji or :shortcode:>"
+            ))
+        );
+        assert_eq!(
+            Command::parse("/upload ./my notes.txt"),
+            Ok(Command::Upload(PathBuf::from("./my notes.txt")))
+        );
+        assert_eq!(
+            Command::parse("/download"),
+            Err(CommandError::Usage("/download <file id>"))
+        );
+        assert_eq!(
+            Command::parse("/read #rust 7"),
+            Ok(Command::MarkRead {
+                room: "rust".to_string(),
+                id: Some("7".to_string())
+            })
+        );
+        assert_eq!(
+            Command::parse("/read"),
+            Err(CommandError::Usage("/read <room> [message id]"))
+        );
+        assert_eq!(
+            Command::parse("/thread"),
+            Err(CommandError::Usage("/thread <message id>"))
+        );
+        assert_eq!(
+            Command::parse("/msg Bob hi there"),
+            Ok(Command::Chat("/msg Bob hi there".to_string()))
+        );
+        assert!(Command::parse("/msg Bob").is_err());
+        assert_eq!(
+            Command::parse("//nick is a command"),
+            Ok(Command::Chat("//nick is a command".to_string()))
+        );
+        assert_eq!(
+            Command::parse("/set timeout 5"),
+            Ok(Command::Set {
+                setting: Some("timeout".to_string()),
+                value: Some("5".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_set_config() {
+        let mut config = Config::default();
+        config.set("heartbeat_interval", "2.5").unwrap();
+        assert_eq!(config.heartbeat_interval, Duration::from_millis(2500));
+        assert_eq!(config.get("heartbeat_interval").unwrap(), "2.5");
+
+        assert!(config.set("timeout", "-1").is_err());
+        assert!(config.set("max_reconnect_attempts", "lots").is_err());
+        assert_eq!(
+            config.set("endpoint", "1"),
+            Err(CommandError::UnknownSetting("endpoint".to_string()))
+        );
+    }
+}
@@ -0,0 +1,28 @@
+//! The chat client as a library, see [`ChatClient`] to embed it in bots, tests or other
+//! interfaces.
+
+mod api;
+pub mod client;
+pub mod commands;
+mod transfer;
+pub mod types;
+pub mod utils;
+
+pub use api::{ChatClient, ServerEvents};
+pub use types::{Config, ConnectionState, ServerEvent};
+
+static KEY: &str = "THERESHOULDB3S0MESECRETKEYINHEREBUTRIGHTNOWTHEREISN'T";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error("Unable to construct HWID, {0}")]
+    Hwid(String),
+    #[error("Unable to connect to {0}")]
+    Connect(std::net::SocketAddr),
+    #[error("'{0}' is not a valid name")]
+    InvalidName(String),
+    #[error("The client has stopped")]
+    Stopped,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
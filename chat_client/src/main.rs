@@ -1,34 +1,79 @@
-use crate::{client::Client, config::config::ConfigManager};
+use chat_client::{client::Client, utils::construct_hwid, Config};
+use chat_shared::{config, logging};
 
+use clap::Parser;
+use cli::Cli;
 use std::{
-    io::{self},
-    net::TcpStream,
+    io::{self, IsTerminal},
+    path::PathBuf,
+    process,
 };
-use utils::construct_hwid;
+use tokio::sync::mpsc;
+use ui::headless;
 
-pub mod client;
-mod config;
-pub mod types;
-pub mod utils;
-
-static KEY: &str = "THERESHOULDB3S0MESECRETKEYINHEREBUTRIGHTNOWTHEREISN'T";
+mod cli;
+mod ui;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
-    let hwid = construct_hwid();
+    // Usage errors exit with 2, like headless::EXIT_USAGE
+    let cli = Cli::parse();
+    let options = cli.headless();
 
-    let config = ConfigManager::initialize_or_create().await.unwrap();
+    // The logger needs the config, so errors are printed directly
+    let path = cli.config_path();
+    if cli.write_config {
+        if let Err(why) = config::write_default::<Config>(&path).await {
+            eprintln!("{why}");
+            process::exit(headless::EXIT_USAGE);
+        }
+        println!("Wrote the default config to {}", path.display());
+        return Ok(());
+    }
 
-    match TcpStream::connect_timeout(&config.endpoint, config.timeout) {
-        Ok(stream) => {
-            log::info!("Connected to server");
-            Client::start(&stream, &config, &hwid).await?;
+    let mut config = match config::load::<Config>(&path).await {
+        Ok(config) => config,
+        Err(why) => {
+            eprintln!("{why}");
+            process::exit(headless::EXIT_USAGE);
         }
+    };
+    cli.apply(&mut config);
+    let tui =
+        options.is_none() && config.tui && io::stdin().is_terminal() && io::stdout().is_terminal();
+
+    // Logs would mess up the full-screen interface, so they go to a file instead
+    if tui && config.log.file.is_none() {
+        config.log.file = Some(PathBuf::from("chat_client.log"));
+    }
+    if let Err(why) = logging::init(&config.log, cli.log_filters().as_deref()) {
+        eprintln!("{why}");
+        process::exit(headless::EXIT_USAGE);
+    }
+
+    let hwid = match construct_hwid() {
+        Ok(hwid) => hwid,
         Err(why) => {
-            log::error!("Can't connect to endpoint \n {why}");
+            tracing::error!("{why}");
+            process::exit(0);
         }
+    };
+
+    if let Some(options) = options {
+        process::exit(headless::run(options, config, hwid).await);
+    }
+
+    let (input, inbox) = mpsc::unbounded_channel();
+    let (events, event_inbox) = mpsc::unbounded_channel();
+    let (name, notify) = (config.name.clone(), config.notify);
+    let client = tokio::spawn(Client::new(config, hwid, events).run(inbox));
+
+    if tui {
+        // Quitting drops the input channel, which stops the client
+        ui::tui::run(name, notify, input, event_inbox).await?;
+    } else {
+        ui::plain::run(notify, input, event_inbox).await;
     }
 
-    Ok(())
+    client.await?
 }
@@ -0,0 +1,258 @@
+use chat_shared::utils::MAX_FILE_NAME_LENGTH;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum TransferError {
+    #[error("{0} is not a file")]
+    NotAFile(PathBuf),
+    #[error("The name of {0} is not valid UTF-8 or longer than {MAX_FILE_NAME_LENGTH} bytes")]
+    InvalidName(PathBuf),
+    #[error("{0} does not match its SHA-256 hash and was discarded")]
+    HashMismatch(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A file which is uploaded, the server knows it by its hash until it assigned an id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upload {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// What became of a received chunk.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Received {
+    /// The offset of the next chunk to ask for
+    Next(u64),
+    /// Where the complete file was saved
+    Done(PathBuf),
+}
+
+/// The uploads and downloads which are in progress, they continue where they stopped after
+/// reconnecting. Downloads are written to `<id>.part` until they are complete.
+#[derive(Debug, Default)]
+pub struct Transfers {
+    directory: PathBuf,
+    /// By the hash of the file
+    uploads: HashMap<String, Upload>,
+    /// The ids of the files which are downloaded
+    downloads: BTreeSet<String>,
+}
+
+impl Transfers {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            ..Default::default()
+        }
+    }
+
+    pub fn uploads(&self) -> impl Iterator<Item = &Upload> {
+        self.uploads.values()
+    }
+
+    pub fn downloads(&self) -> impl Iterator<Item = &String> {
+        self.downloads.iter()
+    }
+
+    /// Hashes the file, the upload is announced to the server with the result.
+    pub async fn upload(&mut self, path: &Path) -> Result<Upload, TransferError> {
+        let metadata = fs::metadata(path).await?;
+        if !metadata.is_file() {
+            return Err(TransferError::NotAFile(path.to_path_buf()));
+        }
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| name.len() <= MAX_FILE_NAME_LENGTH)
+            .ok_or_else(|| TransferError::InvalidName(path.to_path_buf()))?;
+
+        let upload = Upload {
+            path: path.to_path_buf(),
+            name: name.to_string(),
+            size: metadata.len(),
+            sha256: hash_file(path).await?,
+        };
+        self.uploads.insert(upload.sha256.clone(), upload.clone());
+        Ok(upload)
+    }
+
+    /// Reads the chunk of an upload the server asked for, `None` if the upload is unknown.
+    pub async fn read_chunk(
+        &self,
+        sha256: &str,
+        offset: u64,
+        length: usize,
+    ) -> Option<Result<Vec<u8>, TransferError>> {
+        let upload = self.uploads.get(sha256)?;
+        let read = async {
+            let length = upload.size.saturating_sub(offset).min(length as u64);
+            let mut input = fs::File::open(&upload.path).await?;
+            input.seek(SeekFrom::Start(offset)).await?;
+            let mut data = vec![0; length as usize];
+            input.read_exact(&mut data).await?;
+            Ok(data)
+        };
+        Some(read.await)
+    }
+
+    /// Forgets an upload once the server shared the file, or it failed.
+    pub fn finish_upload(&mut self, sha256: &str) -> Option<Upload> {
+        self.uploads.remove(sha256)
+    }
+
+    /// Starts or continues a download, returns the offset to ask for first.
+    pub async fn download(&mut self, id: &str) -> io::Result<u64> {
+        fs::create_dir_all(&self.directory).await?;
+        self.downloads.insert(id.to_string());
+        Ok(self.offset(id).await)
+    }
+
+    fn part(&self, id: &str) -> PathBuf {
+        self.directory.join(format!("{id}.part"))
+    }
+
+    /// How much of the file was downloaded so far.
+    pub async fn offset(&self, id: &str) -> u64 {
+        fs::metadata(self.part(id)).await.map_or(0, |m| m.len())
+    }
+
+    /// Appends a chunk to its download, `None` if the file is not downloaded. Once complete, the
+    /// file is checked against its hash and saved under its name.
+    pub async fn write_chunk(
+        &mut self,
+        id: &str,
+        name: &str,
+        size: u64,
+        sha256: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Option<Result<Received, TransferError>> {
+        if !self.downloads.contains(id) {
+            return None;
+        }
+
+        let result = self.append(id, name, size, sha256, offset, data).await;
+        if !matches!(result, Ok(Received::Next(_))) {
+            self.downloads.remove(id);
+        }
+        Some(result)
+    }
+
+    async fn append(
+        &self,
+        id: &str,
+        name: &str,
+        size: u64,
+        sha256: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<Received, TransferError> {
+        let part = self.part(id);
+        let stored = self.offset(id).await;
+        // Answers to requests of an earlier connection
+        if offset != stored {
+            return Ok(Received::Next(stored));
+        }
+
+        let mut output = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&part)
+            .await?;
+        output.write_all(data).await?;
+        output.flush().await?;
+        let stored = stored + data.len() as u64;
+        if stored < size && !data.is_empty() {
+            return Ok(Received::Next(stored));
+        }
+
+        if stored != size || !hash_file(&part).await?.eq_ignore_ascii_case(sha256) {
+            fs::remove_file(&part).await?;
+            return Err(TransferError::HashMismatch(name.to_string()));
+        }
+
+        // Names come from other users, so only the last component is used
+        let name = Path::new(name)
+            .file_name()
+            .map_or_else(|| id.to_string(), |name| name.to_string_lossy().to_string());
+        let mut path = self.directory.join(&name);
+        if fs::try_exists(&path).await? {
+            path = self
+                .directory
+                .join(format!("{}-{name}", &id[..id.len().min(8)]));
+        }
+        fs::rename(&part, &path).await?;
+        Ok(Received::Done(path))
+    }
+}
+
+/// The SHA-256 of a file as lowercase hex.
+async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut input = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = input.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Received, TransferError, Transfers};
+
+    #[tokio::test]
+    async fn test_transfers() {
+        let temp = tempfile::tempdir().unwrap();
+        let directory = temp.path();
+        let mut transfers = Transfers::new(directory.to_path_buf());
+
+        let source = directory.join("source.txt");
+        std::fs::write(&source, b"hello").unwrap();
+        let upload = transfers.upload(&source).await.unwrap();
+        assert_eq!((upload.name.as_str(), upload.size), ("source.txt", 5));
+        let chunk = transfers.read_chunk(&upload.sha256, 3, 10).await;
+        assert_eq!(chunk.unwrap().unwrap(), b"lo");
+
+        let sha256 = upload.sha256.clone();
+        let chunk = transfers.write_chunk("f00d", "a.txt", 5, &sha256, 0, b"hel");
+        assert!(chunk.await.is_none());
+        assert_eq!(transfers.download("f00d").await.unwrap(), 0);
+        let chunk = transfers.write_chunk("f00d", "../a.txt", 5, &sha256, 0, b"hel");
+        assert_eq!(chunk.await.unwrap().unwrap(), Received::Next(3));
+        // Chunks asked for before reconnecting are asked for again from where the download is
+        let chunk = transfers.write_chunk("f00d", "../a.txt", 5, &sha256, 0, b"hel");
+        assert_eq!(chunk.await.unwrap().unwrap(), Received::Next(3));
+        assert_eq!(transfers.download("f00d").await.unwrap(), 3);
+        let chunk = transfers.write_chunk("f00d", "../a.txt", 5, &sha256, 3, b"lo");
+        let path = directory.join("a.txt");
+        assert_eq!(chunk.await.unwrap().unwrap(), Received::Done(path.clone()));
+        assert_eq!(std::fs::read(path).unwrap(), b"hello");
+
+        transfers.download("beef").await.unwrap();
+        let result = transfers.write_chunk("beef", "b.txt", 5, &sha256, 0, b"jello");
+        assert!(matches!(
+            result.await,
+            Some(Err(TransferError::HashMismatch(_)))
+        ));
+        assert_eq!(transfers.downloads().count(), 0);
+    }
+}
@@ -1,11 +1,33 @@
-use std::{net::SocketAddr, time::Duration};
+use chat_shared::{
+    config::{check_buffer_size, check_endpoint, check_nonzero, ConfigFile, InvalidValue},
+    logging::LogConfig,
+};
+use std::{fmt::Display, net::SocketAddr, path::PathBuf, time::Duration};
 
 #[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
     pub endpoint: SocketAddr,
     pub buffer_size: usize,
     pub name: String,
     pub timeout: Duration,
+    /// How often the server is pinged
+    pub heartbeat_interval: Duration,
+    /// How long the server may stay silent before it is considered dead
+    pub heartbeat_timeout: Duration,
+    /// The delay before the first reconnect, doubled with every failed attempt
+    pub reconnect_delay: Duration,
+    pub max_reconnect_delay: Duration,
+    /// Attempts to reconnect before giving up, 0 retries forever
+    pub max_reconnect_attempts: u32,
+    /// Whether the full-screen interface is used when running in a terminal
+    pub tui: bool,
+    /// How to get our attention when someone mentions us
+    pub notify: Notify,
+    /// Where downloaded files are saved, unfinished downloads end in `.part`
+    pub downloads: PathBuf,
+    /// While the full-screen interface is open, logs go to `chat_client.log` unless a file is given
+    pub log: LogConfig,
 }
 
 impl Default for Config {
@@ -15,6 +37,324 @@ impl Default for Config {
             buffer_size: 2048,
             name: format!("User{}", rand::prelude::random::<i16>()),
             timeout: Duration::from_secs(10),
+            heartbeat_interval: Duration::from_secs(30),
+            heartbeat_timeout: Duration::from_secs(90),
+            reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(60),
+            max_reconnect_attempts: 0,
+            tui: true,
+            notify: Notify::Bell,
+            downloads: PathBuf::from("downloads"),
+            log: LogConfig::default(),
+        }
+    }
+}
+
+impl ConfigFile for Config {
+    fn validate(&self) -> Result<(), InvalidValue> {
+        check_endpoint("endpoint", self.endpoint)?;
+        if self.endpoint.ip().is_unspecified() {
+            return Err(InvalidValue::new(
+                "endpoint",
+                "must be the address of the server",
+            ));
+        }
+        check_buffer_size("buffer_size", self.buffer_size)?;
+        self.log.validate("log")?;
+        if self.name.is_empty() || self.name.contains(char::is_whitespace) {
+            return Err(InvalidValue::new("name", "must be a single word"));
+        }
+
+        check_nonzero("timeout", self.timeout)?;
+        check_nonzero("heartbeat_interval", self.heartbeat_interval)?;
+        if self.heartbeat_timeout <= self.heartbeat_interval {
+            return Err(InvalidValue::new(
+                "heartbeat_timeout",
+                "must be longer than heartbeat_interval",
+            ));
+        }
+        check_nonzero("reconnect_delay", self.reconnect_delay)?;
+        if self.max_reconnect_delay < self.reconnect_delay {
+            return Err(InvalidValue::new(
+                "max_reconnect_delay",
+                "must not be shorter than reconnect_delay",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// How mentions are announced, only when writing to a terminal.
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Notify {
+    Off,
+    /// Rings the terminal bell
+    Bell,
+    /// Rings the bell and asks the terminal for a desktop notification, which not all support
+    Desktop,
+}
+
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting {
+        attempt: u32,
+        #[serde(serialize_with = "as_secs")]
+        delay: Duration,
+    },
+    /// The client gave up or the user quit
+    Disconnected,
+}
+
+impl Display for ConnectionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionState::Connecting => write!(f, "Connecting..."),
+            ConnectionState::Connected => write!(f, "Connected"),
+            ConnectionState::Reconnecting { attempt, delay } => write!(
+                f,
+                "Reconnecting in {:.1}s (attempt {attempt})",
+                delay.as_secs_f64()
+            ),
+            ConnectionState::Disconnected => write!(f, "Disconnected"),
+        }
+    }
+}
+
+/// Everything which happens on the server, and what becomes of the connection to it.
+/// Serialized as JSON objects with the kind of event in `event` and durations in seconds.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ServerEvent {
+    Status(ConnectionState),
+    /// Our own name or room changed
+    Identity {
+        name: String,
+        room: Option<String>,
+    },
+    Message {
+        id: String,
+        username: String,
+        content: String,
+        /// Unix timestamp
+        timestamp: u64,
+        /// The id of the message this one replies to
+        reply_to: Option<String>,
+    },
+    /// A message of a thread we asked for, or a reply to a followed thread in another room
+    ThreadMessage {
+        /// The id of the first message of the thread
+        thread: String,
+        room: String,
+        id: String,
+        username: String,
+        content: String,
+        timestamp: u64,
+        reply_to: Option<String>,
+    },
+    /// Someone mentioned us, by name or with `@here`, also when we are in another room
+    Mention {
+        id: String,
+        room: String,
+        username: String,
+        content: String,
+        /// How we were mentioned, e.g. `@here`
+        mention: String,
+        timestamp: u64,
+    },
+    /// The id the server assigned to a message we sent
+    MessageSent {
+        id: String,
+        room: String,
+        timestamp: u64,
+    },
+    MessageEdited {
+        id: String,
+        room: String,
+        content: String,
+        edited_at: u64,
+    },
+    /// Every reaction to a message with how often it was given, empty once all were removed
+    ReactionsUpdated {
+        id: String,
+        room: String,
+        reactions: Vec<(String, u64)>,
+    },
+    DirectMessage {
+        username: String,
+        content: String,
+        /// Unix timestamp, older for messages which arrived while offline
+        timestamp: u64,
+    },
+    /// Sent after logging in, followed by the direct messages and mentions which were missed
+    Welcome {
+        /// Unread messages per room, only for rooms which were marked read before
+        unread: Vec<(String, u64)>,
+        pending: u64,
+    },
+    /// A file was uploaded to a room, `/download` it by its id
+    FileShared {
+        id: String,
+        room: String,
+        username: String,
+        name: String,
+        size: u64,
+        sha256: String,
+        timestamp: u64,
+    },
+    /// A download is complete and matched its hash
+    FileDownloaded {
+        id: String,
+        name: String,
+        path: PathBuf,
+    },
+    System {
+        content: String,
+    },
+    UserJoined {
+        username: String,
+        room: String,
+    },
+    UserLeft {
+        username: String,
+        room: String,
+    },
+    UsernameChanged {
+        old_username: String,
+        new_username: String,
+    },
+    MessageDeleted {
+        id: String,
+        room: String,
+    },
+    RoomMembers {
+        room: String,
+        usernames: Vec<String>,
+    },
+    /// The answer to `/ping`
+    Pong {
+        #[serde(serialize_with = "as_secs")]
+        latency: Duration,
+    },
+    /// The server stops, the client reconnects after the given time
+    ServerShutdown {
+        reason: String,
+        #[serde(serialize_with = "as_secs")]
+        reconnect_after: Duration,
+    },
+}
+
+fn as_secs<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl Display for ServerEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServerEvent::Status(state) => write!(f, "{state}"),
+            ServerEvent::Identity { name, room } => match room {
+                Some(room) => write!(f, "You are {name} in #{room}"),
+                None => write!(f, "You are {name}"),
+            },
+            ServerEvent::Message {
+                id,
+                username,
+                content,
+                reply_to: Some(reply_to),
+                ..
+            } => write!(f, "[{id}] {username} (reply to {reply_to}) --> {content}"),
+            ServerEvent::Message {
+                id,
+                username,
+                content,
+                ..
+            } => write!(f, "[{id}] {username} --> {content}"),
+            ServerEvent::ThreadMessage {
+                thread,
+                room,
+                id,
+                username,
+                content,
+                ..
+            } => write!(
+                f,
+                "[{id}] #{room} thread {thread}: {username} --> {content}"
+            ),
+            ServerEvent::Mention {
+                id,
+                room,
+                username,
+                content,
+                ..
+            } => write!(
+                f,
+                "[{id}] {username} mentioned you in #{room} --> {content}"
+            ),
+            ServerEvent::MessageSent { id, room, .. } => write!(f, "[{id}] Sent to #{room}"),
+            ServerEvent::MessageEdited { id, content, .. } => {
+                write!(f, "[{id}] (edited) --> {content}")
+            }
+            ServerEvent::ReactionsUpdated { id, reactions, .. } => {
+                let reactions = reactions
+                    .iter()
+                    .map(|(reaction, count)| format!("{reaction} {count}"))
+                    .collect::<Vec<_>>();
+                write!(f, "[{id}] (reactions) --> {}", reactions.join(", "))
+            }
+            ServerEvent::DirectMessage {
+                username, content, ..
+            } => write!(f, "{username} (private) --> {content}"),
+            ServerEvent::Welcome { unread, pending } => {
+                let unread = unread
+                    .iter()
+                    .map(|(room, count)| format!("{count} in #{room}"))
+                    .collect::<Vec<_>>();
+                match unread.is_empty() {
+                    true => write!(f, "No unread messages")?,
+                    false => write!(f, "Unread: {}", unread.join(", "))?,
+                }
+                write!(f, ", {pending} missed while away")
+            }
+            ServerEvent::FileShared {
+                id,
+                room,
+                username,
+                name,
+                size,
+                ..
+            } => write!(
+                f,
+                "{username} shared {name} ({size} bytes) in #{room}, /download {id}"
+            ),
+            ServerEvent::FileDownloaded { name, path, .. } => {
+                write!(f, "Downloaded {name} to {}", path.display())
+            }
+            ServerEvent::System { content } => write!(f, "{content}"),
+            ServerEvent::UserJoined { username, room } => write!(f, "{username} joined #{room}"),
+            ServerEvent::UserLeft { username, room } => write!(f, "{username} left #{room}"),
+            ServerEvent::UsernameChanged {
+                old_username,
+                new_username,
+            } => write!(f, "{old_username} is now known as {new_username}"),
+            ServerEvent::MessageDeleted { id, room } => {
+                write!(f, "Message {id} in #{room} was deleted")
+            }
+            ServerEvent::RoomMembers { room, usernames } => {
+                write!(f, "In #{room}: {}", usernames.join(", "))
+            }
+            ServerEvent::Pong { latency } => write!(f, "Pong after {}ms", latency.as_millis()),
+            ServerEvent::ServerShutdown {
+                reason,
+                reconnect_after,
+            } => write!(
+                f,
+                "{reason}, reconnecting in {}s",
+                reconnect_after.as_secs()
+            ),
         }
     }
 }
@@ -0,0 +1,183 @@
+use chat_client::{commands::Command, ChatClient, Config, ServerEvent};
+use futures::StreamExt;
+use std::{path::PathBuf, time::Duration};
+use tokio::{
+    fs::File,
+    io::{self, AsyncBufReadExt, AsyncRead, BufReader},
+    sync::mpsc,
+    time::sleep,
+};
+
+pub const EXIT_OK: i32 = 0;
+/// Invalid flags, an invalid config or an unreadable script
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONNECT_FAILED: i32 = 3;
+/// The server did not acknowledge the commands in time
+pub const EXIT_TIMEOUT: i32 = 4;
+/// The connection was lost for good before everything was done
+pub const EXIT_DISCONNECTED: i32 = 5;
+
+/// How the client runs without an interface.
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub script: Option<PathBuf>,
+    pub send: Vec<String>,
+    pub room: Option<String>,
+    pub wait_for_ack: bool,
+    pub timeout: Option<Duration>,
+}
+
+async fn read_lines(reader: impl AsyncRead + Unpin, lines: mpsc::UnboundedSender<String>) {
+    let mut reader = BufReader::new(reader).lines();
+    loop {
+        match reader.next_line().await {
+            Ok(Some(line)) => {
+                if lines.send(line).is_err() {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(why) => {
+                tracing::error!("Unable to read commands! {why}");
+                break;
+            }
+        }
+    }
+}
+
+/// Sends the commands and prints every event as a JSON line, returns the exit code.
+pub async fn run(options: Options, config: Config, hwid: String) -> i32 {
+    let (sender, mut lines) = mpsc::unbounded_channel();
+    let script = match options.script.as_deref() {
+        Some(path) if path.as_os_str() == "-" => None,
+        Some(path) => match File::open(path).await {
+            Ok(file) => Some(file),
+            Err(why) => {
+                tracing::error!("Unable to open {}! {why}", path.display());
+                return EXIT_USAGE;
+            }
+        },
+        None => None,
+    };
+
+    if let Some(room) = &options.room {
+        let _ = sender.send(format!("/join {room}"));
+    }
+    if !options.send.is_empty() {
+        for line in options.send {
+            let _ = sender.send(line);
+        }
+        drop(sender);
+    } else if let Some(file) = script {
+        tokio::spawn(read_lines(file, sender));
+    } else {
+        tokio::spawn(read_lines(io::stdin(), sender));
+    }
+
+    let timeout = options.timeout.unwrap_or(config.timeout);
+    let (client, mut events) = match ChatClient::connect_as(config, hwid).await {
+        Ok(connected) => connected,
+        Err(why) => {
+            tracing::error!("{why}");
+            return EXIT_CONNECT_FAILED;
+        }
+    };
+
+    // Every ping is answered in order, so once the last one is, everything before was handled
+    let (mut pings, mut pongs) = (0, 0);
+    let mut input_done = false;
+    let deadline = sleep(Duration::MAX);
+    tokio::pin!(deadline);
+
+    let code = loop {
+        tokio::select! {
+            line = lines.recv(), if !input_done => match line {
+                Some(line) => {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+
+                    match Command::parse(line) {
+                        Ok(Command::Ping) => pings += 1,
+                        // The client stops, which ends the events
+                        Ok(Command::Quit) => input_done = true,
+                        _ => {}
+                    }
+                    if client.send(line).is_err() {
+                        break EXIT_DISCONNECTED;
+                    }
+                }
+                None if options.wait_for_ack => {
+                    input_done = true;
+                    pings += 1;
+                    if client.send("/ping").is_err() {
+                        break EXIT_DISCONNECTED;
+                    }
+                    deadline.as_mut().reset(tokio::time::Instant::now() + timeout);
+                }
+                None => break EXIT_OK,
+            },
+            event = events.next() => match event {
+                Some(event) => {
+                    match serde_json::to_string(&event) {
+                        Ok(json) => println!("{json}"),
+                        Err(why) => tracing::error!("Unable to serialize {event:?}! {why}"),
+                    }
+
+                    if matches!(event, ServerEvent::Pong { .. }) {
+                        pongs += 1;
+                        if input_done && options.wait_for_ack && pongs >= pings {
+                            break EXIT_OK;
+                        }
+                    }
+                }
+                None if input_done && !options.wait_for_ack => break EXIT_OK,
+                None => break EXIT_DISCONNECTED,
+            },
+            _ = &mut deadline, if input_done => {
+                tracing::error!("The server did not acknowledge the commands in time");
+                break EXIT_TIMEOUT;
+            }
+        }
+    };
+
+    // Disconnect cleanly and print what arrived in the meantime
+    if client.quit().await.is_err() && code == EXIT_OK {
+        return EXIT_DISCONNECTED;
+    }
+    while let Some(event) = events.next().await {
+        if let Ok(json) = serde_json::to_string(&event) {
+            println!("{json}");
+        }
+    }
+
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use chat_client::{ConnectionState, ServerEvent};
+    use std::time::Duration;
+
+    #[test]
+    fn test_events_as_json() {
+        let event = ServerEvent::Status(ConnectionState::Reconnecting {
+            attempt: 2,
+            delay: Duration::from_millis(1500),
+        });
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"status","state":"reconnecting","attempt":2,"delay":1.5}"#
+        );
+
+        let event = ServerEvent::UserJoined {
+            username: "Alice".to_string(),
+            room: "general".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"user_joined","username":"Alice","room":"general"}"#
+        );
+    }
+}
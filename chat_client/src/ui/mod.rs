@@ -0,0 +1,25 @@
+pub mod headless;
+pub mod plain;
+pub mod tui;
+
+use chat_client::types::Notify;
+use std::io::{self, IsTerminal, Write};
+
+/// Gets the attention of the user, e.g. when mentioned. Does nothing unless stdout is a terminal.
+pub fn notify(mode: Notify, text: &str) {
+    let mut stdout = io::stdout();
+    if mode == Notify::Off || !stdout.is_terminal() {
+        return;
+    }
+
+    // OSC 9 asks the terminal for a desktop notification, control characters would end it early
+    let sequence = match mode {
+        Notify::Desktop => {
+            let text = text.replace(|c: char| c.is_control(), " ");
+            format!("\x1b]9;{text}\x07\x07")
+        }
+        _ => "\x07".to_string(),
+    };
+    let _ = stdout.write_all(sequence.as_bytes());
+    let _ = stdout.flush();
+}
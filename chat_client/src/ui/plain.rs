@@ -0,0 +1,47 @@
+use chat_client::types::{Notify, ServerEvent};
+use tokio::{
+    io::{self, AsyncBufReadExt, BufReader},
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+};
+
+/// Reads lines from stdin and prints every event, used when not running in a terminal.
+pub async fn run(
+    notify: Notify,
+    input: UnboundedSender<String>,
+    mut events: UnboundedReceiver<ServerEvent>,
+) {
+    tokio::spawn(read_lines(input));
+
+    // The client closes the channel once stdin is closed and everything was sent
+    while let Some(event) = events.recv().await {
+        // Joining a room is already announced by the server, and nothing to catch up on is not
+        // worth a line
+        let quiet = match &event {
+            ServerEvent::Identity { .. } => true,
+            ServerEvent::Welcome { unread, pending } => unread.is_empty() && *pending == 0,
+            _ => false,
+        };
+        if !quiet {
+            println!("{event}");
+        }
+        if let ServerEvent::Mention { username, room, .. } = &event {
+            super::notify(notify, &format!("{username} mentioned you in #{room}"));
+        }
+    }
+}
+
+async fn read_lines(input: UnboundedSender<String>) -> io::Result<()> {
+    let mut lines = BufReader::new(io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        let trimmed_input = line.trim();
+        if trimmed_input.is_empty() {
+            continue;
+        }
+
+        if input.send(trimmed_input.to_string()).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,620 @@
+use super::{complete::Completion, input::Input};
+use chat_client::{
+    commands::Command,
+    types::{ConnectionState, ServerEvent},
+};
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use std::collections::BTreeSet;
+use tokio::sync::mpsc::UnboundedSender;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    Message {
+        username: String,
+    },
+    Action,
+    DirectMessage {
+        username: String,
+    },
+    System,
+    /// Joins, leaves and renames
+    Notice,
+}
+
+/// A line in the message pane.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Set for messages, so they can be edited and deleted later on
+    pub id: Option<String>,
+    pub kind: EntryKind,
+    pub content: String,
+    /// The id of the message this one replies to
+    pub reply_to: Option<String>,
+    /// Every reaction with how often it was given
+    pub reactions: Vec<(String, u64)>,
+    /// Someone mentioned us in this message
+    pub mentioned: bool,
+    pub edited: bool,
+    pub deleted: bool,
+}
+
+impl Entry {
+    fn new(kind: EntryKind, content: String) -> Self {
+        Self {
+            id: None,
+            kind,
+            content,
+            reply_to: None,
+            reactions: Vec::new(),
+            mentioned: false,
+            edited: false,
+            deleted: false,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Room {
+    pub name: String,
+    pub entries: Vec<Entry>,
+    pub members: BTreeSet<String>,
+    /// Entries which arrived while another room was shown
+    pub unread: usize,
+    /// We were mentioned while another room was shown
+    pub mentioned: bool,
+}
+
+impl Room {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            ..Default::default()
+        }
+    }
+}
+
+pub struct App {
+    pub rooms: Vec<Room>,
+    /// The room which is shown
+    pub active: usize,
+    pub input: Input,
+    /// Set while cycling through completions with Tab
+    completion: Option<Completion>,
+    /// How many lines the message pane is scrolled up
+    pub scroll: usize,
+    pub status: ConnectionState,
+    pub name: String,
+    /// The room the server put us in
+    pub room: Option<String>,
+    /// Lines the user submitted, they are sent by the client
+    outgoing: UnboundedSender<String>,
+    /// What to notify the user about, taken after every event
+    pub alert: Option<String>,
+    pub quit: bool,
+}
+
+impl App {
+    pub fn new(name: String, outgoing: UnboundedSender<String>) -> Self {
+        Self {
+            // Everything which happens before joining a room ends up here
+            rooms: vec![Room::new(String::new())],
+            active: 0,
+            input: Input::default(),
+            completion: None,
+            scroll: 0,
+            status: ConnectionState::Connecting,
+            name,
+            room: None,
+            outgoing,
+            alert: None,
+            quit: false,
+        }
+    }
+
+    pub fn active_room(&self) -> &Room {
+        &self.rooms[self.active]
+    }
+
+    fn room_index(&mut self, name: &str) -> usize {
+        if let Some(index) = self.rooms.iter().position(|r| r.name.eq(name)) {
+            return index;
+        }
+
+        // The placeholder is replaced by the first real room
+        if self.rooms.len() == 1 && self.rooms[0].name.is_empty() {
+            self.rooms[0].name = name.to_string();
+            return 0;
+        }
+
+        self.rooms.push(Room::new(name.to_string()));
+        self.rooms.len() - 1
+    }
+
+    /// Adds the entry to the room, or to the shown one if the room is unknown.
+    fn push(&mut self, room: Option<&str>, entry: Entry) {
+        let index = match room {
+            Some(room) => self.room_index(room),
+            None => self.active,
+        };
+
+        let room = &mut self.rooms[index];
+        room.entries.push(entry);
+        if index != self.active {
+            room.unread += 1;
+        } else if self.scroll > 0 {
+            // Keep the lines the user is reading in place
+            self.scroll += 1;
+        }
+    }
+
+    /// Showing a room marks it as read on the server as well.
+    fn select(&mut self, index: usize) {
+        self.active = index;
+        self.rooms[index].unread = 0;
+        self.rooms[index].mentioned = false;
+        self.scroll = 0;
+
+        let name = self.rooms[index].name.clone();
+        if !name.is_empty() {
+            self.send(format!("/read {name}"));
+        }
+    }
+
+    fn send(&mut self, line: String) {
+        if self.outgoing.send(line).is_err() {
+            self.quit = true;
+        }
+    }
+
+    /// Switching to another room tab joins that room.
+    fn switch_room(&mut self, offset: isize) {
+        let count = self.rooms.len() as isize;
+        let index = (self.active as isize + offset).rem_euclid(count) as usize;
+        let name = self.rooms[index].name.clone();
+
+        if name.is_empty() || self.room.as_deref() == Some(name.as_str()) {
+            self.select(index);
+        } else {
+            self.send(format!("/join {name}"));
+        }
+    }
+
+    pub fn handle_server_event(&mut self, event: ServerEvent) {
+        let room = self.room.clone();
+        let room = room.as_deref();
+
+        match event {
+            ServerEvent::Status(status) => self.status = status,
+            ServerEvent::Identity { name, room } => {
+                self.name = name;
+                if let Some(room) = &room {
+                    let index = self.room_index(room);
+                    self.select(index);
+                }
+                self.room = room;
+            }
+            ServerEvent::Message {
+                id,
+                username,
+                content,
+                reply_to,
+                ..
+            } => {
+                let entry = Entry {
+                    id: Some(id.clone()),
+                    reply_to,
+                    ..Entry::new(EntryKind::Message { username }, content)
+                };
+                self.push(room, entry);
+
+                if let Some(room) = room.filter(|r| self.active_room().name.eq(r)) {
+                    self.send(format!("/read {room} {id}"));
+                }
+            }
+            ServerEvent::ThreadMessage {
+                room,
+                id,
+                username,
+                content,
+                reply_to,
+                ..
+            } => {
+                // Fetched threads repeat messages which may already be shown
+                let index = self.room_index(&room);
+                if self.rooms[index]
+                    .entries
+                    .iter()
+                    .any(|e| e.id.as_ref() == Some(&id))
+                {
+                    return;
+                }
+                let entry = Entry {
+                    id: Some(id),
+                    reply_to,
+                    ..Entry::new(EntryKind::Message { username }, content)
+                };
+                self.push(Some(&room), entry);
+            }
+            ServerEvent::DirectMessage {
+                username, content, ..
+            } => {
+                self.push(
+                    None,
+                    Entry::new(EntryKind::DirectMessage { username }, content),
+                );
+            }
+            ServerEvent::Welcome { unread, pending } => {
+                if unread.is_empty() && pending == 0 {
+                    return;
+                }
+                let content = ServerEvent::Welcome {
+                    unread: unread.clone(),
+                    pending,
+                }
+                .to_string();
+
+                // The messages themselves are not fetched, the tabs only show how many there are
+                for (name, count) in unread {
+                    let index = self.room_index(&name);
+                    if index != self.active {
+                        self.rooms[index].unread += count as usize;
+                    }
+                }
+                self.push(None, Entry::new(EntryKind::System, content));
+            }
+            ServerEvent::FileShared { ref room, .. } => {
+                let room = room.clone();
+                let entry = Entry::new(EntryKind::Notice, event.to_string());
+                self.push(Some(&room), entry);
+            }
+            ServerEvent::FileDownloaded { .. } => {
+                self.push(None, Entry::new(EntryKind::System, event.to_string()));
+            }
+            ServerEvent::System { content } => {
+                // `/me` messages are sent as system messages starting with an asterisk
+                let kind = match content.starts_with("* ") {
+                    true => EntryKind::Action,
+                    false => EntryKind::System,
+                };
+                self.push(None, Entry::new(kind, content));
+            }
+            ServerEvent::UserJoined { username, room } => {
+                let index = self.room_index(&room);
+                self.rooms[index].members.insert(username.clone());
+                let entry = Entry::new(EntryKind::Notice, format!("{username} joined #{room}"));
+                self.push(Some(&room), entry);
+            }
+            ServerEvent::UserLeft { username, room } => {
+                let index = self.room_index(&room);
+                self.rooms[index].members.remove(&username);
+                let entry = Entry::new(EntryKind::Notice, format!("{username} left #{room}"));
+                self.push(Some(&room), entry);
+            }
+            ServerEvent::UsernameChanged {
+                old_username,
+                new_username,
+            } => {
+                let content = format!("{old_username} is now known as {new_username}");
+                for index in 0..self.rooms.len() {
+                    let members = &mut self.rooms[index].members;
+                    if members.remove(&old_username) {
+                        members.insert(new_username.clone());
+                        let entry = Entry::new(EntryKind::Notice, content.clone());
+                        let name = self.rooms[index].name.clone();
+                        self.push(Some(&name), entry);
+                    }
+                }
+            }
+            ServerEvent::Mention {
+                id,
+                room,
+                username,
+                content,
+                ..
+            } => {
+                self.alert = Some(format!("{username} mentioned you in #{room}"));
+                let index = self.room_index(&room);
+                self.rooms[index].mentioned |= index != self.active;
+
+                let entries = &mut self.rooms[index].entries;
+                match entries.iter_mut().find(|e| e.id.as_ref() == Some(&id)) {
+                    Some(entry) => entry.mentioned = true,
+                    // We are in another room, so the message itself did not arrive
+                    None => {
+                        let entry = Entry {
+                            id: Some(id),
+                            mentioned: true,
+                            ..Entry::new(EntryKind::Message { username }, content)
+                        };
+                        self.push(Some(&room), entry);
+                    }
+                }
+            }
+            ServerEvent::MessageSent { id, room, .. } => {
+                // Our messages are shown right away, they only learn their id now
+                let index = self.room_index(&room);
+                let name = &self.name;
+                let entries = &mut self.rooms[index].entries;
+                if let Some(entry) = entries.iter_mut().rev().find(|e| {
+                    e.id.is_none()
+                        && matches!(&e.kind, EntryKind::Message { username } if username == name)
+                }) {
+                    entry.id = Some(id);
+                }
+            }
+            ServerEvent::MessageEdited {
+                id, room, content, ..
+            } => {
+                let index = self.room_index(&room);
+                let entries = &mut self.rooms[index].entries;
+                if let Some(entry) = entries.iter_mut().find(|e| e.id.as_ref() == Some(&id)) {
+                    entry.content = content;
+                    entry.edited = true;
+                }
+            }
+            ServerEvent::ReactionsUpdated {
+                id,
+                room,
+                reactions,
+            } => {
+                let index = self.room_index(&room);
+                let entries = &mut self.rooms[index].entries;
+                if let Some(entry) = entries.iter_mut().find(|e| e.id.as_ref() == Some(&id)) {
+                    entry.reactions = reactions;
+                }
+            }
+            ServerEvent::MessageDeleted { id, room } => {
+                let index = self.room_index(&room);
+                let entries = &mut self.rooms[index].entries;
+                if let Some(entry) = entries.iter_mut().find(|e| e.id.as_ref() == Some(&id)) {
+                    entry.deleted = true;
+                }
+            }
+            ServerEvent::RoomMembers { room, usernames } => {
+                let index = self.room_index(&room);
+                self.rooms[index].members = usernames.into_iter().collect();
+            }
+            event @ (ServerEvent::Pong { .. } | ServerEvent::ServerShutdown { .. }) => {
+                self.push(None, Entry::new(EntryKind::System, event.to_string()));
+            }
+        }
+    }
+
+    fn complete(&mut self) {
+        if self.completion.is_none() {
+            let members = &self.active_room().members;
+            let usernames = members.iter().map(String::as_str).collect::<Vec<_>>();
+            self.completion = Completion::new(&self.input, &usernames);
+        }
+
+        if let Some(completion) = &mut self.completion {
+            completion.apply(&mut self.input);
+        }
+    }
+
+    pub fn handle_terminal_event(&mut self, event: Event) {
+        match event {
+            Event::Key(key) if key.kind != KeyEventKind::Release => self.handle_key(key),
+            Event::Paste(text) => text
+                .chars()
+                .filter(|c| !c.is_control())
+                .for_each(|c| self.input.insert(c)),
+            _ => {}
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+        if key.code == KeyCode::Tab {
+            return self.complete();
+        }
+        self.completion = None;
+
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => self.quit = true,
+            KeyCode::Char('a') if ctrl => self.input.home(),
+            KeyCode::Char('e') if ctrl => self.input.end(),
+            KeyCode::Char('u') if ctrl => self.input.clear_to_start(),
+            KeyCode::Char('w') if ctrl => self.input.delete_word(),
+            KeyCode::Char('n') if ctrl => self.switch_room(1),
+            KeyCode::Char('p') if ctrl => self.switch_room(-1),
+            KeyCode::Left if alt => self.switch_room(-1),
+            KeyCode::Right if alt => self.switch_room(1),
+            KeyCode::Char(c) if !ctrl && !alt => self.input.insert(c),
+            KeyCode::Backspace => self.input.backspace(),
+            KeyCode::Delete => self.input.delete(),
+            KeyCode::Left => self.input.left(),
+            KeyCode::Right => self.input.right(),
+            KeyCode::Home => self.input.home(),
+            KeyCode::End => self.input.end(),
+            KeyCode::Up => self.input.history_previous(),
+            KeyCode::Down => self.input.history_next(),
+            KeyCode::PageUp => self.scroll += 10,
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Enter => {
+                if let Some(line) = self.input.submit() {
+                    self.scroll = 0;
+                    // The server does not echo our own messages back
+                    let (content, reply_to) = match Command::parse(&line) {
+                        Ok(Command::Reply { id, content }) => (Some(content), Some(id)),
+                        _ if !line.starts_with('/') => (Some(line.clone()), None),
+                        _ => (None, None),
+                    };
+                    if let (Some(content), Some(_)) = (content, &self.room) {
+                        let kind = EntryKind::Message {
+                            username: self.name.clone(),
+                        };
+                        let room = self.room.clone();
+                        let entry = Entry {
+                            reply_to,
+                            ..Entry::new(kind, content)
+                        };
+                        self.push(room.as_deref(), entry);
+                    }
+                    self.send(line);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{App, EntryKind};
+    use chat_client::types::ServerEvent;
+    use crossterm::event::{KeyCode, KeyEvent};
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn test_room_tabs() {
+        let (outgoing, mut lines) = mpsc::unbounded_channel();
+        let mut app = App::new("Me".to_string(), outgoing);
+
+        let identity = |room: &str| ServerEvent::Identity {
+            name: "Me".to_string(),
+            room: Some(room.to_string()),
+        };
+        app.handle_server_event(identity("general"));
+        app.handle_server_event(ServerEvent::RoomMembers {
+            room: "general".to_string(),
+            usernames: vec!["Me".to_string(), "You".to_string()],
+        });
+        app.handle_server_event(identity("rust"));
+        app.handle_server_event(ServerEvent::Message {
+            id: "1".to_string(),
+            username: "You".to_string(),
+            content: "Hello".to_string(),
+            timestamp: 1_700_000_000,
+            reply_to: None,
+        });
+
+        assert_eq!(app.rooms.len(), 2);
+        assert_eq!(app.active_room().name, "rust");
+        let entry = app.active_room().entries.last().unwrap();
+        assert!(matches!(&entry.kind, EntryKind::Message { username } if username == "You"));
+        assert_eq!(app.rooms[0].members.len(), 2);
+
+        // Shown rooms and messages are marked read, switching back to the other tab asks the
+        // server to join the room
+        app.switch_room(1);
+        let sent = std::iter::from_fn(|| lines.try_recv().ok()).collect::<Vec<_>>();
+        assert_eq!(
+            sent,
+            [
+                "/read general",
+                "/read rust",
+                "/read rust 1",
+                "/join general"
+            ]
+        );
+
+        app.handle_server_event(ServerEvent::MessageDeleted {
+            id: "1".to_string(),
+            room: "rust".to_string(),
+        });
+        assert!(app.rooms[1].entries.last().unwrap().deleted);
+
+        app.handle_server_event(ServerEvent::Welcome {
+            unread: vec![("general".to_string(), 3), ("rust".to_string(), 2)],
+            pending: 1,
+        });
+        assert_eq!(app.rooms[0].unread, 3);
+        assert_eq!(app.rooms[1].unread, 0);
+        assert!(matches!(
+            app.active_room().entries.last().unwrap().kind,
+            EntryKind::System
+        ));
+    }
+
+    #[test]
+    fn test_message_edits() {
+        let (outgoing, _lines) = mpsc::unbounded_channel();
+        let mut app = App::new("Me".to_string(), outgoing);
+        app.handle_server_event(ServerEvent::Identity {
+            name: "Me".to_string(),
+            room: Some("general".to_string()),
+        });
+
+        app.handle_key(KeyEvent::from(KeyCode::Char('x')));
+        app.handle_key(KeyEvent::from(KeyCode::Enter));
+        app.handle_server_event(ServerEvent::MessageSent {
+            id: "7".to_string(),
+            room: "general".to_string(),
+            timestamp: 1_700_000_000,
+        });
+        app.handle_server_event(ServerEvent::MessageEdited {
+            id: "7".to_string(),
+            room: "general".to_string(),
+            content: "y".to_string(),
+            edited_at: 1_700_000_001,
+        });
+
+        app.handle_server_event(ServerEvent::ReactionsUpdated {
+            id: "7".to_string(),
+            room: "general".to_string(),
+            reactions: vec![(":tada:".to_string(), 2)],
+        });
+
+        let entry = app.active_room().entries.last().unwrap();
+        assert_eq!(entry.id.as_deref(), Some("7"));
+        assert_eq!(entry.content, "y");
+        assert!(entry.edited);
+        assert_eq!(entry.reactions, [(":tada:".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_thread_messages() {
+        let (outgoing, _lines) = mpsc::unbounded_channel();
+        let mut app = App::new("Me".to_string(), outgoing);
+        let message = |id: &str, reply_to: Option<&str>| ServerEvent::ThreadMessage {
+            thread: "1".to_string(),
+            room: "general".to_string(),
+            id: id.to_string(),
+            username: "Alice".to_string(),
+            content: "x".to_string(),
+            timestamp: 1_700_000_000,
+            reply_to: reply_to.map(str::to_string),
+        };
+
+        app.handle_server_event(message("1", None));
+        app.handle_server_event(message("2", Some("1")));
+        app.handle_server_event(message("2", Some("1")));
+
+        let index = app.room_index("general");
+        let entries = &app.rooms[index].entries;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].reply_to.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_mentions() {
+        let (outgoing, _lines) = mpsc::unbounded_channel();
+        let mut app = App::new("Me".to_string(), outgoing);
+        app.handle_server_event(ServerEvent::Identity {
+            name: "Me".to_string(),
+            room: Some("general".to_string()),
+        });
+        let mention = |room: &str| ServerEvent::Mention {
+            id: "3".to_string(),
+            room: room.to_string(),
+            username: "Alice".to_string(),
+            content: "@Me look".to_string(),
+            mention: "@Me".to_string(),
+            timestamp: 1_700_000_000,
+        };
+
+        app.handle_server_event(mention("rust"));
+        assert!(app.alert.take().is_some());
+        let index = app.room_index("rust");
+        assert!(app.rooms[index].mentioned);
+        assert!(app.rooms[index].entries[0].mentioned);
+
+        app.select(index);
+        assert!(!app.rooms[index].mentioned);
+        app.handle_server_event(mention("rust"));
+        assert_eq!(app.rooms[index].entries.len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use super::input::Input;
+use chat_client::commands::{COMMANDS, SETTINGS};
+
+/// Cycles through the completions of the word before the cursor, one per press of Tab.
+#[derive(Debug)]
+pub struct Completion {
+    /// Where the completed word starts
+    start: usize,
+    candidates: Vec<String>,
+    index: usize,
+}
+
+impl Completion {
+    /// Completes commands at the start of the line, settings after `/set` and usernames
+    /// everywhere else. Returns `None` if nothing matches.
+    pub fn new(input: &Input, usernames: &[&str]) -> Option<Self> {
+        let start = input.word_start();
+        let before = input.text().chars().take(start).collect::<String>();
+        let word = input
+            .text()
+            .chars()
+            .skip(start)
+            .take(input.cursor() - start)
+            .collect::<String>()
+            .to_lowercase();
+        let matches = |candidate: &&&str| candidate.to_lowercase().starts_with(&word);
+
+        let candidates = if start == 0 && word.starts_with('/') {
+            let word = &word[1..];
+            COMMANDS
+                .iter()
+                .filter(|(name, _)| name.starts_with(word))
+                .map(|(name, _)| format!("/{name} "))
+                .collect()
+        } else if before.trim_end().eq_ignore_ascii_case("/set") {
+            SETTINGS
+                .iter()
+                .filter(matches)
+                .map(|setting| format!("{setting} "))
+                .collect()
+        } else if word.is_empty() {
+            vec![]
+        } else {
+            // Addressing someone at the start of a message
+            let suffix = if start == 0 { ": " } else { " " };
+            usernames
+                .iter()
+                .filter(matches)
+                .map(|username| format!("{username}{suffix}"))
+                .collect::<Vec<_>>()
+        };
+
+        if candidates.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            start,
+            candidates,
+            index: 0,
+        })
+    }
+
+    /// Puts the current candidate into the input and moves on to the next one.
+    pub fn apply(&mut self, input: &mut Input) {
+        input.replace(self.start, &self.candidates[self.index]);
+        self.index = (self.index + 1) % self.candidates.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Completion;
+    use crate::ui::tui::input::Input;
+
+    fn complete(text: &str, usernames: &[&str]) -> Vec<String> {
+        let mut input = Input::default();
+        input.set(text.to_string());
+
+        let Some(mut completion) = Completion::new(&input, usernames) else {
+            return vec![];
+        };
+        (0..completion.candidates.len())
+            .map(|_| {
+                input.set(text.to_string());
+                completion.apply(&mut input);
+                input.text().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_completion() {
+        assert_eq!(complete("/rec", &[]), vec!["/reconnect "]);
+        assert_eq!(
+            complete("/re", &[]),
+            vec!["/reply ", "/react ", "/read ", "/reconnect "]
+        );
+        assert_eq!(complete("/m", &[]), vec!["/msg ", "/me "]);
+        assert_eq!(complete("/set heart", &[]).len(), 2);
+        assert_eq!(
+            complete("/msg al", &["Bob", "Alice", "alex"]),
+            vec!["/msg Alice ", "/msg alex "]
+        );
+        assert_eq!(complete("bo", &["Bob"]), vec!["Bob: "]);
+        assert!(complete("hello ", &["Bob"]).is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+use super::app::{App, Entry, EntryKind};
+use chat_client::types::ConnectionState;
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Tabs},
+    Frame,
+};
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+const SIDEBAR_WIDTH: u16 = 20;
+const USERNAME_COLORS: [Color; 6] = [
+    Color::Cyan,
+    Color::Green,
+    Color::Yellow,
+    Color::Magenta,
+    Color::Blue,
+    Color::LightRed,
+];
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [tabs, body, input, status] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(1),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [messages, users] =
+        Layout::horizontal([Constraint::Min(1), Constraint::Length(SIDEBAR_WIDTH)]).areas(body);
+
+    draw_tabs(frame, app, tabs);
+    draw_messages(frame, app, messages);
+    draw_users(frame, app, users);
+    draw_input(frame, app, input);
+    draw_status(frame, app, status);
+}
+
+/// Messages mentioning us, and the tabs of rooms with unseen mentions.
+const MENTION: Style = Style::new()
+    .fg(Color::LightYellow)
+    .add_modifier(Modifier::BOLD);
+
+fn username_color(username: &str) -> Color {
+    let mut hasher = DefaultHasher::new();
+    username.hash(&mut hasher);
+    USERNAME_COLORS[hasher.finish() as usize % USERNAME_COLORS.len()]
+}
+
+fn draw_tabs(frame: &mut Frame, app: &App, area: Rect) {
+    let titles = app.rooms.iter().map(|room| {
+        let name = match room.name.is_empty() {
+            true => "(no room)".to_string(),
+            false => format!("#{}", room.name),
+        };
+        let line = match room.unread {
+            0 => Line::from(name),
+            unread => Line::from(vec![
+                Span::raw(name),
+                Span::raw(format!(" ({unread})")).bold(),
+            ]),
+        };
+        match room.mentioned {
+            true => line.style(MENTION),
+            false => line,
+        }
+    });
+
+    let tabs = Tabs::new(titles)
+        .select(app.active)
+        .highlight_style(Style::new().reversed());
+    frame.render_widget(tabs, area);
+}
+
+/// The prefix and the text of an entry.
+fn entry_spans(entry: &Entry) -> (Vec<Span<'_>>, Style) {
+    let dim = Style::new().fg(Color::DarkGray);
+    if entry.deleted {
+        return (vec![], dim.add_modifier(Modifier::ITALIC));
+    }
+
+    match &entry.kind {
+        EntryKind::Message { username } => {
+            let mut prefix = vec![];
+            if let Some(id) = &entry.reply_to {
+                prefix.push(Span::styled(format!("↳ {id} "), dim));
+            }
+            prefix.push(Span::styled(
+                format!("{username}: "),
+                Style::new().fg(username_color(username)).bold(),
+            ));
+            (prefix, Style::new())
+        }
+        EntryKind::DirectMessage { username } => (
+            vec![Span::styled(
+                format!("{username} (private): "),
+                Style::new().fg(username_color(username)).bold(),
+            )],
+            Style::new().italic(),
+        ),
+        EntryKind::Action => (vec![], Style::new().fg(Color::Magenta)),
+        EntryKind::System => (vec![], Style::new().fg(Color::Yellow)),
+        EntryKind::Notice => (vec![], dim),
+    }
+}
+
+/// Splits the text into lines of at most `width` characters, breaking at spaces when possible.
+/// The first line is shortened by `indent`, to make room for a prefix.
+pub fn wrap(text: &str, width: usize, indent: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = vec![];
+    let mut line = String::new();
+    let mut length = indent.min(width - 1);
+    let mut empty = true;
+
+    for word in text.split(' ') {
+        let mut word = word.chars().collect::<Vec<_>>();
+
+        if !empty && length + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+            length = 0;
+        } else if !empty {
+            line.push(' ');
+            length += 1;
+        }
+
+        // Words which do not fit on a line at all are broken up
+        while length + word.len() > width {
+            let rest = word.split_off(width - length);
+            line.extend(word);
+            lines.push(std::mem::take(&mut line));
+            length = 0;
+            word = rest;
+        }
+
+        length += word.len();
+        line.extend(word);
+        empty = false;
+    }
+
+    lines.push(line);
+    lines
+}
+
+fn draw_messages(frame: &mut Frame, app: &App, area: Rect) {
+    let width = area.width as usize;
+    let mut lines = vec![];
+
+    for entry in &app.active_room().entries {
+        let (prefix, mut style) = entry_spans(entry);
+        if entry.mentioned && !entry.deleted {
+            style = style.patch(MENTION);
+        }
+        let prefix_length = prefix.iter().map(|s| s.content.chars().count()).sum();
+        let mut content = match (entry.deleted, entry.edited) {
+            (true, _) => "(message deleted)".to_string(),
+            (false, true) => format!("{} (edited)", entry.content),
+            (false, false) => entry.content.clone(),
+        };
+        if !entry.deleted && !entry.reactions.is_empty() {
+            let reactions = entry.reactions.iter();
+            let reactions = reactions.map(|(reaction, count)| format!("{reaction} {count}"));
+            content = format!("{content}  [{}]", reactions.collect::<Vec<_>>().join(", "));
+        }
+
+        let mut wrapped = wrap(&content, width, prefix_length).into_iter();
+        if let Some(first) = wrapped.next() {
+            let mut spans = prefix;
+            spans.push(Span::styled(first, style));
+            lines.push(Line::from(spans));
+        }
+        lines.extend(wrapped.map(|line| Line::styled(line, style)));
+    }
+
+    // Show the newest lines at the bottom, scrolled up by the requested amount
+    let height = area.height as usize;
+    let scroll = app.scroll.min(lines.len().saturating_sub(height));
+    let end = lines.len() - scroll;
+    let start = end.saturating_sub(height);
+    let visible = lines.drain(start..end).collect::<Vec<_>>();
+
+    frame.render_widget(Paragraph::new(visible), area);
+}
+
+fn draw_users(frame: &mut Frame, app: &App, area: Rect) {
+    let members = &app.active_room().members;
+    let lines = members
+        .iter()
+        .map(|name| {
+            let style = Style::new().fg(username_color(name));
+            match name.eq(&app.name) {
+                true => Line::styled(name.as_str(), style.bold()),
+                false => Line::styled(name.as_str(), style),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let block = Block::new()
+        .borders(Borders::LEFT)
+        .title(format!(" Users ({}) ", members.len()));
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn draw_input(frame: &mut Frame, app: &App, area: Rect) {
+    let block = Block::bordered().title(format!(" {} ", app.name));
+    let inner = block.inner(area);
+
+    // Scroll the line horizontally so the cursor stays visible
+    let width = inner.width.saturating_sub(1) as usize;
+    let cursor = app.input.cursor();
+    let offset = cursor.saturating_sub(width);
+    let text = app.input.text().chars().skip(offset).collect::<String>();
+
+    frame.render_widget(Paragraph::new(text).block(block), area);
+    frame.set_cursor_position(Position::new(inner.x + (cursor - offset) as u16, inner.y));
+}
+
+fn draw_status(frame: &mut Frame, app: &App, area: Rect) {
+    let color = match app.status {
+        ConnectionState::Connected => Color::Green,
+        ConnectionState::Connecting | ConnectionState::Reconnecting { .. } => Color::Yellow,
+        ConnectionState::Disconnected => Color::Red,
+    };
+    let room = match &app.room {
+        Some(room) => format!("#{room}"),
+        None => "no room".to_string(),
+    };
+
+    let line = Line::from(vec![
+        Span::styled(format!(" {} ", app.status), Style::new().fg(color).bold()),
+        Span::raw(format!("| {} in {room} ", app.name)),
+        Span::styled(
+            "| Tab complete, Alt+←/→ rooms, PgUp/PgDn scroll, Ctrl+C quit",
+            Style::new().fg(Color::DarkGray),
+        ),
+    ]);
+    frame.render_widget(Paragraph::new(line).reversed(), area);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wrap;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap("hello big world", 9, 0), vec!["hello big", "world"]);
+        assert_eq!(wrap("abcdefghij", 4, 0), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(wrap("a abcdef", 4, 0), vec!["a", "abcd", "ef"]);
+        assert_eq!(wrap("hello world", 8, 3), vec!["hello", "world"]);
+        assert_eq!(wrap("", 4, 2), vec![""]);
+    }
+}
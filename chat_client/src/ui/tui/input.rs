@@ -0,0 +1,215 @@
+/// The line the user is typing, with a cursor and a history of the submitted lines.
+#[derive(Debug, Default)]
+pub struct Input {
+    text: String,
+    /// In characters, not bytes
+    cursor: usize,
+    history: Vec<String>,
+    /// The entry of the history which is shown, `None` while editing a new line
+    history_index: Option<usize>,
+    /// What was typed before browsing the history
+    draft: String,
+}
+
+impl Input {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    fn byte_index(&self, cursor: usize) -> usize {
+        self.text
+            .char_indices()
+            .nth(cursor)
+            .map_or(self.text.len(), |(index, _)| index)
+    }
+
+    fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    pub fn insert(&mut self, c: char) {
+        let index = self.byte_index(self.cursor);
+        self.text.insert(index, c);
+        self.cursor += 1;
+    }
+
+    /// Deletes the character before the cursor.
+    pub fn backspace(&mut self) {
+        if self.cursor == 0 {
+            return;
+        }
+        self.cursor -= 1;
+        let index = self.byte_index(self.cursor);
+        self.text.remove(index);
+    }
+
+    /// Deletes the character under the cursor.
+    pub fn delete(&mut self) {
+        if self.cursor < self.len() {
+            let index = self.byte_index(self.cursor);
+            self.text.remove(index);
+        }
+    }
+
+    /// Deletes the word before the cursor, like Ctrl+W in a shell.
+    pub fn delete_word(&mut self) {
+        let chars = self.text.chars().collect::<Vec<_>>();
+        let mut start = self.cursor;
+        while start > 0 && chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+
+        let (from, to) = (self.byte_index(start), self.byte_index(self.cursor));
+        self.text.replace_range(from..to, "");
+        self.cursor = start;
+    }
+
+    /// Deletes everything before the cursor.
+    pub fn clear_to_start(&mut self) {
+        let index = self.byte_index(self.cursor);
+        self.text.replace_range(..index, "");
+        self.cursor = 0;
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.len());
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.len();
+    }
+
+    /// Replaces the whole line, e.g. with an entry of the history.
+    pub fn set(&mut self, text: String) {
+        self.text = text;
+        self.cursor = self.len();
+    }
+
+    /// Where the word before the cursor starts.
+    pub fn word_start(&self) -> usize {
+        let chars = self.text.chars().take(self.cursor).collect::<Vec<_>>();
+        chars
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |index| index + 1)
+    }
+
+    /// Replaces everything from `start` up to the cursor, e.g. with a completion.
+    pub fn replace(&mut self, start: usize, text: &str) {
+        let (from, to) = (self.byte_index(start), self.byte_index(self.cursor));
+        self.text.replace_range(from..to, text);
+        self.cursor = start + text.chars().count();
+    }
+
+    pub fn history_previous(&mut self) {
+        let index = match self.history_index {
+            Some(0) => return,
+            Some(index) => index - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.text.clone();
+                self.history.len() - 1
+            }
+        };
+
+        self.history_index = Some(index);
+        self.set(self.history[index].clone());
+    }
+
+    pub fn history_next(&mut self) {
+        let Some(index) = self.history_index else {
+            return;
+        };
+
+        if index + 1 < self.history.len() {
+            self.history_index = Some(index + 1);
+            self.set(self.history[index + 1].clone());
+        } else {
+            self.history_index = None;
+            let draft = std::mem::take(&mut self.draft);
+            self.set(draft);
+        }
+    }
+
+    /// Takes the line and remembers it in the history, returns `None` if it is blank.
+    pub fn submit(&mut self) -> Option<String> {
+        let line = std::mem::take(&mut self.text).trim().to_string();
+        self.cursor = 0;
+        self.history_index = None;
+        self.draft.clear();
+
+        if line.is_empty() {
+            return None;
+        }
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Input;
+
+    fn input(text: &str) -> Input {
+        let mut input = Input::default();
+        text.chars().for_each(|c| input.insert(c));
+        input
+    }
+
+    #[test]
+    fn test_input_editing() {
+        let mut input = input("hällo world");
+        input.delete_word();
+        assert_eq!(input.text(), "hällo ");
+
+        input.home();
+        input.right();
+        input.delete();
+        input.insert('e');
+        assert_eq!(input.text(), "hello ");
+        assert_eq!(input.cursor(), 2);
+
+        input.end();
+        input.backspace();
+        input.left();
+        input.clear_to_start();
+        assert_eq!(input.text(), "o");
+    }
+
+    #[test]
+    fn test_input_history() {
+        let mut input = input("first");
+        input.submit();
+        "second".chars().for_each(|c| input.insert(c));
+        input.submit();
+        "draft".chars().for_each(|c| input.insert(c));
+
+        input.history_previous();
+        assert_eq!(input.text(), "second");
+        input.history_previous();
+        input.history_previous();
+        assert_eq!(input.text(), "first");
+        input.history_next();
+        input.history_next();
+        assert_eq!(input.text(), "draft");
+        assert_eq!(input.submit().as_deref(), Some("draft"));
+        assert_eq!(input.submit(), None);
+    }
+}
@@ -0,0 +1,52 @@
+mod app;
+mod complete;
+mod draw;
+mod input;
+
+use app::App;
+use chat_client::types::{Notify, ServerEvent};
+use crossterm::event::EventStream;
+use futures::StreamExt;
+use std::io;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// Runs the full-screen interface until the user quits or the client stops.
+pub async fn run(
+    name: String,
+    notify: Notify,
+    input: UnboundedSender<String>,
+    mut events: UnboundedReceiver<ServerEvent>,
+) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let mut terminal_events = EventStream::new();
+    let mut app = App::new(name, input);
+
+    let result = loop {
+        if let Err(e) = terminal.draw(|frame| draw::draw(frame, &app)) {
+            break Err(e);
+        }
+
+        tokio::select! {
+            event = terminal_events.next() => match event {
+                Some(Ok(event)) => app.handle_terminal_event(event),
+                Some(Err(e)) => break Err(e),
+                None => break Ok(()),
+            },
+            event = events.recv() => match event {
+                Some(event) => app.handle_server_event(event),
+                None => break Ok(()),
+            },
+        }
+
+        if let Some(alert) = app.alert.take() {
+            super::notify(notify, &alert);
+        }
+
+        if app.quit {
+            break Ok(());
+        }
+    };
+
+    ratatui::restore();
+    result
+}
@@ -3,42 +3,39 @@ use chat_shared::{
     types::{Deserialize, Serialize},
 };
 use machineid_rs::{HWIDComponent, IdBuilder};
-use std::{io::Write, net::TcpStream, process};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-use crate::KEY;
+use crate::{ClientError, KEY};
 
-pub fn construct_hwid() -> String {
+pub fn construct_hwid() -> Result<String, ClientError> {
     let mut builder = IdBuilder::new(machineid_rs::Encryption::SHA256);
     builder
         .add_component(HWIDComponent::CPUID)
         .add_component(HWIDComponent::SystemID);
 
-    match builder.build(KEY) {
-        Ok(k) => k,
-        Err(why) => {
-            log::error!("Unable to construct HWID, {why}");
-            process::exit(0);
-        }
-    }
+    builder
+        .build(KEY)
+        .map_err(|why| ClientError::Hwid(why.to_string()))
 }
 
-pub async fn write_to_stream<T>(
-    mut stream: &TcpStream,
-    content: &T,
-) -> Result<bool, WriteToStreamError>
+pub async fn write_to_stream<W, T>(stream: &mut W, content: &T) -> Result<bool, WriteToStreamError>
 where
+    W: AsyncWrite + Unpin,
     T: Serialize + Deserialize,
 {
     let Ok(serialized) = &content.serialize().await else {
-        log::error!("Unable to serialize message");
+        tracing::error!("Unable to serialize message");
         return Ok(false);
     };
 
-    if stream.write_all(&serialized[..]).is_ok() {
-        log::info!("[✔] Message broadcasted!");
-        Ok(true)
-    } else {
-        log::warn!("[❌] There was an error broadcasting the message");
-        Ok(false)
+    match stream.write_all(&serialized[..]).await {
+        Ok(_) => {
+            tracing::trace!(bytes = serialized.len(), "Message written");
+            Ok(true)
+        }
+        Err(why) => {
+            tracing::warn!("Unable to write the message! {why}");
+            Ok(false)
+        }
     }
 }
@@ -62,7 +62,9 @@ pub fn derive_deserialize(input: TokenStream) -> TokenStream {
     let mut return_string = String::from("return Ok(Self {\n");
     fields.iter().for_each(|f| {
         let field_name = f.ident.clone().unwrap().to_string();
-        return_string.push_str(&format!("{field_name}: {field_name}.unwrap(),\n"));
+        return_string.push_str(&format!(
+            "{field_name}: {field_name}.ok_or(DeserializerError::InvalidData)?,\n"
+        ));
     });
     return_string.push_str("});");
 
@@ -86,10 +88,6 @@ pub fn derive_deserialize(input: TokenStream) -> TokenStream {
 
                 let mut inner_cursor = prepare_inner_cursor(&mut data).await?;
                 {variables}
-
-                //TODO IMPORTANT!! ADD #.is_none() checks and return 
-                //`return Err(DeserializerError::InvalidData);`
-
                 {return_string}
             }}
         }}"
@@ -121,23 +119,23 @@ fn get_struct_name(input: &DeriveInput) -> String {
 }
 
 fn parse_attr(input: &DeriveInput) -> String {
-    if input.attrs.len() != 1 {
+    // Other attributes, e.g. doc comments, are allowed next to it
+    let mut attrs = input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("Belonging"));
+    let (Some(attr), None) = (attrs.next(), attrs.next()) else {
         panic!(
-            "Struct must have exactly one attribute: either ClientMessageType or ServerMessageType"
+            "Struct must have exactly one Belonging attribute: either ClientMessageType or ServerMessageType"
         );
-    }
+    };
 
-    let attr = input.attrs.first().unwrap();
-    if attr.path().is_ident("Belonging") {
-        let args = attr.parse_args::<TypePath>().unwrap();
-        let message_type = args.to_token_stream().to_string();
+    let args = attr.parse_args::<TypePath>().unwrap();
+    let message_type = args.to_token_stream().to_string();
 
-        if message_type != "ClientMessageType" && message_type != "ServerMessageType" {
-            panic!("Belonging attribute argument must be either 'ClientMessageType' or 'ServerMessageType'");
-        }
-
-        message_type
-    } else {
-        panic!("Only the Belonging attribute is supported");
+    if message_type != "ClientMessageType" && message_type != "ServerMessageType" {
+        panic!("Belonging attribute argument must be either 'ClientMessageType' or 'ServerMessageType'");
     }
+
+    message_type
 }
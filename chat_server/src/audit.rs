@@ -0,0 +1,485 @@
+use crate::{
+    moderation::Role,
+    types::{AuditConfig, Client},
+    utils::unix_timestamp,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+/// The `prev` of the first entry.
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub username: String,
+    /// Unknown if the account was only referred to by its name, e.g. by `/unban`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hwid: Option<String>,
+}
+
+impl Account {
+    pub fn new(hwid: &str, username: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            hwid: Some(hwid.to_string()),
+        }
+    }
+
+    pub fn named(username: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            hwid: None,
+        }
+    }
+
+    fn is(&self, user: &str) -> bool {
+        self.username.eq(user) || self.hwid.as_deref() == Some(user)
+    }
+}
+
+impl From<&Client> for Account {
+    fn from(client: &Client) -> Self {
+        Self::new(&client.hwid, &client.name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Login {
+        address: IpAddr,
+        protocol: String,
+        resumed: bool,
+    },
+    /// A banned account or IP tried to connect
+    LoginRejected {
+        address: IpAddr,
+        protocol: String,
+        reason: String,
+    },
+    Logout,
+    Rename {
+        old_username: String,
+        new_username: String,
+    },
+    Kick {
+        reason: String,
+    },
+    Ban {
+        reason: String,
+        until: Option<u64>,
+        address: Option<IpAddr>,
+    },
+    Unban,
+    Mute {
+        reason: String,
+        until: Option<u64>,
+    },
+    Unmute,
+    /// Disconnected by the rate limiter
+    FloodDisconnect,
+    /// Only recorded if a moderator edited the message of someone else
+    EditMessage {
+        id: String,
+        room: String,
+    },
+    /// Only recorded if a moderator deleted the message of someone else
+    DeleteMessage {
+        id: String,
+        room: String,
+    },
+    SetRole {
+        role: Role,
+        room: Option<String>,
+    },
+    ConfigReload {
+        changed: Vec<String>,
+    },
+    ServerStart,
+    ServerStop {
+        reason: String,
+    },
+}
+
+impl AuditEvent {
+    /// The `event` field, e.g. `login_rejected`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Login { .. } => "login",
+            Self::LoginRejected { .. } => "login_rejected",
+            Self::Logout => "logout",
+            Self::Rename { .. } => "rename",
+            Self::Kick { .. } => "kick",
+            Self::Ban { .. } => "ban",
+            Self::Unban => "unban",
+            Self::Mute { .. } => "mute",
+            Self::Unmute => "unmute",
+            Self::FloodDisconnect => "flood_disconnect",
+            Self::EditMessage { .. } => "edit_message",
+            Self::DeleteMessage { .. } => "delete_message",
+            Self::SetRole { .. } => "set_role",
+            Self::ConfigReload { .. } => "config_reload",
+            Self::ServerStart => "server_start",
+            Self::ServerStop { .. } => "server_stop",
+        }
+    }
+}
+
+/// A line of the audit log, without its `hash`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Starts at 1, so removed entries leave a gap
+    pub seq: u64,
+    /// Unix timestamp
+    pub time: u64,
+    /// Who did it, `None` if the server did it on its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<Account>,
+    /// Who it was done to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Account>,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+    /// The hash of the previous entry
+    pub prev: String,
+}
+
+impl Entry {
+    /// Whether the username or HWID is the actor or the target.
+    pub fn involves(&self, user: &str) -> bool {
+        [&self.actor, &self.target]
+            .into_iter()
+            .flatten()
+            .any(|account| account.is(user))
+    }
+}
+
+/// The hash of an entry, the keys of a JSON object are always serialized in order.
+fn digest(entry: &serde_json::Value) -> String {
+    hex::encode(Sha256::digest(entry.to_string().as_bytes()))
+}
+
+struct Chain {
+    file: File,
+    seq: u64,
+    hash: String,
+}
+
+/// Appends hash-chained JSON lines, every entry contains the hash of the one before it.
+pub struct AuditLog {
+    chain: Option<Mutex<Chain>>,
+}
+
+impl AuditLog {
+    /// Continues the chain of an existing file, a damaged last entry prevents the start.
+    pub async fn open(config: &AuditConfig) -> io::Result<Self> {
+        if !config.enabled {
+            return Ok(Self { chain: None });
+        }
+
+        let (seq, hash) = match tokio::fs::read_to_string(&config.file).await {
+            Ok(contents) => last_link(&contents).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "The last entry of {} is damaged, see `chat_server audit verify`",
+                        config.file.display()
+                    ),
+                )
+            })?,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => (0, GENESIS.to_string()),
+            Err(why) => return Err(why),
+        };
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.file)
+            .await?;
+        Ok(Self {
+            chain: Some(Mutex::new(Chain { file, seq, hash })),
+        })
+    }
+
+    pub async fn record(&self, actor: Option<Account>, target: Option<Account>, event: AuditEvent) {
+        let Some(chain) = &self.chain else {
+            return;
+        };
+        let mut chain = chain.lock().await;
+
+        let entry = Entry {
+            seq: chain.seq + 1,
+            time: unix_timestamp(),
+            actor,
+            target,
+            event,
+            prev: chain.hash.clone(),
+        };
+        let mut value = match serde_json::to_value(&entry) {
+            Ok(value) => value,
+            Err(why) => {
+                tracing::error!("Unable to serialize audit entry! {why}");
+                return;
+            }
+        };
+        let hash = digest(&value);
+        value["hash"] = hash.clone().into();
+
+        let line = format!("{value}\n");
+        let written = match chain.file.write_all(line.as_bytes()).await {
+            Ok(()) => chain.file.flush().await,
+            Err(why) => Err(why),
+        };
+        if let Err(why) = written {
+            tracing::error!("Unable to write audit entry {}! {why}", entry.seq);
+            return;
+        }
+
+        chain.seq = entry.seq;
+        chain.hash = hash;
+    }
+}
+
+/// The `seq` and `hash` of the last entry.
+fn last_link(contents: &str) -> Option<(u64, String)> {
+    let Some(line) = contents.lines().rev().find(|line| !line.trim().is_empty()) else {
+        return Some((0, GENESIS.to_string()));
+    };
+
+    let value = serde_json::from_str::<serde_json::Value>(line).ok()?;
+    let seq = value.get("seq")?.as_u64()?;
+    let hash = value.get("hash")?.as_str()?;
+    Some((seq, hash.to_string()))
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ChainError {
+    #[error("line {line}: not an audit entry, {reason}")]
+    Invalid { line: usize, reason: String },
+    #[error("line {line}: the entry was modified")]
+    Modified { line: usize },
+    #[error("line {line}: expected entry {expected}, found {found}")]
+    Sequence {
+        line: usize,
+        expected: u64,
+        found: u64,
+    },
+    #[error("line {line}: does not follow the entry before it")]
+    Unlinked { line: usize },
+}
+
+/// Checks every entry against its hash and the entry before it, returns the hash of the last one.
+/// Entries removed from the end can only be noticed by comparing that hash with an earlier copy.
+pub fn verify(contents: &str) -> Result<(Vec<Entry>, String), ChainError> {
+    let mut entries = Vec::new();
+    let mut previous = GENESIS.to_string();
+
+    for (index, text) in contents.lines().enumerate() {
+        let line = index + 1;
+        let invalid = |reason: String| ChainError::Invalid { line, reason };
+
+        let mut value = serde_json::from_str::<serde_json::Value>(text)
+            .map_err(|why| invalid(why.to_string()))?;
+        let hash = value
+            .as_object_mut()
+            .and_then(|object| object.remove("hash"))
+            .and_then(|hash| hash.as_str().map(str::to_string))
+            .ok_or_else(|| invalid("the hash is missing".to_string()))?;
+        if digest(&value) != hash {
+            return Err(ChainError::Modified { line });
+        }
+
+        let entry =
+            serde_json::from_value::<Entry>(value).map_err(|why| invalid(why.to_string()))?;
+        let expected = entries.len() as u64 + 1;
+        if entry.seq != expected {
+            return Err(ChainError::Sequence {
+                line,
+                expected,
+                found: entry.seq,
+            });
+        }
+        if entry.prev != previous {
+            return Err(ChainError::Unlinked { line });
+        }
+
+        previous = hash;
+        entries.push(entry);
+    }
+
+    Ok((entries, previous))
+}
+
+/// Which entries `chat_server audit query` prints, every given filter has to match.
+#[derive(Debug, Default, Clone)]
+pub struct Query {
+    pub user: Option<String>,
+    pub events: Vec<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &Entry) -> bool {
+        self.user.as_ref().is_none_or(|user| entry.involves(user))
+            && (self.events.is_empty() || self.events.iter().any(|e| e == entry.event.name()))
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time <= until)
+    }
+}
+
+/// The `audit.file` of the config at `config_path`, the audit subcommands work even if the rest
+/// of the config is invalid. Falls back to the default if the file doesn't set it.
+pub async fn configured_file(config_path: &Path) -> PathBuf {
+    let contents = tokio::fs::read_to_string(config_path)
+        .await
+        .unwrap_or_default();
+    contents
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|config| {
+            config
+                .get("audit")?
+                .get("file")?
+                .as_str()
+                .map(PathBuf::from)
+        })
+        .unwrap_or_else(|| AuditConfig::default().file)
+}
+
+/// Prints the result of `chat_server audit verify`, returns the exit code.
+pub async fn run_verify(file: &Path) -> i32 {
+    let contents = match tokio::fs::read_to_string(file).await {
+        Ok(contents) => contents,
+        Err(why) => {
+            eprintln!("Unable to read {}! {why}", file.display());
+            return 1;
+        }
+    };
+
+    match verify(&contents) {
+        Ok((entries, last_hash)) => {
+            println!("{} entries, the chain is intact", entries.len());
+            println!("Last hash: {last_hash}");
+            0
+        }
+        Err(why) => {
+            eprintln!("{}: {why}", file.display());
+            1
+        }
+    }
+}
+
+/// Prints the matching lines of the file as they are, returns the exit code.
+pub async fn run_query(file: &Path, query: &Query) -> i32 {
+    let contents = match tokio::fs::read_to_string(file).await {
+        Ok(contents) => contents,
+        Err(why) => {
+            eprintln!("Unable to read {}! {why}", file.display());
+            return 1;
+        }
+    };
+
+    for (index, line) in contents.lines().enumerate() {
+        match serde_json::from_str::<Entry>(line) {
+            Ok(entry) if query.matches(&entry) => println!("{line}"),
+            Ok(_) => {}
+            Err(why) => eprintln!("{}:{}: skipped, {why}", file.display(), index + 1),
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{configured_file, verify, Account, AuditEvent, AuditLog, ChainError, Query};
+    use crate::types::AuditConfig;
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_audit_chain() {
+        let directory = tempfile::tempdir().unwrap();
+        let file = directory.path().join("audit.jsonl");
+        let config = AuditConfig {
+            enabled: true,
+            file: file.clone(),
+        };
+
+        let log = AuditLog::open(&config).await.unwrap();
+        let alice = Account::new("hwid-a", "alice");
+        let login = AuditEvent::Login {
+            address: "127.0.0.1".parse().unwrap(),
+            protocol: "native".to_string(),
+            resumed: false,
+        };
+        log.record(Some(alice.clone()), None, login).await;
+        let kick = AuditEvent::Kick {
+            reason: "spam".to_string(),
+        };
+        log.record(Some(alice.clone()), Some(Account::named("bob")), kick)
+            .await;
+        drop(log);
+
+        // Reopening continues the chain
+        let log = AuditLog::open(&config).await.unwrap();
+        log.record(None, Some(alice), AuditEvent::Logout).await;
+
+        let contents = std::fs::read_to_string(&file).unwrap();
+        let (entries, _) = verify(&contents).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].seq, 3);
+
+        let query = Query {
+            user: Some("bob".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(entries.iter().filter(|e| query.matches(e)).count(), 1);
+        let query = Query {
+            user: Some("hwid-a".to_string()),
+            events: vec!["login".to_string(), "logout".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(entries.iter().filter(|e| query.matches(e)).count(), 2);
+
+        let tampered = contents.replace("spam", "ham");
+        assert_eq!(verify(&tampered), Err(ChainError::Modified { line: 2 }));
+
+        let lines = contents.lines().collect::<Vec<_>>();
+        let removed = format!("{}\n{}\n", lines[0], lines[2]);
+        assert!(matches!(
+            verify(&removed),
+            Err(ChainError::Sequence { line: 2, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_configured_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = directory.path().join("config.toml");
+        // Invalid otherwise, but the audit log can still be found
+        std::fs::write(
+            &config,
+            "buffer_size = 1\n[audit]\nfile = \"custom.jsonl\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            configured_file(&config).await,
+            PathBuf::from("custom.jsonl")
+        );
+
+        std::fs::write(&config, "buffer_size = 1\n").unwrap();
+        assert_eq!(configured_file(&config).await, AuditConfig::default().file);
+        std::fs::remove_file(&config).unwrap();
+        assert_eq!(configured_file(&config).await, AuditConfig::default().file);
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{audit::Query, types::Config, utils::unix_timestamp};
+use chat_shared::{arguments::DurationArg, config};
+use clap::{Args, Parser, Subcommand};
+use std::{env, net::SocketAddr, path::PathBuf};
+
+/// The chat server. Flags and CHAT_* environment variables take precedence over the config file.
+#[derive(Parser, Debug, Clone)]
+#[command(version, about)]
+pub struct Cli {
+    /// The config file, ./server_config.toml if it exists or else
+    /// $XDG_CONFIG_HOME/rust_chat/server_config.toml. The defaults are used if it does not exist
+    #[arg(long, env = "CHAT_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Write the default config to the config file and exit
+    #[arg(long)]
+    pub write_config: bool,
+
+    /// The address to listen on, e.g. 0.0.0.0:7878
+    #[arg(long, env = "CHAT_ENDPOINT")]
+    pub endpoint: Option<SocketAddr>,
+
+    /// The name of the server, shown to IRC clients
+    #[arg(long, env = "CHAT_NAME")]
+    pub name: Option<String>,
+
+    /// A level like `debug` or filters like `chat_server=trace`, takes precedence over RUST_LOG
+    /// and the `log.level` of the config
+    #[arg(long, env = "CHAT_LOG")]
+    pub log_level: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Inspect the audit log instead of starting the server
+    Audit(AuditArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct AuditArgs {
+    /// The audit log, the `audit.file` of the config by default
+    #[arg(long)]
+    pub file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: AuditCommand,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum AuditCommand {
+    /// Check that no entry was modified or removed, exits with 1 if the chain is broken
+    Verify,
+    /// Print the entries which match all of the given filters
+    Query(QueryArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct QueryArgs {
+    /// The username or HWID of the actor or the target
+    #[arg(long)]
+    pub user: Option<String>,
+
+    /// Events like `login` or `ban`, can be given multiple times
+    #[arg(long)]
+    pub event: Vec<String>,
+
+    /// A unix timestamp, or a duration like `2h` which means that long ago
+    #[arg(long, value_parser = parse_time)]
+    pub since: Option<u64>,
+
+    /// A unix timestamp, or a duration like `2h` which means that long ago
+    #[arg(long, value_parser = parse_time)]
+    pub until: Option<u64>,
+}
+
+impl From<QueryArgs> for Query {
+    fn from(args: QueryArgs) -> Self {
+        Self {
+            user: args.user,
+            events: args.event,
+            since: args.since,
+            until: args.until,
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Result<u64, String> {
+    if let Ok(timestamp) = value.parse::<u64>() {
+        return Ok(timestamp);
+    }
+
+    value
+        .parse::<DurationArg>()
+        .map(|DurationArg(ago)| unix_timestamp().saturating_sub(ago.as_secs()))
+        .map_err(|_| "expected a unix timestamp or a duration like 2h".to_string())
+}
+
+impl Cli {
+    pub fn config_path(&self) -> PathBuf {
+        self.config
+            .clone()
+            .unwrap_or_else(|| config::default_path("server_config.toml"))
+    }
+
+    /// Overrides the settings of the config file.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(endpoint) = self.endpoint {
+            config.endpoint = endpoint;
+        }
+        if let Some(name) = &self.name {
+            config.irc.server_name = name.clone();
+        }
+    }
+
+    /// The filters which override the config.
+    pub fn log_filters(&self) -> Option<String> {
+        self.log_level.clone().or_else(|| env::var("RUST_LOG").ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AuditCommand, Cli, Command};
+    use crate::types::Config;
+    use clap::Parser;
+
+    #[test]
+    fn test_cli_overrides() {
+        let cli = Cli::try_parse_from([
+            "chat_server",
+            "--config",
+            "other.toml",
+            "--endpoint",
+            "0.0.0.0:9000",
+            "--name",
+            "chat.example.com",
+        ])
+        .unwrap();
+
+        let mut config = Config::default();
+        cli.apply(&mut config);
+        assert_eq!(cli.config_path().to_str(), Some("other.toml"));
+        assert_eq!(config.endpoint.port(), 9000);
+        assert_eq!(config.irc.server_name, "chat.example.com");
+
+        assert!(Cli::try_parse_from(["chat_server", "--endpoint", "nowhere"]).is_err());
+    }
+
+    #[test]
+    fn test_audit_command() {
+        let cli = Cli::try_parse_from([
+            "chat_server",
+            "audit",
+            "query",
+            "--user",
+            "alice",
+            "--event",
+            "ban",
+            "--since",
+            "1700000000",
+            "--until",
+            "1h",
+        ])
+        .unwrap();
+        let Some(Command::Audit(args)) = cli.command else {
+            panic!("expected the audit command");
+        };
+        let AuditCommand::Query(query) = args.command else {
+            panic!("expected a query");
+        };
+        assert_eq!(query.user.as_deref(), Some("alice"));
+        assert_eq!(query.event, ["ban"]);
+        assert_eq!(query.since, Some(1_700_000_000));
+        assert!(query.until.is_some_and(|until| until > 1_700_000_000));
+
+        assert!(Cli::try_parse_from(["chat_server", "audit", "query", "--since", "soon"]).is_err());
+    }
+}
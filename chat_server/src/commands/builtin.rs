@@ -0,0 +1,557 @@
+use super::{Arguments, Command, CommandContext, CommandError, CommandRegistry};
+use crate::{
+    event_handler::EventHandler,
+    moderation::{self, Action, Permission},
+    types::Outgoing,
+};
+use async_trait::async_trait;
+use chat_shared::protocols::client::{
+    ChangeUsername, ChatMessage, DeleteMessage, EditMessage, FetchThread,
+};
+
+pub fn register(registry: &mut CommandRegistry) {
+    registry.register(Help);
+    registry.register(Nick);
+    registry.register(Me);
+    registry.register(Who);
+    registry.register(Join);
+    registry.register(Msg);
+    registry.register(Edit);
+    registry.register(Delete);
+    registry.register(Reply);
+    registry.register(Thread);
+    registry.register(Follow);
+    registry.register(Unfollow);
+    registry.register(React);
+    registry.register(Unreact);
+}
+
+pub struct Help;
+
+#[async_trait]
+impl Command for Help {
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/help [command]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists all commands or shows how to use a command"
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let registry = &context.server.commands;
+
+        if let Some(name) = arguments.optional::<String>("command")? {
+            let name = name.trim_start_matches('/');
+            let command = registry
+                .get(name)
+                .ok_or_else(|| CommandError::UnknownCommand(name.to_string()))?;
+
+            let mut help = format!("{} - {}", command.usage(), command.description());
+            if !command.aliases().is_empty() {
+                help.push_str(&format!(" (aliases: /{})", command.aliases().join(", /")));
+            }
+            context.reply(help).await;
+            return Ok(());
+        }
+
+        let moderation = &context.server.moderation;
+        let mut commands = Vec::new();
+        for command in registry.commands() {
+            let permitted = match command.permission() {
+                Some(permission) => {
+                    moderation
+                        .has_permission(&context.invoker, permission)
+                        .await
+                }
+                None => true,
+            };
+            if permitted {
+                commands.push(format!("{} - {}", command.usage(), command.description()));
+            }
+        }
+        commands.sort();
+
+        context
+            .reply(format!("Available commands:\n{}", commands.join("\n")))
+            .await;
+        Ok(())
+    }
+}
+
+pub struct Nick;
+
+#[async_trait]
+impl Command for Nick {
+    fn name(&self) -> &'static str {
+        "nick"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["name"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "/nick <username>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Changes your username"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::ChangeUsername)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let change_username = ChangeUsername {
+            hwid: context.invoker.hwid.clone(),
+            new_username: arguments.required("username")?,
+        };
+
+        EventHandler::handle_change_username(change_username, context.server).await
+    }
+}
+
+pub struct Me;
+
+#[async_trait]
+impl Command for Me {
+    fn name(&self) -> &'static str {
+        "me"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/me <action>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Describes what you are doing, e.g. /me waves"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::SendMessages)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let content = arguments.rest("action")?;
+        EventHandler::check_length(context.server, &content)?;
+        let Some(room) = context.invoker.room.clone() else {
+            return Err(CommandError::Failed("You are not in a room".to_string()));
+        };
+
+        let event = Outgoing::Action {
+            username: context.invoker.name.clone(),
+            room: room.clone(),
+            content,
+        };
+        EventHandler::broadcast_room(
+            &context.server.connected_clients,
+            &room,
+            Some(&context.invoker.hwid),
+            event,
+        )
+        .await;
+        Ok(())
+    }
+}
+
+pub struct Who;
+
+#[async_trait]
+impl Command for Who {
+    fn name(&self) -> &'static str {
+        "who"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/who [room]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lists the users in your or another room"
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let room = match arguments.optional::<String>("room")? {
+            Some(room) => room.trim_start_matches('#').to_string(),
+            None => context
+                .invoker
+                .room
+                .clone()
+                .ok_or_else(|| CommandError::Failed("You are not in a room".to_string()))?,
+        };
+
+        let mut users = context
+            .server
+            .connected_clients
+            .lock()
+            .await
+            .values()
+            .filter(|(_, c)| c.room.as_deref() == Some(room.as_str()))
+            .map(|(_, c)| c.name.clone())
+            .collect::<Vec<_>>();
+        users.sort();
+
+        context
+            .reply(format!(
+                "{} user(s) in #{room}: {}",
+                users.len(),
+                users.join(", ")
+            ))
+            .await;
+        Ok(())
+    }
+}
+
+pub struct Join;
+
+#[async_trait]
+impl Command for Join {
+    fn name(&self) -> &'static str {
+        "join"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/join <room>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Leaves your current room and joins another one"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::JoinRooms)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let room = arguments.required::<String>("room")?;
+
+        // The client has already been told why it can't join
+        if !moderation::throttle(context.server, &context.invoker.hwid, Action::Join).await {
+            return Ok(());
+        }
+
+        EventHandler::join_room(
+            &context.server.connected_clients,
+            &context.invoker.hwid,
+            room.trim_start_matches('#'),
+        )
+        .await
+    }
+}
+
+pub struct Msg;
+
+#[async_trait]
+impl Command for Msg {
+    fn name(&self) -> &'static str {
+        "msg"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["dm", "whisper"]
+    }
+
+    fn usage(&self) -> &'static str {
+        "/msg <username> <message>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Sends a private message to another user"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::DirectMessages)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let recipient = arguments.required::<String>("username")?;
+        let content = arguments.rest("message")?;
+
+        EventHandler::direct_message(context.server, &context.invoker.hwid, &recipient, content)
+            .await
+    }
+}
+
+pub struct Edit;
+
+#[async_trait]
+impl Command for Edit {
+    fn name(&self) -> &'static str {
+        "edit"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/edit <message id> <message>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Replaces the content of your message, moderators can edit any message"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::SendMessages)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let edit_message = EditMessage {
+            hwid: context.invoker.hwid.clone(),
+            id: arguments.required("message id")?,
+            content: arguments.rest("message")?,
+        };
+
+        EventHandler::handle_edit_message(edit_message, context.server).await
+    }
+}
+
+pub struct Delete;
+
+#[async_trait]
+impl Command for Delete {
+    fn name(&self) -> &'static str {
+        "delete"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/delete <message id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Deletes your message for everyone, moderators can delete any message"
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let delete_message = DeleteMessage {
+            hwid: context.invoker.hwid.clone(),
+            id: arguments.required("message id")?,
+        };
+
+        EventHandler::handle_delete_message(delete_message, context.server).await
+    }
+}
+
+pub struct Reply;
+
+#[async_trait]
+impl Command for Reply {
+    fn name(&self) -> &'static str {
+        "reply"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/reply <message id> <message>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Replies to a message, in the room it was sent in"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::SendMessages)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let reply_to = arguments.required("message id")?;
+        let content = arguments.rest("message")?;
+        // Otherwise the reply would be executed as a command
+        let content = match content.starts_with('/') {
+            true => format!("/{content}"),
+            false => content,
+        };
+
+        let message = ChatMessage {
+            hwid: context.invoker.hwid.clone(),
+            content,
+            reply_to,
+        };
+        EventHandler::handle_send_message(message, context.server).await;
+        Ok(())
+    }
+}
+
+pub struct Thread;
+
+#[async_trait]
+impl Command for Thread {
+    fn name(&self) -> &'static str {
+        "thread"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/thread <message id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows the thread a message belongs to"
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let fetch_thread = FetchThread {
+            hwid: context.invoker.hwid.clone(),
+            id: arguments.required("message id")?,
+        };
+
+        EventHandler::handle_fetch_thread(fetch_thread, context.server).await
+    }
+}
+
+pub struct Follow;
+
+#[async_trait]
+impl Command for Follow {
+    fn name(&self) -> &'static str {
+        "follow"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/follow <message id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Receive the replies to a thread while in other rooms, until you disconnect"
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let id = arguments.required::<String>("message id")?;
+        EventHandler::handle_follow_thread(&context.invoker.hwid, &id, true, context.server).await
+    }
+}
+
+pub struct Unfollow;
+
+#[async_trait]
+impl Command for Unfollow {
+    fn name(&self) -> &'static str {
+        "unfollow"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/unfollow <message id>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Stops following a thread"
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let id = arguments.required::<String>("message id")?;
+        EventHandler::handle_follow_thread(&context.invoker.hwid, &id, false, context.server).await
+    }
+}
+
+pub struct React;
+
+#[async_trait]
+impl Command for React {
+    fn name(&self) -> &'static str {
+        "react"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/react <message id> <emoji or :shortcode:>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Reacts to a message"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::SendMessages)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let id = arguments.required::<String>("message id")?;
+        let reaction = arguments.required::<String>("reaction")?;
+        let hwid = &context.invoker.hwid;
+        EventHandler::handle_reaction(hwid, &id, &reaction, true, context.server).await
+    }
+}
+
+pub struct Unreact;
+
+#[async_trait]
+impl Command for Unreact {
+    fn name(&self) -> &'static str {
+        "unreact"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/unreact <message id> <emoji or :shortcode:>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Takes a reaction back"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::SendMessages)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let id = arguments.required::<String>("message id")?;
+        let reaction = arguments.required::<String>("reaction")?;
+        let hwid = &context.invoker.hwid;
+        EventHandler::handle_reaction(hwid, &id, &reaction, false, context.server).await
+    }
+}
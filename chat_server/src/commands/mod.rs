@@ -0,0 +1,294 @@
+use crate::{
+    event_handler::EventHandler,
+    moderation::Permission,
+    types::{Client, Outgoing, ServerContext},
+};
+use async_trait::async_trait;
+use chat_shared::arguments::ArgumentError;
+use std::{collections::HashMap, sync::Arc};
+
+pub use chat_shared::arguments::{Arguments, DurationArg};
+
+pub mod builtin;
+pub mod moderation;
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum CommandError {
+    #[error("Unknown command /{0}, see /help for a list of commands")]
+    UnknownCommand(String),
+    #[error(transparent)]
+    Argument(#[from] ArgumentError),
+    #[error("You are not allowed to use this command")]
+    PermissionDenied,
+    #[error("{0}")]
+    Failed(String),
+}
+
+/// Messages starting with a single `/` are commands, `//` escapes the slash.
+pub fn is_command(content: &str) -> bool {
+    content.starts_with('/') && !content.starts_with("//")
+}
+
+/// Everything a command knows about the client which invoked it.
+pub struct CommandContext<'a> {
+    pub server: &'a ServerContext,
+    pub invoker: Client,
+}
+
+impl CommandContext<'_> {
+    /// Sends a system message to the client which invoked the command.
+    pub async fn reply(&self, content: impl Into<String>) {
+        let event = Outgoing::System {
+            content: content.into(),
+        };
+        EventHandler::send_to(&self.server.connected_clients, &self.invoker.hwid, event).await;
+    }
+
+    /// Looks up a connected client by its username.
+    pub async fn find_client(&self, username: &str) -> Result<Client, CommandError> {
+        let lock = self.server.connected_clients.lock().await;
+        lock.values()
+            .find(|(_, c)| c.name.eq(username))
+            .map(|(_, c)| c.clone())
+            .ok_or_else(|| CommandError::Failed(format!("{username} is not online")))
+    }
+}
+
+/// A command which can be invoked by sending a message starting with `/`.
+///
+/// Implement this and add it to the `CommandRegistry` to provide custom commands, then start the
+/// server with `Server::with_commands`.
+#[async_trait]
+pub trait Command: Send + Sync {
+    /// The name the command is invoked with, without the leading `/`
+    fn name(&self) -> &'static str;
+
+    /// Other names the command can be invoked with
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Describes the arguments, e.g. `/msg <username> <message>`
+    fn usage(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// The permission the invoker needs in its current room, respectively globally for global
+    /// permissions, checked before executing
+    fn permission(&self) -> Option<Permission> {
+        None
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        arguments: Arguments,
+    ) -> Result<(), CommandError>;
+}
+
+#[derive(Default)]
+pub struct CommandRegistry {
+    commands: Vec<Arc<dyn Command>>,
+    /// Maps names and aliases to their index in `commands`
+    lookup: HashMap<String, usize>,
+}
+
+impl CommandRegistry {
+    /// A registry containing all built-in commands.
+    pub fn with_builtin() -> Self {
+        let mut registry = Self::default();
+        builtin::register(&mut registry);
+        moderation::register(&mut registry);
+        registry
+    }
+
+    /// Adds a command, replacing any command which uses the same name or alias. Replaced
+    /// commands can't be invoked by their other aliases either.
+    pub fn register(&mut self, command: impl Command + 'static) {
+        let index = self.commands.len();
+        let names = std::iter::once(command.name())
+            .chain(command.aliases().iter().copied())
+            .map(str::to_lowercase)
+            .collect::<Vec<_>>();
+
+        let replaced = names
+            .iter()
+            .filter_map(|name| self.lookup.get(name).copied())
+            .collect::<Vec<_>>();
+        self.lookup.retain(|_, index| !replaced.contains(index));
+        for name in names {
+            self.lookup.insert(name, index);
+        }
+        self.commands.push(Arc::new(command));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Command>> {
+        self.lookup
+            .get(&name.to_lowercase())
+            .map(|index| &self.commands[*index])
+    }
+
+    /// All commands which are still reachable by their name.
+    pub fn commands(&self) -> impl Iterator<Item = &Arc<dyn Command>> {
+        self.commands
+            .iter()
+            .enumerate()
+            .filter(|(index, command)| {
+                self.lookup.get(&command.name().to_lowercase()) == Some(index)
+            })
+            .map(|(_, command)| command)
+    }
+
+    /// Executes `input` (the message without its leading `/`) for the client with the HWID.
+    pub async fn dispatch(&self, server: &ServerContext, hwid: &str, input: &str) {
+        let Some((_, invoker)) = server.connected_clients.lock().await.get(hwid).cloned() else {
+            return;
+        };
+        let context = CommandContext { server, invoker };
+
+        let (name, arguments) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let result = match self.get(name) {
+            Some(command) => {
+                let permitted = match command.permission() {
+                    Some(permission) => server.moderation.check(&context.invoker, permission).await,
+                    None => Ok(()),
+                };
+
+                match permitted {
+                    Ok(()) => {
+                        tracing::info!("{} used /{}", context.invoker.name, command.name());
+                        command.execute(&context, Arguments::new(arguments)).await
+                    }
+                    Err(why) => Err(why),
+                }
+            }
+            None => Err(CommandError::UnknownCommand(name.to_string())),
+        };
+
+        match result {
+            Ok(()) => {}
+            Err(why @ CommandError::Argument(_)) => {
+                let usage = self.get(name).map(|c| c.usage()).unwrap_or_default();
+                context.reply(format!("{why}. Usage: {usage}")).await;
+            }
+            Err(why) => context.reply(why.to_string()).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arguments, Command, CommandContext, CommandError, CommandRegistry};
+    use crate::{
+        server::Server,
+        types::{Client, Config, Outgoing},
+    };
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use tokio::sync::mpsc;
+
+    struct Greet;
+
+    #[async_trait]
+    impl Command for Greet {
+        fn name(&self) -> &'static str {
+            "greet"
+        }
+
+        fn usage(&self) -> &'static str {
+            "/greet <username>"
+        }
+
+        fn description(&self) -> &'static str {
+            "Greets someone"
+        }
+
+        async fn execute(
+            &self,
+            context: &CommandContext<'_>,
+            mut arguments: Arguments,
+        ) -> Result<(), CommandError> {
+            let username = arguments.required::<String>("username")?;
+            context.reply(format!("Hello {username}")).await;
+            Ok(())
+        }
+    }
+
+    struct Hello;
+
+    #[async_trait]
+    impl Command for Hello {
+        fn name(&self) -> &'static str {
+            "Hello"
+        }
+
+        fn aliases(&self) -> &'static [&'static str] {
+            &["hi", "greet"]
+        }
+
+        fn usage(&self) -> &'static str {
+            "/hello"
+        }
+
+        fn description(&self) -> &'static str {
+            "Says hello"
+        }
+
+        async fn execute(
+            &self,
+            context: &CommandContext<'_>,
+            _arguments: Arguments,
+        ) -> Result<(), CommandError> {
+            context.reply("Hello").await;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_replace_command() {
+        let mut commands = CommandRegistry::default();
+        commands.register(Greet);
+        commands.register(Hello);
+        let names = commands.commands().map(|c| c.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["Hello"]);
+        assert!(commands.get("HI").is_some());
+
+        // Replacing the alias replaces the whole command
+        commands.register(Greet);
+        let names = commands.commands().map(|c| c.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["greet"]);
+        assert!(commands.get("hi").is_none() && commands.get("hello").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_custom_command() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = Config::in_directory(directory.path());
+
+        let mut commands = CommandRegistry::with_builtin();
+        commands.register(Greet);
+        assert!(commands.commands().any(|c| c.name() == "greet"));
+        let server = Server::with_commands(config, commands).await.unwrap();
+
+        let client = Client {
+            name: "Alice".to_string(),
+            hwid: "hwid".to_string(),
+            session_token: "token".to_string(),
+            room: Some("general".to_string()),
+            address: "127.0.0.1".parse().unwrap(),
+        };
+        let (outbox, mut inbox) = mpsc::unbounded_channel();
+        let context = &server.context;
+        *context.connected_clients.lock().await =
+            HashMap::from([(client.hwid.clone(), (outbox, client))]);
+
+        context
+            .commands
+            .dispatch(context, "hwid", "greet Bob")
+            .await;
+        let Some(Outgoing::System { content }) = inbox.recv().await else {
+            panic!("Expected a reply");
+        };
+        assert_eq!(content, "Hello Bob");
+    }
+}
@@ -0,0 +1,405 @@
+use super::{Arguments, Command, CommandContext, CommandError, CommandRegistry, DurationArg};
+use crate::{
+    audit::{Account, AuditEvent},
+    event_handler::EventHandler,
+    moderation::{Permission, Role, Sanction},
+    types::{Client, Outgoing},
+    utils::expires_at,
+};
+use async_trait::async_trait;
+
+pub fn register(registry: &mut CommandRegistry) {
+    registry.register(Kick);
+    registry.register(Ban);
+    registry.register(Unban);
+    registry.register(Mute);
+    registry.register(Unmute);
+    registry.register(SetRole);
+    registry.register(Stats);
+}
+
+/// Looks up the target and makes sure the invoker outranks it.
+async fn find_target(context: &CommandContext<'_>, username: &str) -> Result<Client, CommandError> {
+    let target = context.find_client(username).await?;
+
+    if !context
+        .server
+        .moderation
+        .outranks(&context.invoker, &target)
+        .await
+    {
+        return Err(CommandError::Failed(format!(
+            "{username} has the same or a higher role than you"
+        )));
+    }
+
+    Ok(target)
+}
+
+/// Records the command in the audit log, the invoker is the actor.
+async fn audit(context: &CommandContext<'_>, target: Option<Account>, event: AuditEvent) {
+    let actor = Some(Account::from(&context.invoker));
+    context.server.audit.record(actor, target, event).await;
+}
+
+fn sanction(
+    context: &CommandContext<'_>,
+    target: &Client,
+    duration: Option<DurationArg>,
+    reason: String,
+) -> Sanction {
+    Sanction {
+        username: target.name.clone(),
+        reason,
+        issued_by: context.invoker.name.clone(),
+        until: duration.map(|DurationArg(d)| expires_at(d)),
+    }
+}
+
+pub struct Kick;
+
+#[async_trait]
+impl Command for Kick {
+    fn name(&self) -> &'static str {
+        "kick"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/kick <username> [reason]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Disconnects a user"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::Kick)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let target = find_target(context, &arguments.required::<String>("username")?).await?;
+        let reason = arguments.remaining();
+
+        tracing::info!(
+            "{} kicked {} ({})",
+            context.invoker.name,
+            target.name,
+            reason
+        );
+        let message = format!("You were kicked by {}: {reason}", context.invoker.name);
+        EventHandler::disconnect(&context.server.connected_clients, &target.hwid, message).await;
+        audit(
+            context,
+            Some(Account::from(&target)),
+            AuditEvent::Kick { reason },
+        )
+        .await;
+        context.reply(format!("Kicked {}", target.name)).await;
+        Ok(())
+    }
+}
+
+pub struct Ban;
+
+#[async_trait]
+impl Command for Ban {
+    fn name(&self) -> &'static str {
+        "ban"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/ban <username> [duration] [reason]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Bans the account and IP of a user, permanently if no duration is given"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::Ban)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let target = find_target(context, &arguments.required::<String>("username")?).await?;
+        let duration = arguments.try_optional::<DurationArg>();
+        let ban = sanction(context, &target, duration, arguments.remaining());
+
+        tracing::info!(
+            "{} banned {} {}",
+            context.invoker.name,
+            target.name,
+            ban.describe()
+        );
+        let message = format!(
+            "You were banned by {} {}",
+            context.invoker.name,
+            ban.describe()
+        );
+        let reply = format!("Banned {} {}", target.name, ban.describe());
+        let event = AuditEvent::Ban {
+            reason: ban.reason.clone(),
+            until: ban.until,
+            address: Some(target.address),
+        };
+        context
+            .server
+            .moderation
+            .ban(&target.hwid, Some(target.address), ban)
+            .await;
+
+        EventHandler::disconnect(&context.server.connected_clients, &target.hwid, message).await;
+        audit(context, Some(Account::from(&target)), event).await;
+        context.reply(reply).await;
+        Ok(())
+    }
+}
+
+pub struct Unban;
+
+#[async_trait]
+impl Command for Unban {
+    fn name(&self) -> &'static str {
+        "unban"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/unban <username>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Lifts all bans of a user"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::Ban)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let username = arguments.required::<String>("username")?;
+
+        if !context.server.moderation.unban(&username).await {
+            return Err(CommandError::Failed(format!("{username} is not banned")));
+        }
+
+        tracing::info!("{} unbanned {}", context.invoker.name, username);
+        audit(context, Some(Account::named(&username)), AuditEvent::Unban).await;
+        context.reply(format!("Unbanned {username}")).await;
+        Ok(())
+    }
+}
+
+pub struct Mute;
+
+#[async_trait]
+impl Command for Mute {
+    fn name(&self) -> &'static str {
+        "mute"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/mute <username> <duration> [reason]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Prevents a user from sending messages for a while"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::Mute)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let target = find_target(context, &arguments.required::<String>("username")?).await?;
+        let duration = arguments.required::<DurationArg>("duration")?;
+        let mute = sanction(context, &target, Some(duration), arguments.remaining());
+
+        tracing::info!(
+            "{} muted {} {}",
+            context.invoker.name,
+            target.name,
+            mute.describe()
+        );
+        let message = format!(
+            "You were muted by {} {}",
+            context.invoker.name,
+            mute.describe()
+        );
+        let reply = format!("Muted {} {}", target.name, mute.describe());
+        let event = AuditEvent::Mute {
+            reason: mute.reason.clone(),
+            until: mute.until,
+        };
+        context.server.moderation.mute(&target.hwid, mute).await;
+        audit(context, Some(Account::from(&target)), event).await;
+
+        let event = Outgoing::System { content: message };
+        EventHandler::send_to(&context.server.connected_clients, &target.hwid, event).await;
+        context.reply(reply).await;
+        Ok(())
+    }
+}
+
+pub struct Unmute;
+
+#[async_trait]
+impl Command for Unmute {
+    fn name(&self) -> &'static str {
+        "unmute"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/unmute <username>"
+    }
+
+    fn description(&self) -> &'static str {
+        "Allows a muted user to send messages again"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::Mute)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let target = find_target(context, &arguments.required::<String>("username")?).await?;
+
+        if !context.server.moderation.unmute(&target.hwid).await {
+            return Err(CommandError::Failed(format!(
+                "{} is not muted",
+                target.name
+            )));
+        }
+
+        tracing::info!("{} unmuted {}", context.invoker.name, target.name);
+        audit(context, Some(Account::from(&target)), AuditEvent::Unmute).await;
+        context.reply(format!("Unmuted {}", target.name)).await;
+        Ok(())
+    }
+}
+
+pub struct SetRole;
+
+#[async_trait]
+impl Command for SetRole {
+    fn name(&self) -> &'static str {
+        "role"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/role <username> <role> [room]"
+    }
+
+    fn description(&self) -> &'static str {
+        "Assigns a role to a user, globally or only in a room"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::ManageRoles)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        mut arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let target = find_target(context, &arguments.required::<String>("username")?).await?;
+        let role = arguments.required::<Role>("role")?;
+        let room = arguments
+            .optional::<String>("room")?
+            .map(|room| room.trim_start_matches('#').to_string());
+
+        let moderation = &context.server.moderation;
+        let own_role = moderation.role_of(&context.invoker.hwid, None).await;
+        if role >= own_role && own_role != Role::Owner {
+            return Err(CommandError::Failed(format!(
+                "You can only assign roles below {own_role}"
+            )));
+        }
+
+        moderation
+            .set_role(&target.hwid, room.as_deref(), role)
+            .await;
+
+        let event = AuditEvent::SetRole {
+            role,
+            room: room.clone(),
+        };
+        audit(context, Some(Account::from(&target)), event).await;
+
+        let scope = room.map_or("globally".to_string(), |room| format!("in #{room}"));
+        tracing::info!(
+            "{} made {} {} {}",
+            context.invoker.name,
+            target.name,
+            role,
+            scope
+        );
+
+        let event = Outgoing::System {
+            content: format!("{} made you {role} {scope}", context.invoker.name),
+        };
+        EventHandler::send_to(&context.server.connected_clients, &target.hwid, event).await;
+        context
+            .reply(format!("{} is now {role} {scope}", target.name))
+            .await;
+        Ok(())
+    }
+}
+
+pub struct Stats;
+
+#[async_trait]
+impl Command for Stats {
+    fn name(&self) -> &'static str {
+        "stats"
+    }
+
+    fn usage(&self) -> &'static str {
+        "/stats"
+    }
+
+    fn description(&self) -> &'static str {
+        "Shows how many connections are open and how many were rejected"
+    }
+
+    fn permission(&self) -> Option<Permission> {
+        Some(Permission::ViewStats)
+    }
+
+    async fn execute(
+        &self,
+        context: &CommandContext<'_>,
+        _arguments: Arguments,
+    ) -> Result<(), CommandError> {
+        let limiter = &context.server.connection_limiter;
+        let clients = context.server.connected_clients.lock().await.len();
+
+        context
+            .reply(format!(
+                "{} open connection(s), {clients} authenticated client(s)\n{}",
+                limiter.open_connections(),
+                limiter.metrics.summary()
+            ))
+            .await;
+        Ok(())
+    }
+}
@@ -1,81 +1,1135 @@
-use crate::{types::Client, utils::write_to_stream};
+use crate::{
+    audit::{Account, AuditEvent},
+    commands::CommandError,
+    files::Written,
+    history::{Reacted, StoredMessage, MAX_REACTIONS},
+    mailbox::{Pending, ReadMarker},
+    mentions::{self, Mention},
+    metrics::Metrics,
+    moderation::Permission,
+    threads::MAX_FOLLOWED_THREADS,
+    types::{Client, ClientList, Outbox, Outgoing, ServerContext},
+    utils::{is_valid_reaction, is_valid_room_name, is_valid_username, unix_timestamp},
+};
 use chat_shared::{
-    error::WriteToStreamError,
-    protocols::{
-        client::{ChangeUsername, ChatMessage, ClientMessageType, RequestAuthentication},
-        server::BroadcastMessage,
+    protocols::client::{
+        ChangeUsername, ChatMessage, ClientMessageType, DeleteMessage, DownloadChunk, EditMessage,
+        FetchThread, MarkRead, RequestAuthentication, ResumeSession, StartUpload, UploadChunk,
     },
     types::Deserialize,
+    utils::{chunk_size, max_content_length, read_frame},
 };
-use std::{collections::HashMap, io::Read, net::TcpStream, process, sync::Arc};
-use tokio::sync::Mutex;
+use std::collections::HashMap;
+use tokio::{io::AsyncRead, time::Instant};
 
 pub struct EventHandler;
 
 impl EventHandler {
-    pub async fn handle_send_message(
-        chat_message: ChatMessage,
-        clients: &Arc<Mutex<HashMap<String, (TcpStream, Client)>>>,
-    ) -> Result<(), WriteToStreamError> {
+    pub async fn handle_send_message(chat_message: ChatMessage, context: &ServerContext) {
+        let content = match chat_message.content.strip_prefix('/') {
+            // A double slash sends the message with a single leading slash
+            Some(escaped) if escaped.starts_with('/') => escaped.to_string(),
+            Some(command) => {
+                return context
+                    .commands
+                    .dispatch(context, &chat_message.hwid, command)
+                    .await;
+            }
+            None => chat_message.content,
+        };
+
+        let clients = &context.connected_clients;
+        if let Err(why) = Self::check_length(context, &content) {
+            let event = Outgoing::System {
+                content: why.to_string(),
+            };
+            Self::send_to(clients, &chat_message.hwid, event).await;
+            return;
+        }
+        let Some((_, sender)) = clients.lock().await.get(&chat_message.hwid).cloned() else {
+            return;
+        };
+
+        // Replies are sent to the room of the thread, which followers might not be in
+        let parent = match chat_message.reply_to.as_str() {
+            "" => Ok(None),
+            id => Self::visible_message(context, &sender, id).await.map(Some),
+        };
+        // Posting into another room needs the same permissions as sending while being in it
+        let parent = match parent {
+            Ok(Some(parent)) if sender.room.as_ref() != Some(&parent.room) => context
+                .moderation
+                .check_in(&sender, Some(&parent.room), Permission::SendMessages)
+                .await
+                .map(|()| Some(parent)),
+            parent => parent,
+        };
+        let parent = match parent {
+            Ok(parent) => parent,
+            Err(why) => {
+                let event = Outgoing::System {
+                    content: why.to_string(),
+                };
+                Self::send_to(clients, &chat_message.hwid, event).await;
+                return;
+            }
+        };
+        let username = sender.name;
+        let Some(room) = parent.as_ref().map(|p| p.room.clone()).or(sender.room) else {
+            return;
+        };
+
+        tracing::info!(
+            room,
+            username,
+            content,
+            reply_to = chat_message.reply_to,
+            "Message sent"
+        );
+        let stored = context
+            .history
+            .push(
+                room.clone(),
+                chat_message.hwid.clone(),
+                username,
+                content,
+                parent.as_ref(),
+            )
+            .await;
+        let sent = Outgoing::MessageSent {
+            id: stored.id.clone(),
+            room: stored.room.clone(),
+            sent_at: stored.sent_at,
+        };
+        let message = Outgoing::Message {
+            id: stored.id.clone(),
+            username: stored.username.clone(),
+            room: stored.room.clone(),
+            content: stored.content.clone(),
+            sent_at: stored.sent_at,
+            reply_to: stored.reply_to.clone(),
+        };
+        let started = Instant::now();
+        Self::broadcast_room(clients, &room, Some(&chat_message.hwid), message).await;
+        context.metrics.broadcast(started.elapsed());
+        Self::send_to(clients, &chat_message.hwid, sent).await;
+
+        Self::notify_mentioned(context, &chat_message.hwid, &stored).await;
+        if stored.thread.is_some() {
+            Self::notify_followers(context, &chat_message.hwid, stored).await;
+        }
+    }
+
+    /// Sends a `Mention` to everyone mentioned in the message except its author, wherever they are.
+    /// Users who are mentioned by name while offline get it once they log in again.
+    async fn notify_mentioned(context: &ServerContext, author_hwid: &str, message: &StoredMessage) {
+        let mentions = mentions::parse(&message.content);
+        if mentions.is_empty() {
+            return;
+        }
+
+        let mention = |mention: &Mention, missed| Outgoing::Mention {
+            id: message.id.clone(),
+            room: message.room.clone(),
+            username: message.username.clone(),
+            content: message.content.clone(),
+            mention: mention.text(),
+            sent_at: message.sent_at,
+            missed,
+        };
+        let mut online = Vec::new();
+        for (hwid, (outbox, client)) in context.connected_clients.lock().await.iter() {
+            online.push(client.name.clone());
+            let room = client.room.as_deref();
+            let Some(matched) = mentions
+                .iter()
+                .find(|m| m.matches(&client.name, room, &message.room))
+            else {
+                continue;
+            };
+            if !hwid.eq(author_hwid) {
+                let _ = outbox.send(mention(matched, false));
+            }
+        }
+
+        for matched in &mentions {
+            let usernames = matched.usernames();
+            if usernames
+                .iter()
+                .any(|name| online.iter().any(|o| o.eq(name)))
+            {
+                continue;
+            }
+            for name in usernames {
+                if let Some(hwid) = context.mailbox.account_named(name).await {
+                    let pending = Pending::Mention {
+                        id: message.id.clone(),
+                        room: message.room.clone(),
+                        username: message.username.clone(),
+                        content: message.content.clone(),
+                        mention: matched.text(),
+                        sent_at: message.sent_at,
+                    };
+                    context.mailbox.store(&hwid, pending).await;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Delivers a reply to the followers of its thread which are not in the room of the thread.
+    async fn notify_followers(context: &ServerContext, author_hwid: &str, reply: StoredMessage) {
+        let followers = context.followers.of(reply.thread_id()).await;
+        if followers.is_empty() {
+            return;
+        }
+
+        let event = Self::thread_message(reply.clone());
+        let lock = context.connected_clients.lock().await;
+        for (hwid, (outbox, client)) in lock.iter() {
+            if followers.contains(&client.session_token)
+                && !hwid.eq(author_hwid)
+                && client.room.as_ref() != Some(&reply.room)
+            {
+                let _ = outbox.send(event.clone());
+            }
+        }
+    }
+
+    fn thread_message(message: StoredMessage) -> Outgoing {
+        Outgoing::ThreadMessage {
+            thread: message.thread_id().to_string(),
+            room: message.room,
+            id: message.id,
+            username: message.username,
+            content: message.content,
+            sent_at: message.sent_at,
+            reply_to: message.reply_to,
+        }
+    }
+
+    /// Sends every stored message of the thread the message belongs to.
+    pub async fn handle_fetch_thread(
+        fetch_thread: FetchThread,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        let clients = &context.connected_clients;
+        let Some((_, client)) = clients.lock().await.get(&fetch_thread.hwid).cloned() else {
+            return Ok(());
+        };
+        Self::visible_message(context, &client, &fetch_thread.id).await?;
+        let Some(thread) = context.history.thread(&fetch_thread.id).await else {
+            return Err(Self::unknown_message(&fetch_thread.id));
+        };
+
+        for message in thread {
+            let event = Self::thread_message(message);
+            Self::send_to(clients, &fetch_thread.hwid, event).await;
+        }
+        Ok(())
+    }
+
+    /// Follows the thread the message belongs to, or stops following it. Only threads in rooms
+    /// the client may read can be followed.
+    pub async fn handle_follow_thread(
+        hwid: &str,
+        id: &str,
+        follow: bool,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        let clients = &context.connected_clients;
+        let Some((_, client)) = clients.lock().await.get(hwid).cloned() else {
+            return Ok(());
+        };
+        let message = match follow {
+            true => Self::visible_message(context, &client, id).await?,
+            false => match context.history.get(id).await {
+                Some(message) => message,
+                None => return Err(Self::unknown_message(id)),
+            },
+        };
+        let thread = message.thread_id();
+
+        let content = match follow {
+            true if context
+                .followers
+                .follow(thread, &client.session_token)
+                .await =>
+            {
+                format!("Following the thread of message {thread}")
+            }
+            true => {
+                return Err(CommandError::Failed(format!(
+                    "You can't follow more than {MAX_FOLLOWED_THREADS} threads"
+                )));
+            }
+            false
+                if context
+                    .followers
+                    .unfollow(thread, &client.session_token)
+                    .await =>
+            {
+                format!("Stopped following the thread of message {thread}")
+            }
+            false => {
+                return Err(CommandError::Failed(format!(
+                    "You don't follow the thread of message {thread}"
+                )));
+            }
+        };
+
+        Self::send_to(clients, hwid, Outgoing::System { content }).await;
+        Ok(())
+    }
+
+    /// Replaces the content of a message for everyone in its room.
+    pub async fn handle_edit_message(
+        edit_message: EditMessage,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        if edit_message.content.trim().is_empty() {
+            return Err(CommandError::Failed("A message can't be empty".to_string()));
+        }
+        Self::check_length(context, &edit_message.content)?;
+
+        let (editor, message) =
+            Self::authorize_change(context, &edit_message.hwid, &edit_message.id).await?;
+        let Some(edited) = context
+            .history
+            .edit(&edit_message.id, edit_message.content)
+            .await
+        else {
+            return Err(Self::unknown_message(&edit_message.id));
+        };
+
+        tracing::info!(
+            id = edited.id,
+            room = edited.room,
+            editor = editor.name,
+            "Message edited"
+        );
+        if !editor.hwid.eq(&message.author_hwid) {
+            let author = Account::new(&message.author_hwid, &message.username);
+            let event = AuditEvent::EditMessage {
+                id: edited.id.clone(),
+                room: edited.room.clone(),
+            };
+            context
+                .audit
+                .record(Some(Account::from(&editor)), Some(author), event)
+                .await;
+        }
+
+        let edited_at = edited
+            .edits
+            .last()
+            .map_or(edited.sent_at, |e| e.replaced_at);
+        let event = Outgoing::MessageEdited {
+            id: edited.id,
+            room: edited.room.clone(),
+            content: edited.content,
+            edited_at,
+        };
+        Self::broadcast_room(&context.connected_clients, &edited.room, None, event).await;
+        Ok(())
+    }
+
+    /// Removes a message from the history and from the views of everyone in its room.
+    pub async fn handle_delete_message(
+        delete_message: DeleteMessage,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        let (deleter, message) =
+            Self::authorize_change(context, &delete_message.hwid, &delete_message.id).await?;
+        if context.history.remove(&message.id).await.is_none() {
+            return Err(Self::unknown_message(&message.id));
+        }
+
+        tracing::info!(
+            id = message.id,
+            room = message.room,
+            deleter = deleter.name,
+            "Message deleted"
+        );
+        if !deleter.hwid.eq(&message.author_hwid) {
+            let author = Account::new(&message.author_hwid, &message.username);
+            let event = AuditEvent::DeleteMessage {
+                id: message.id.clone(),
+                room: message.room.clone(),
+            };
+            context
+                .audit
+                .record(Some(Account::from(&deleter)), Some(author), event)
+                .await;
+        }
+
+        let event = Outgoing::MessageDeleted {
+            id: message.id,
+            room: message.room.clone(),
+        };
+        Self::broadcast_room(&context.connected_clients, &message.room, None, event).await;
+        Ok(())
+    }
+
+    /// Adds or removes a reaction, everyone in the room of the message gets the new counts. Only
+    /// messages in rooms the client may read can be reacted to.
+    pub async fn handle_reaction(
+        hwid: &str,
+        id: &str,
+        reaction: &str,
+        add: bool,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        if !is_valid_reaction(reaction) {
+            return Err(CommandError::Failed(format!(
+                "{reaction} is neither an emoji nor a shortcode like :thumbs_up:"
+            )));
+        }
+
+        let Some((_, client)) = context.connected_clients.lock().await.get(hwid).cloned() else {
+            return Ok(());
+        };
+        Self::visible_message(context, &client, id).await?;
+        let message = match context.history.react(id, reaction, hwid, add).await {
+            Some(Reacted::Changed(message)) => message,
+            // Repeating a reaction is no reason to bother the room
+            Some(Reacted::Unchanged) => return Ok(()),
+            Some(Reacted::Full) => {
+                return Err(CommandError::Failed(format!(
+                    "A message can't have more than {MAX_REACTIONS} different reactions"
+                )));
+            }
+            None => return Err(Self::unknown_message(id)),
+        };
+
+        tracing::debug!(id, reaction, add, "Reactions updated");
+        let event = Outgoing::ReactionsUpdated {
+            id: message.id.clone(),
+            room: message.room.clone(),
+            reactions: message.reaction_counts(),
+        };
+        Self::broadcast_room(&context.connected_clients, &message.room, None, event).await;
+        Ok(())
+    }
+
+    /// Authors may change their own messages, moderators every message in the rooms they
+    /// moderate. Returns the client changing the message and the message.
+    async fn authorize_change(
+        context: &ServerContext,
+        hwid: &str,
+        id: &str,
+    ) -> Result<(Client, StoredMessage), CommandError> {
+        let Some((_, client)) = context.connected_clients.lock().await.get(hwid).cloned() else {
+            return Err(CommandError::PermissionDenied);
+        };
+        let Some(message) = context.history.get(id).await else {
+            return Err(Self::unknown_message(id));
+        };
+
+        if !message.author_hwid.eq(hwid) {
+            // The permission is needed in the room the message was sent in
+            let role = context.moderation.role_of(hwid, Some(&message.room)).await;
+            if !role.has(Permission::DeleteMessages) {
+                return Err(CommandError::PermissionDenied);
+            }
+        }
+
+        Ok((client, message))
+    }
+
+    /// Looks up a message in a room the client may read. Messages in other rooms are reported
+    /// as unknown, so walking through the ids reveals nothing about them.
+    async fn visible_message(
+        context: &ServerContext,
+        client: &Client,
+        id: &str,
+    ) -> Result<StoredMessage, CommandError> {
+        match context.history.get(id).await {
+            Some(message) if Self::can_read(context, client, &message.room).await => Ok(message),
+            _ => Err(Self::unknown_message(id)),
+        }
+    }
+
+    /// Clients can read the room they are in and every room they are allowed to join.
+    async fn can_read(context: &ServerContext, client: &Client, room: &str) -> bool {
+        client.room.as_deref() == Some(room)
+            || context
+                .moderation
+                .has_permission_in(client, Some(room), Permission::JoinRooms)
+                .await
+    }
+
+    fn unknown_message(id: &str) -> CommandError {
+        CommandError::Failed(format!("There is no message with the id {id}"))
+    }
+
+    /// Longer messages would not fit into the frames the recipients accept.
+    pub fn check_length(context: &ServerContext, content: &str) -> Result<(), CommandError> {
+        let max_length = max_content_length(context.config().buffer_size);
+        if content.len() > max_length {
+            return Err(CommandError::Failed(format!(
+                "A message can't be longer than {max_length} bytes"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Delivers `event` to every connected client, except the one with the HWID `except`.
+    pub async fn broadcast(clients: &ClientList, except: Option<&str>, event: Outgoing) {
+        let lock = clients.lock().await;
+
+        for (client_hwid, (outbox, _)) in &*lock {
+            if except.is_some_and(|hwid| hwid.eq(client_hwid)) {
+                continue;
+            }
+
+            // A closed outbox belongs to a connection which is about to be removed
+            let _ = outbox.send(event.clone());
+        }
+    }
+
+    /// Like `broadcast`, but only for the clients which are currently in `room`.
+    pub async fn broadcast_room(
+        clients: &ClientList,
+        room: &str,
+        except: Option<&str>,
+        event: Outgoing,
+    ) {
         let lock = clients.lock().await;
 
-        for (client_hwid, (client_stream, c)) in &*lock {
-            if client_hwid.eq(&chat_message.hwid) {
-                log::info!("{} --> {}", c.name, chat_message.content.clone());
+        for (client_hwid, (outbox, client)) in &*lock {
+            if except.is_some_and(|hwid| hwid.eq(client_hwid))
+                || client.room.as_deref() != Some(room)
+            {
                 continue;
             }
 
-            let message = BroadcastMessage {
-                username: c.name.clone(),
-                content: chat_message.content.to_string(),
+            let _ = outbox.send(event.clone());
+        }
+    }
+
+    /// Delivers `event` to a single client, returns `false` if it isn't connected.
+    pub async fn send_to(clients: &ClientList, hwid: &str, event: Outgoing) -> bool {
+        match clients.lock().await.get(hwid) {
+            Some((outbox, _)) => outbox.send(event).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Moves the client into `room`, leaving the room it was in before.
+    pub async fn join_room(
+        clients: &ClientList,
+        hwid: &str,
+        room: &str,
+    ) -> Result<(), CommandError> {
+        if !is_valid_room_name(room) {
+            return Err(CommandError::Failed(format!(
+                "'{room}' is not a valid room name"
+            )));
+        }
+
+        let Some((username, previous_room)) = clients.lock().await.get_mut(hwid).map(|(_, c)| {
+            let previous_room = c.room.replace(room.to_string());
+            (c.name.clone(), previous_room)
+        }) else {
+            return Ok(());
+        };
+
+        if previous_room.as_deref() == Some(room) {
+            return Ok(());
+        }
+
+        if let Some(previous_room) = previous_room {
+            let event = Outgoing::UserLeft {
+                username: username.clone(),
+                room: previous_room.clone(),
             };
-            write_to_stream(client_stream, &message).await?;
+            // The client itself is no longer in the room, so it has to be notified separately
+            Self::broadcast_room(clients, &previous_room, None, event.clone()).await;
+            Self::send_to(clients, hwid, event).await;
+        }
+
+        let event = Outgoing::UserJoined {
+            username,
+            room: room.to_string(),
+        };
+        Self::broadcast_room(clients, room, None, event).await;
+
+        let mut usernames = clients
+            .lock()
+            .await
+            .values()
+            .filter(|(_, c)| c.room.as_deref() == Some(room))
+            .map(|(_, c)| c.name.clone())
+            .collect::<Vec<_>>();
+        usernames.sort();
+        let event = Outgoing::RoomMembers {
+            room: room.to_string(),
+            usernames,
+        };
+        Self::send_to(clients, hwid, event).await;
+        Ok(())
+    }
+
+    /// Removes the client from the room it is in, without joining another one.
+    pub async fn leave_room(clients: &ClientList, hwid: &str) {
+        let Some((username, Some(room))) = clients
+            .lock()
+            .await
+            .get_mut(hwid)
+            .map(|(_, c)| (c.name.clone(), c.room.take()))
+        else {
+            return;
+        };
 
+        let event = Outgoing::UserLeft {
+            username,
+            room: room.clone(),
+        };
+        Self::broadcast_room(clients, &room, None, event.clone()).await;
+        Self::send_to(clients, hwid, event).await;
+    }
+
+    /// Removes a disconnected client and lets the users in its room know about it.
+    ///
+    /// The session token makes sure a client which already reconnected is not removed.
+    /// Removes the client if the session is still current and returns it.
+    pub async fn handle_disconnect(
+        clients: &ClientList,
+        hwid: &str,
+        session_token: &str,
+    ) -> Option<Client> {
+        let mut lock = clients.lock().await;
+        if !lock
+            .get(hwid)
+            .is_some_and(|(_, c)| c.session_token.eq(session_token))
+        {
+            return None;
+        }
+
+        let (_, client) = lock.remove(hwid)?;
+        drop(lock);
+
+        if let Some(room) = &client.room {
+            let event = Outgoing::UserLeft {
+                username: client.name.clone(),
+                room: room.clone(),
+            };
+            Self::broadcast_room(clients, room, None, event).await;
+        }
+        Some(client)
+    }
+
+    /// Closes the connection of a client, e.g. because it was kicked, and returns it.
+    pub async fn disconnect(clients: &ClientList, hwid: &str, reason: String) -> Option<Client> {
+        let session_token = match clients.lock().await.get(hwid) {
+            Some((outbox, client)) => {
+                let _ = outbox.send(Outgoing::Disconnect { reason });
+                client.session_token.clone()
+            }
+            None => return None,
+        };
+
+        Self::handle_disconnect(clients, hwid, &session_token).await
+    }
+
+    /// Recipients who are offline get the message once they log in again.
+    pub async fn direct_message(
+        context: &ServerContext,
+        hwid: &str,
+        recipient: &str,
+        content: String,
+    ) -> Result<(), CommandError> {
+        Self::check_length(context, &content)?;
+        let clients = &context.connected_clients;
+        let lock = clients.lock().await;
+        let Some((_, sender)) = lock.get(hwid) else {
+            return Ok(());
+        };
+        let username = sender.name.clone();
+        let event = Outgoing::DirectMessage {
+            username: username.clone(),
+            content: content.clone(),
+            sent_at: unix_timestamp(),
+        };
+
+        tracing::info!(username, recipient, content, "Direct message sent");
+        if let Some((outbox, _)) = lock.values().find(|(_, c)| c.name.eq(recipient)) {
+            let _ = outbox.send(event);
             return Ok(());
         }
+        drop(lock);
 
-        return Ok(());
+        let pending = Pending::DirectMessage {
+            username,
+            content,
+            sent_at: unix_timestamp(),
+        };
+        let stored = match context.mailbox.account_named(recipient).await {
+            Some(account) => context.mailbox.store(&account, pending).await,
+            None => false,
+        };
+        if !stored {
+            return Err(CommandError::Failed(format!("{recipient} is not online")));
+        }
+
+        let content = format!("{recipient} is offline and gets your message once they are back");
+        Self::send_to(clients, hwid, Outgoing::System { content }).await;
+        Ok(())
     }
 
-    pub async fn handle_auth(mut stream: &TcpStream) -> Option<(String, String)> {
-        // Doesn't need to be bigger since it's just the HWID Authorization event
-        let mut buffer = [0; 1024];
+    /// Tells a client which just logged in how many messages it has not read yet, then delivers
+    /// everything which was kept for it while it was offline.
+    pub async fn welcome(context: &ServerContext, hwid: &str, name: &str) {
+        let pending = context.mailbox.login(hwid, name).await;
+        let markers = context.mailbox.read_markers(hwid).await;
+        let event = Outgoing::Welcome {
+            unread: context.history.unread(hwid, &markers).await,
+            pending: pending.len(),
+        };
 
-        match stream.read(&mut buffer) {
-            Ok(bytes_read) => {
-                if bytes_read == 0 {
-                    log::info!("Client disconnected");
-                    process::exit(0);
-                }
+        let clients = &context.connected_clients;
+        Self::send_to(clients, hwid, event).await;
+        for pending in pending {
+            let event = match pending {
+                Pending::DirectMessage {
+                    username,
+                    content,
+                    sent_at,
+                } => Outgoing::DirectMessage {
+                    username,
+                    content,
+                    sent_at,
+                },
+                Pending::Mention {
+                    id,
+                    room,
+                    username,
+                    content,
+                    mention,
+                    sent_at,
+                } => Outgoing::Mention {
+                    id,
+                    room,
+                    username,
+                    content,
+                    mention,
+                    sent_at,
+                    missed: true,
+                },
+            };
+            Self::send_to(clients, hwid, event).await;
+        }
+    }
 
-                match ClientMessageType::from(buffer[0]) {
-                    ClientMessageType::RequestAuthentication => {
-                        let message = RequestAuthentication::deserialize(&buffer).await.unwrap();
-                        return Some((message.hwid.to_string(), message.name.to_string()));
-                    }
-                    _ => {
-                        log::error!("Received invalid event before authentication");
-                        return None;
-                    }
-                }
+    /// Starts an upload to the room of the client, or continues the unfinished upload of the
+    /// same file. Answered with where the upload continues.
+    pub async fn handle_start_upload(
+        start: StartUpload,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        let clients = &context.connected_clients;
+        let Some((_, client)) = clients.lock().await.get(&start.hwid).cloned() else {
+            return Err(CommandError::PermissionDenied);
+        };
+        let Some(room) = client.room.clone() else {
+            return Err(CommandError::Failed(
+                "Join a room to share files in it".to_string(),
+            ));
+        };
+        let Ok(size) = start.size.parse() else {
+            return Err(CommandError::Failed(format!(
+                "'{}' is not a file size",
+                start.size
+            )));
+        };
+
+        let files = &context.files;
+        let (id, offset) = files
+            .start_upload(&client, &room, &start.name, size, &start.sha256)
+            .await?;
+        tracing::debug!(id, offset, size, "Upload started");
+
+        let event = Outgoing::UploadOffset {
+            id,
+            sha256: start.sha256.to_lowercase(),
+            offset,
+            chunk_size: chunk_size(context.config().buffer_size),
+        };
+        Self::send_to(clients, &start.hwid, event).await;
+        Ok(())
+    }
+
+    /// Stores a chunk of an upload, the completed file is shared with its room and the uploader.
+    pub async fn handle_upload_chunk(
+        chunk: UploadChunk,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        let (Ok(offset), Ok(data)) = (chunk.offset.parse(), hex::decode(&chunk.data)) else {
+            return Err(CommandError::Failed("The chunk is malformed".to_string()));
+        };
+
+        let clients = &context.connected_clients;
+        let files = &context.files;
+        let file = match files
+            .write_chunk(&chunk.hwid, &chunk.id, offset, &data)
+            .await?
+        {
+            Written::Partial { sha256, offset } => {
+                let event = Outgoing::UploadOffset {
+                    id: chunk.id,
+                    sha256,
+                    offset,
+                    chunk_size: chunk_size(context.config().buffer_size),
+                };
+                Self::send_to(clients, &chunk.hwid, event).await;
+                return Ok(());
+            }
+            Written::Complete(file) => file,
+        };
+
+        tracing::info!(
+            id = file.id,
+            size = file.size,
+            room = file.room,
+            "File shared"
+        );
+        let event = Outgoing::FileShared {
+            id: file.id,
+            room: file.room.clone(),
+            username: file.username,
+            name: file.name,
+            size: file.size,
+            sha256: file.sha256,
+            uploaded_at: file.uploaded_at,
+        };
+        // The uploader might have left the room in the meantime
+        Self::send_to(clients, &chunk.hwid, event.clone()).await;
+        Self::broadcast_room(clients, &file.room, Some(&chunk.hwid), event).await;
+        Ok(())
+    }
+
+    /// Sends a chunk of a shared file, anyone who knows the id of a file may download it.
+    pub async fn handle_download_chunk(
+        download: DownloadChunk,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        let (Ok(offset), Ok(length)) = (download.offset.parse(), download.length.parse::<usize>())
+        else {
+            return Err(CommandError::Failed(
+                "The download request is malformed".to_string(),
+            ));
+        };
+
+        let length = length.min(chunk_size(context.config().buffer_size));
+        let (file, data) = context
+            .files
+            .read_chunk(&download.id, offset, length)
+            .await?;
+
+        let event = Outgoing::FileChunk {
+            id: file.id,
+            name: file.name,
+            size: file.size,
+            sha256: file.sha256,
+            offset,
+            data,
+        };
+        Self::send_to(&context.connected_clients, &download.hwid, event).await;
+        Ok(())
+    }
+
+    /// Moves the read marker of the client in a room forward, to the latest message if no id is
+    /// given.
+    pub async fn handle_mark_read(
+        mark_read: MarkRead,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        let history = &context.history;
+        let marker = match mark_read.id.as_str() {
+            "" => match history.latest(&mark_read.room).await {
+                Some(latest) => ReadMarker::from(&latest),
+                None => ReadMarker {
+                    sent_at: unix_timestamp(),
+                    id: 0,
+                },
+            },
+            id => match history.get(id).await {
+                Some(message) if message.room.eq(&mark_read.room) => ReadMarker::from(&message),
+                _ => return Err(Self::unknown_message(id)),
+            },
+        };
+
+        context
+            .mailbox
+            .mark_read(&mark_read.hwid, &mark_read.room, marker)
+            .await;
+        Ok(())
+    }
+
+    /// Whether another client is online with the name, or another account owns it. Names stay
+    /// with their account while it is offline, so nobody else receives what is kept for it.
+    /// Takes the locked client list, so the name can be claimed under the same lock.
+    pub async fn is_name_taken(
+        context: &ServerContext,
+        clients: &HashMap<String, (Outbox, Client)>,
+        hwid: &str,
+        name: &str,
+    ) -> bool {
+        clients
+            .iter()
+            .any(|(other, (_, client))| !other.eq(hwid) && client.name.eq(name))
+            || context
+                .mailbox
+                .account_named(name)
+                .await
+                .is_some_and(|owner| !owner.eq(hwid))
+    }
+
+    /// The requested name if it is free, otherwise the first free one with a numeric suffix.
+    pub async fn free_username(
+        context: &ServerContext,
+        clients: &HashMap<String, (Outbox, Client)>,
+        hwid: &str,
+        requested: &str,
+    ) -> String {
+        let mut name = requested.to_string();
+        let mut number = 1;
+        while Self::is_name_taken(context, clients, hwid, &name).await {
+            number += 1;
+            let suffix = format!("_{number}");
+            // Usernames are at most 32 bytes long
+            let mut end = requested.len().min(32 - suffix.len());
+            while !requested.is_char_boundary(end) {
+                end -= 1;
             }
+            name = format!("{}{suffix}", &requested[..end]);
+        }
+        name
+    }
+
+    pub async fn handle_change_username(
+        change_username: ChangeUsername,
+        context: &ServerContext,
+    ) -> Result<(), CommandError> {
+        let clients = &context.connected_clients;
+        let new_username = change_username.new_username;
+        if !is_valid_username(&new_username) {
+            return Err(CommandError::Failed(format!(
+                "'{new_username}' is not a valid username"
+            )));
+        }
+
+        let mut lock = clients.lock().await;
+        if Self::is_name_taken(context, &lock, &change_username.hwid, &new_username).await {
+            return Err(CommandError::Failed(format!(
+                "{new_username} is already in use"
+            )));
+        }
 
+        let Some((_, client)) = lock.get_mut(&change_username.hwid) else {
+            return Ok(());
+        };
+        let old_username = std::mem::replace(&mut client.name, new_username.clone());
+        drop(lock);
+
+        tracing::info!(old_username, new_username, "Username changed");
+        let actor = Account::new(&change_username.hwid, &new_username);
+        let event = AuditEvent::Rename {
+            old_username: old_username.clone(),
+            new_username: new_username.clone(),
+        };
+        context.audit.record(Some(actor), None, event).await;
+        context
+            .mailbox
+            .rename(&change_username.hwid, &new_username)
+            .await;
+        let event = Outgoing::UsernameChanged {
+            old_username,
+            new_username,
+        };
+        Self::broadcast(clients, None, event).await;
+        Ok(())
+    }
+
+    /// Reads the first message of a connection, returns the HWID, the requested name and the
+    /// token of the session to resume, if there is one.
+    pub async fn handle_auth<R>(
+        stream: &mut R,
+        max_length: usize,
+        metrics: &Metrics,
+    ) -> Option<(String, String, Option<String>)>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let frame = match read_frame(stream, max_length).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                tracing::info!("Client disconnected");
+                return None;
+            }
+            Err(why) => {
+                tracing::error!("Unable to read from stream! {why}");
+                metrics.deserialize_error(&why);
+                metrics.auth_failure("native", "invalid");
+                return None;
+            }
+        };
+
+        let message_type = ClientMessageType::from(frame[0]);
+        metrics.received("native", message_type.name(), frame.len());
+        let auth = match message_type {
+            ClientMessageType::RequestAuthentication => RequestAuthentication::deserialize(&frame)
+                .await
+                .map(|message| (message.hwid, message.name, None)),
+            ClientMessageType::ResumeSession => ResumeSession::deserialize(&frame)
+                .await
+                .map(|message| (message.hwid, message.name, Some(message.token))),
+            _ => {
+                tracing::error!("Received invalid event before authentication");
+                metrics.auth_failure("native", "invalid");
+                return None;
+            }
+        };
+
+        match auth {
+            Ok(auth) => Some(auth),
             Err(why) => {
-                log::error!("Unable to read from stream! {why}");
-                process::exit(0);
+                tracing::error!("Received invalid authentication request! {why}");
+                metrics.deserialize_error(&why);
+                metrics.auth_failure("native", "invalid");
+                None
             }
         }
     }
 
-    pub fn handle_change_username(change_username: ChangeUsername) {
-        log::info!(
-            "{} changed their username to {}",
-            change_username.hwid,
-            change_username.new_username
+    pub fn handle_unknown_message() {
+        tracing::warn!("Received unknown message");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EventHandler;
+    use crate::{
+        commands::CommandRegistry,
+        moderation::Role,
+        server::Server,
+        types::{Client, Config, Outgoing},
+    };
+    use chat_shared::protocols::client::{ChatMessage, FetchThread};
+    use std::collections::HashMap;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_messages_in_other_rooms() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = Config::in_directory(directory.path());
+        // Guests can't join rooms, so they may only read the room they are in
+        config.moderation.default_role = Role::Guest;
+        let server = Server::with_commands(config, CommandRegistry::default())
+            .await
+            .unwrap();
+        let context = &server.context;
+
+        let client = Client {
+            name: "Alice".to_string(),
+            hwid: "alice".to_string(),
+            session_token: "token".to_string(),
+            room: Some("general".to_string()),
+            address: "127.0.0.1".parse().unwrap(),
+        };
+        let (outbox, mut inbox) = mpsc::unbounded_channel();
+        *context.connected_clients.lock().await =
+            HashMap::from([(client.hwid.clone(), (outbox, client))]);
+        let secret = context
+            .history
+            .push(
+                "secret".into(),
+                "bob".into(),
+                "Bob".into(),
+                "hi".into(),
+                None,
+            )
+            .await;
+        let unknown = EventHandler::unknown_message(&secret.id);
+
+        let reply = ChatMessage {
+            hwid: "alice".to_string(),
+            content: "hello".to_string(),
+            reply_to: secret.id.clone(),
+        };
+        EventHandler::handle_send_message(reply, context).await;
+        let Some(Outgoing::System { content }) = inbox.recv().await else {
+            panic!("Expected the reply to be rejected");
+        };
+        assert_eq!(content, unknown.to_string());
+        assert_eq!(context.history.thread(&secret.id).await.unwrap().len(), 1);
+
+        let fetch = FetchThread {
+            hwid: "alice".to_string(),
+            id: secret.id.clone(),
+        };
+        let fetched = EventHandler::handle_fetch_thread(fetch, context).await;
+        assert_eq!(fetched, Err(EventHandler::unknown_message(&secret.id)));
+        let followed = EventHandler::handle_follow_thread("alice", &secret.id, true, context);
+        assert_eq!(followed.await, Err(unknown));
+        let reacted = EventHandler::handle_reaction("alice", &secret.id, ":+1:", true, context);
+        assert_eq!(
+            reacted.await,
+            Err(EventHandler::unknown_message(&secret.id))
         );
+        assert!(context
+            .history
+            .get(&secret.id)
+            .await
+            .unwrap()
+            .reactions
+            .is_empty());
+
+        // Members may join the room, so they may read it as well
+        let moderation = &context.moderation;
+        moderation.set_role("alice", None, Role::Member).await;
+        let followed = EventHandler::handle_follow_thread("alice", &secret.id, true, context);
+        assert_eq!(followed.await, Ok(()));
     }
 
-    pub fn handle_unknown_message() {
-        log::warn!("Received unknown message");
+    #[tokio::test]
+    async fn test_free_username() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = Config::in_directory(directory.path());
+        let server = Server::with_commands(config, CommandRegistry::default())
+            .await
+            .unwrap();
+        let context = &server.context;
+
+        let client = |name: &str| Client {
+            name: name.to_string(),
+            hwid: name.to_lowercase(),
+            session_token: String::new(),
+            room: None,
+            address: "127.0.0.1".parse().unwrap(),
+        };
+        let long = "L".repeat(32);
+        let clients = ["Bob", "Bob_2", &long].map(|name| {
+            (
+                name.to_lowercase(),
+                (mpsc::unbounded_channel().0, client(name)),
+            )
+        });
+        let clients = HashMap::from(clients);
+        let free = |hwid, name| EventHandler::free_username(context, &clients, hwid, name);
+
+        assert_eq!(free("alice", "Alice").await, "Alice");
+        assert_eq!(free("alice", "Bob").await, "Bob_3");
+        // Clients keep their own name
+        assert_eq!(free("bob", "Bob").await, "Bob");
+        assert_eq!(free("alice", &long).await, format!("{}_2", "L".repeat(30)));
+
+        // Offline accounts keep their name as well
+        context.mailbox.login("carol", "Carol").await;
+        context.mailbox.logout("carol").await;
+        assert_eq!(free("mallory", "Carol").await, "Carol_2");
+        assert_eq!(free("carol", "Carol").await, "Carol");
     }
 }
@@ -0,0 +1,464 @@
+use crate::{
+    commands::CommandError,
+    types::{Client, FilesConfig},
+    utils::{is_valid_file_name, unix_timestamp},
+};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    io::{self, SeekFrom},
+    path::PathBuf,
+    sync::RwLock,
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::Mutex,
+};
+
+/// Lists the stored files, next to them in the directory.
+const INDEX: &str = "index.json";
+
+/// Unfinished uploads which did not receive a chunk for this many seconds are discarded.
+const STALE_UPLOAD: u64 = 24 * 60 * 60;
+
+#[derive(thiserror::Error, Debug)]
+pub enum FileError {
+    #[error("File transfers are disabled on this server")]
+    Disabled,
+    #[error("'{0}' is not a valid file name")]
+    InvalidName(String),
+    #[error("'{0}' is not a SHA-256 hash")]
+    InvalidHash(String),
+    #[error("Empty files can't be shared")]
+    Empty,
+    #[error("Files can't be larger than {0} bytes")]
+    TooLarge(u64),
+    #[error("The server has no room left for the file")]
+    StoreFull,
+    #[error("Your files can't take up more than {0} bytes")]
+    QuotaExceeded(u64),
+    #[error("There is no file with the id {0}")]
+    Unknown(String),
+    #[error("The chunk does not fit into the file")]
+    InvalidChunk,
+    #[error("{0} does not match its SHA-256 hash and was discarded")]
+    HashMismatch(String),
+    #[error("Unable to access the stored file")]
+    Io(#[from] io::Error),
+}
+
+impl From<FileError> for CommandError {
+    fn from(why: FileError) -> Self {
+        if let FileError::Io(why) = &why {
+            tracing::error!("File store failed! {why}");
+        }
+        CommandError::Failed(why.to_string())
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    /// As lowercase hex
+    pub sha256: String,
+    /// The HWID of the account which uploaded the file
+    pub uploader: String,
+    pub username: String,
+    /// The room the file is shared in once it is complete
+    pub room: String,
+    /// Unix timestamp of the last chunk, when the upload finished once it is complete. Complete
+    /// files expire `FilesConfig::expire_after` seconds later.
+    pub uploaded_at: u64,
+    pub complete: bool,
+}
+
+/// What became of an uploaded chunk.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Written {
+    /// Where the upload continues. Chunks which do not start at the end of what was stored
+    /// are ignored, the client continues from there instead.
+    Partial {
+        sha256: String,
+        offset: u64,
+    },
+    Complete(StoredFile),
+}
+
+/// Everything which is persisted, keyed by file id.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[serde(default)]
+struct FileIndex {
+    files: HashMap<String, StoredFile>,
+}
+
+/// Keeps shared files on disk, each named after its id.
+pub struct FileStore {
+    index: Mutex<FileIndex>,
+    config: RwLock<FilesConfig>,
+}
+
+impl FileStore {
+    pub async fn load(config: FilesConfig) -> io::Result<Self> {
+        let index = match config.enabled {
+            true => {
+                fs::create_dir_all(&config.directory).await?;
+                match fs::read(config.directory.join(INDEX)).await {
+                    Ok(contents) => serde_json::from_slice(&contents)
+                        .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?,
+                    Err(why) if why.kind() == io::ErrorKind::NotFound => FileIndex::default(),
+                    Err(why) => return Err(why),
+                }
+            }
+            false => FileIndex::default(),
+        };
+
+        Ok(Self {
+            index: Mutex::new(index),
+            config: RwLock::new(config),
+        })
+    }
+
+    /// Applies new size limits, the directory is only read on startup.
+    pub fn reconfigure(&self, config: FilesConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    fn check_enabled(&self) -> Result<(), FileError> {
+        match self.config.read().unwrap().enabled {
+            true => Ok(()),
+            false => Err(FileError::Disabled),
+        }
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.config.read().unwrap().directory.join(id)
+    }
+
+    async fn save(&self, index: &FileIndex) {
+        let contents = match serde_json::to_vec_pretty(index) {
+            Ok(contents) => contents,
+            Err(why) => {
+                tracing::error!("Unable to serialize the file index! {why}");
+                return;
+            }
+        };
+
+        let file = self.config.read().unwrap().directory.join(INDEX);
+        if let Err(why) = fs::write(&file, contents).await {
+            tracing::error!("Unable to write {}! {why}", file.display());
+        }
+    }
+
+    /// Starts an upload to the room, or continues the unfinished upload of the same file by the
+    /// same account. Returns the id of the upload and where it continues.
+    pub async fn start_upload(
+        &self,
+        uploader: &Client,
+        room: &str,
+        name: &str,
+        size: u64,
+        sha256: &str,
+    ) -> Result<(String, u64), FileError> {
+        self.check_enabled()?;
+        if !is_valid_file_name(name) {
+            return Err(FileError::InvalidName(name.to_string()));
+        }
+        let sha256 = sha256.to_lowercase();
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(FileError::InvalidHash(sha256));
+        }
+        let config = self.config.read().unwrap().clone();
+        let max_file_size = config.max_file_size;
+        match size {
+            0 => return Err(FileError::Empty),
+            size if size > max_file_size => return Err(FileError::TooLarge(max_file_size)),
+            _ => {}
+        }
+
+        let mut index = self.index.lock().await;
+        let unfinished = index.files.values_mut().find(|file| {
+            !file.complete
+                && file.uploader.eq(&uploader.hwid)
+                && file.sha256.eq(&sha256)
+                && file.size == size
+        });
+        if let Some(file) = unfinished {
+            file.name = name.to_string();
+            file.username = uploader.name.clone();
+            file.room = room.to_string();
+            file.uploaded_at = unix_timestamp();
+
+            let id = file.id.clone();
+            let stored = fs::metadata(self.path(&id)).await.map_or(0, |m| m.len());
+            return Ok((id, stored));
+        }
+
+        self.discard_expired(&mut index).await;
+        let used = index.files.values().map(|file| file.size).sum::<u64>();
+        if used.saturating_add(size) > config.max_total_size {
+            return Err(FileError::StoreFull);
+        }
+        let files = index.files.values();
+        let own = files.filter(|file| file.uploader.eq(&uploader.hwid));
+        let used = own.map(|file| file.size).sum::<u64>();
+        if used.saturating_add(size) > config.max_size_per_user {
+            return Err(FileError::QuotaExceeded(config.max_size_per_user));
+        }
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        fs::write(self.path(&id), []).await?;
+        let file = StoredFile {
+            id: id.clone(),
+            name: name.to_string(),
+            size,
+            sha256,
+            uploader: uploader.hwid.clone(),
+            username: uploader.name.clone(),
+            room: room.to_string(),
+            uploaded_at: unix_timestamp(),
+            complete: false,
+        };
+        index.files.insert(id.clone(), file);
+        self.save(&index).await;
+
+        Ok((id, 0))
+    }
+
+    /// Whether the file is an unfinished upload which stalled or a shared file which expired.
+    fn is_expired(&self, file: &StoredFile, now: u64) -> bool {
+        let expire_after = self.config.read().unwrap().expire_after;
+        match file.complete {
+            true => expire_after > 0 && file.uploaded_at.saturating_add(expire_after) < now,
+            false => file.uploaded_at.saturating_add(STALE_UPLOAD) < now,
+        }
+    }
+
+    async fn discard_expired(&self, index: &mut FileIndex) {
+        let now = unix_timestamp();
+        let expired = index
+            .files
+            .values()
+            .filter(|file| self.is_expired(file, now))
+            .map(|file| file.id.clone())
+            .collect::<Vec<_>>();
+
+        for id in expired {
+            tracing::debug!(id, "Discarding expired file");
+            index.files.remove(&id);
+            let _ = fs::remove_file(self.path(&id)).await;
+        }
+    }
+
+    /// Appends a chunk to an unfinished upload of the account, the hash of the file is checked
+    /// once it is complete. Hashing happens without holding the index, so other transfers go on.
+    pub async fn write_chunk(
+        &self,
+        hwid: &str,
+        id: &str,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<Written, FileError> {
+        self.check_enabled()?;
+        let mut index = self.index.lock().await;
+        let Some(file) = index
+            .files
+            .get_mut(id)
+            .filter(|file| !file.complete && file.uploader.eq(hwid))
+        else {
+            return Err(FileError::Unknown(id.to_string()));
+        };
+
+        let path = self.path(id);
+        let stored = fs::metadata(&path).await.map_or(0, |m| m.len());
+        let partial = |offset| Written::Partial {
+            sha256: file.sha256.clone(),
+            offset,
+        };
+        if offset != stored {
+            return Ok(partial(stored));
+        }
+        let stored = stored + data.len() as u64;
+        if data.is_empty() || stored > file.size {
+            return Err(FileError::InvalidChunk);
+        }
+
+        let mut output = fs::OpenOptions::new().append(true).open(&path).await?;
+        output.write_all(data).await?;
+        output.flush().await?;
+        file.uploaded_at = unix_timestamp();
+        if stored < file.size {
+            return Ok(partial(stored));
+        }
+        let sha256 = file.sha256.clone();
+        drop(index);
+
+        // Further chunks are rejected since the file is full, so nothing changes meanwhile
+        let matches = hash_file(&path).await?.eq(&sha256);
+        let mut index = self.index.lock().await;
+        if !matches {
+            let name = index.files.remove(id).map(|file| file.name);
+            let _ = fs::remove_file(&path).await;
+            self.save(&index).await;
+            return Err(FileError::HashMismatch(name.unwrap_or_default()));
+        }
+
+        let Some(file) = index.files.get_mut(id) else {
+            return Err(FileError::Unknown(id.to_string()));
+        };
+        file.complete = true;
+        file.uploaded_at = unix_timestamp();
+        let file = file.clone();
+        self.save(&index).await;
+        Ok(Written::Complete(file))
+    }
+
+    /// Reads up to `length` bytes of a complete file, nothing past its end.
+    pub async fn read_chunk(
+        &self,
+        id: &str,
+        offset: u64,
+        length: usize,
+    ) -> Result<(StoredFile, Vec<u8>), FileError> {
+        self.check_enabled()?;
+        let file = self.index.lock().await.files.get(id).cloned();
+        let now = unix_timestamp();
+        let Some(file) = file.filter(|file| file.complete && !self.is_expired(file, now)) else {
+            return Err(FileError::Unknown(id.to_string()));
+        };
+
+        let length = file.size.saturating_sub(offset).min(length as u64);
+        let mut data = vec![0; length as usize];
+        if length > 0 {
+            let mut input = fs::File::open(self.path(id)).await?;
+            input.seek(SeekFrom::Start(offset)).await?;
+            input.read_exact(&mut data).await?;
+        }
+
+        Ok((file, data))
+    }
+}
+
+/// The SHA-256 of a file as lowercase hex.
+async fn hash_file(path: &PathBuf) -> io::Result<String> {
+    let mut input = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = input.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileError, FileStore, Written};
+    use crate::types::{Client, FilesConfig};
+    use sha2::{Digest, Sha256};
+
+    fn client() -> Client {
+        Client {
+            name: "Alice".to_string(),
+            hwid: "alice".to_string(),
+            session_token: String::new(),
+            room: Some("general".to_string()),
+            address: [127, 0, 0, 1].into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = FilesConfig {
+            directory: directory.path().to_path_buf(),
+            max_file_size: 8,
+            ..Default::default()
+        };
+        let store = FileStore::load(config.clone()).await.unwrap();
+        let client = client();
+        let sha256 = hex::encode(Sha256::digest(b"hello"));
+
+        let upload = store.start_upload(&client, "general", "a.txt", 5, &sha256);
+        let (id, offset) = upload.await.unwrap();
+        assert_eq!(offset, 0);
+        let written = store.write_chunk("alice", &id, 0, b"hel").await.unwrap();
+        assert!(matches!(written, Written::Partial { offset: 3, .. }));
+        // Repeated chunks are ignored and the upload continues after a restart
+        let written = store.write_chunk("alice", &id, 0, b"hel").await.unwrap();
+        assert!(matches!(written, Written::Partial { offset: 3, .. }));
+        assert!(store.read_chunk(&id, 0, 5).await.is_err());
+
+        let store = FileStore::load(config).await.unwrap();
+        let upload = store.start_upload(&client, "general", "b.txt", 5, &sha256);
+        assert_eq!(upload.await.unwrap(), (id.clone(), 3));
+        let written = store.write_chunk("alice", &id, 3, b"lo").await.unwrap();
+        assert!(matches!(written, Written::Complete(file) if file.name == "b.txt"));
+
+        let (file, data) = store.read_chunk(&id, 1, 100).await.unwrap();
+        assert_eq!((file.size, data.as_slice()), (5, &b"ello"[..]));
+
+        let wrong = "0".repeat(64);
+        let upload = store.start_upload(&client, "general", "c.txt", 5, &wrong);
+        let (id, _) = upload.await.unwrap();
+        assert!(matches!(
+            store.write_chunk("alice", &id, 0, b"hello").await,
+            Err(FileError::HashMismatch(_))
+        ));
+        assert!(matches!(
+            store
+                .start_upload(&client, "general", "d", 9, &sha256)
+                .await,
+            Err(FileError::TooLarge(8))
+        ));
+        assert!(matches!(
+            store
+                .start_upload(&client, "general", "../d", 5, &sha256)
+                .await,
+            Err(FileError::InvalidName(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_quota_and_expiry() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = FilesConfig {
+            directory: directory.path().to_path_buf(),
+            max_size_per_user: 8,
+            expire_after: 60,
+            ..Default::default()
+        };
+        let store = FileStore::load(config).await.unwrap();
+        let client = client();
+        let sha256 = hex::encode(Sha256::digest(b"hello"));
+        let other = "0".repeat(64);
+
+        let upload = store.start_upload(&client, "general", "a.txt", 5, &sha256);
+        let (id, _) = upload.await.unwrap();
+        store.write_chunk("alice", &id, 0, b"hello").await.unwrap();
+        assert!(matches!(
+            store
+                .start_upload(&client, "general", "b.txt", 4, &other)
+                .await,
+            Err(FileError::QuotaExceeded(8))
+        ));
+
+        // Expired files can't be downloaded anymore and no longer count towards the quota
+        let mut index = store.index.lock().await;
+        index.files.get_mut(&id).unwrap().uploaded_at -= 61;
+        drop(index);
+        assert!(matches!(
+            store.read_chunk(&id, 0, 5).await,
+            Err(FileError::Unknown(_))
+        ));
+        let upload = store.start_upload(&client, "general", "b.txt", 4, &other);
+        assert!(upload.await.is_ok());
+        assert!(!directory.path().join(&id).exists());
+    }
+}
@@ -0,0 +1,358 @@
+use crate::{mailbox::ReadMarker, utils::unix_timestamp};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tokio::sync::Mutex;
+
+/// How many different reactions a single message can have.
+pub const MAX_REACTIONS: usize = 20;
+
+/// A previous version of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub content: String,
+    /// Unix timestamp of when this content was replaced
+    pub replaced_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredMessage {
+    /// Assigned by the server, unique until it restarts
+    pub id: String,
+    pub room: String,
+    pub author_hwid: String,
+    pub username: String,
+    pub content: String,
+    /// Unix timestamp
+    pub sent_at: u64,
+    /// Every previous content, the oldest first
+    pub edits: Vec<Edit>,
+    /// The id of the message this one replies to
+    pub reply_to: Option<String>,
+    /// The id of the first message of the thread, `None` if this is the first one
+    pub thread: Option<String>,
+    /// The HWIDs of everyone who reacted, by reaction
+    pub reactions: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl StoredMessage {
+    /// The id of the thread the message belongs to, every message starts its own.
+    pub fn thread_id(&self) -> &str {
+        self.thread.as_deref().unwrap_or(&self.id)
+    }
+
+    /// How often each reaction was given, in the order of the reactions.
+    pub fn reaction_counts(&self) -> Vec<(String, usize)> {
+        let reactions = self.reactions.iter();
+        reactions
+            .map(|(r, hwids)| (r.clone(), hwids.len()))
+            .collect()
+    }
+}
+
+/// The outcome of adding or removing a reaction.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reacted {
+    Changed(Box<StoredMessage>),
+    /// The reaction was already given, or was not given when removing it
+    Unchanged,
+    /// The message already has `MAX_REACTIONS` different reactions
+    Full,
+}
+
+/// The most recent messages of each room, so they can be referenced by their id. A busy room
+/// can't push the messages of the others out. Nothing is persisted, the history starts over
+/// when the server restarts.
+pub struct History {
+    /// Oldest first
+    rooms: Mutex<HashMap<String, VecDeque<StoredMessage>>>,
+    /// Per room
+    capacity: usize,
+    next_id: AtomicU64,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            rooms: Mutex::new(HashMap::new()),
+            capacity,
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Assigns an id to the message and stores it, dropping the oldest message of the room if
+    /// it is full. Replies continue the thread of the message they reply to.
+    pub async fn push(
+        &self,
+        room: String,
+        author_hwid: String,
+        username: String,
+        content: String,
+        reply_to: Option<&StoredMessage>,
+    ) -> StoredMessage {
+        let message = StoredMessage {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed).to_string(),
+            room,
+            author_hwid,
+            username,
+            content,
+            sent_at: unix_timestamp(),
+            edits: Vec::new(),
+            reply_to: reply_to.map(|parent| parent.id.clone()),
+            thread: reply_to.map(|parent| parent.thread_id().to_string()),
+            reactions: BTreeMap::new(),
+        };
+
+        if self.capacity == 0 {
+            return message;
+        }
+        let mut rooms = self.rooms.lock().await;
+        let messages = rooms.entry(message.room.clone()).or_default();
+        if messages.len() >= self.capacity {
+            messages.pop_front();
+        }
+        messages.push_back(message.clone());
+
+        message
+    }
+
+    pub async fn get(&self, id: &str) -> Option<StoredMessage> {
+        let rooms = self.rooms.lock().await;
+        rooms.values().flatten().find(|m| m.id.eq(id)).cloned()
+    }
+
+    /// The most recent message of the room which is still stored.
+    pub async fn latest(&self, room: &str) -> Option<StoredMessage> {
+        let rooms = self.rooms.lock().await;
+        rooms.get(room)?.back().cloned()
+    }
+
+    /// How many stored messages of others were sent after the read marker of each room, only
+    /// rooms with unread messages are included.
+    pub async fn unread(
+        &self,
+        hwid: &str,
+        markers: &HashMap<String, ReadMarker>,
+    ) -> Vec<(String, usize)> {
+        let rooms = self.rooms.lock().await;
+        let mut unread = BTreeMap::new();
+        for message in rooms.values().flatten() {
+            let Some(marker) = markers.get(&message.room) else {
+                continue;
+            };
+            if !message.author_hwid.eq(hwid) && ReadMarker::from(message) > *marker {
+                *unread.entry(message.room.clone()).or_default() += 1;
+            }
+        }
+
+        unread.into_iter().collect()
+    }
+
+    /// The messages of the thread the message belongs to which are still stored, oldest first.
+    /// Replies are always sent to the room of the thread.
+    pub async fn thread(&self, id: &str) -> Option<Vec<StoredMessage>> {
+        let rooms = self.rooms.lock().await;
+        let message = rooms.values().flatten().find(|m| m.id.eq(id))?;
+        let thread = message.thread_id();
+
+        let thread = rooms[&message.room]
+            .iter()
+            .filter(|m| m.thread_id().eq(thread))
+            .cloned()
+            .collect();
+        Some(thread)
+    }
+
+    /// Replaces the content and keeps the previous one, returns the edited message.
+    pub async fn edit(&self, id: &str, content: String) -> Option<StoredMessage> {
+        let mut rooms = self.rooms.lock().await;
+        let message = rooms.values_mut().flatten().find(|m| m.id.eq(id))?;
+
+        let previous = std::mem::replace(&mut message.content, content);
+        message.edits.push(Edit {
+            content: previous,
+            replaced_at: unix_timestamp(),
+        });
+        Some(message.clone())
+    }
+
+    /// Adds or removes the reaction of a client, `None` if the message is unknown.
+    pub async fn react(&self, id: &str, reaction: &str, hwid: &str, add: bool) -> Option<Reacted> {
+        let mut rooms = self.rooms.lock().await;
+        let message = rooms.values_mut().flatten().find(|m| m.id.eq(id))?;
+        let reactions = &mut message.reactions;
+
+        let changed = match add {
+            true if !reactions.contains_key(reaction) && reactions.len() >= MAX_REACTIONS => {
+                return Some(Reacted::Full);
+            }
+            true => reactions
+                .entry(reaction.to_string())
+                .or_default()
+                .insert(hwid.to_string()),
+            false => {
+                let Some(hwids) = reactions.get_mut(reaction) else {
+                    return Some(Reacted::Unchanged);
+                };
+                let removed = hwids.remove(hwid);
+                if hwids.is_empty() {
+                    reactions.remove(reaction);
+                }
+                removed
+            }
+        };
+
+        match changed {
+            true => Some(Reacted::Changed(Box::new(message.clone()))),
+            false => Some(Reacted::Unchanged),
+        }
+    }
+
+    pub async fn remove(&self, id: &str) -> Option<StoredMessage> {
+        let mut rooms = self.rooms.lock().await;
+        let messages = rooms
+            .values_mut()
+            .find(|messages| messages.iter().any(|m| m.id.eq(id)))?;
+        let index = messages.iter().position(|m| m.id.eq(id))?;
+        messages.remove(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{History, Reacted, MAX_REACTIONS};
+    use crate::mailbox::ReadMarker;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_history_capacity() {
+        let history = History::new(2);
+        let other = history
+            .push("rust".into(), "a".into(), "A".into(), "0".into(), None)
+            .await;
+        let first = history
+            .push("general".into(), "a".into(), "A".into(), "1".into(), None)
+            .await;
+        let second = history
+            .push("general".into(), "a".into(), "A".into(), "2".into(), None)
+            .await;
+        history
+            .push("general".into(), "a".into(), "A".into(), "3".into(), None)
+            .await;
+
+        // Every room keeps its own messages
+        assert_ne!(first.id, second.id);
+        assert_eq!(history.get(&first.id).await, None);
+        assert_eq!(history.get(&other.id).await, Some(other));
+        assert_eq!(history.remove(&second.id).await, Some(second.clone()));
+        assert_eq!(history.get(&second.id).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_history_edits() {
+        let history = History::new(2);
+        let message = history
+            .push(
+                "general".into(),
+                "a".into(),
+                "A".into(),
+                "tpyo".into(),
+                None,
+            )
+            .await;
+
+        history.edit(&message.id, "typo".into()).await.unwrap();
+        let edited = history.edit(&message.id, "typo!".into()).await.unwrap();
+        assert_eq!(edited.content, "typo!");
+        let previous = edited.edits.iter().map(|e| e.content.as_str());
+        assert_eq!(previous.collect::<Vec<_>>(), ["tpyo", "typo"]);
+        assert_eq!(history.get(&message.id).await, Some(edited));
+        assert_eq!(history.edit("unknown", "x".into()).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_history_threads() {
+        let history = History::new(10);
+        let push = |content: &str, reply_to| {
+            history.push(
+                "general".into(),
+                "a".into(),
+                "A".into(),
+                content.into(),
+                reply_to,
+            )
+        };
+        let root = push("root", None).await;
+        push("unrelated", None).await;
+        let reply = push("reply", Some(&root)).await;
+        let nested = push("nested", Some(&reply)).await;
+
+        assert_eq!(nested.reply_to.as_deref(), Some(reply.id.as_str()));
+        assert_eq!(nested.thread_id(), root.id);
+        let thread = history.thread(&nested.id).await.unwrap();
+        let contents = thread.iter().map(|m| m.content.as_str());
+        assert_eq!(contents.collect::<Vec<_>>(), ["root", "reply", "nested"]);
+        assert_eq!(history.thread("unknown").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_history_reactions() {
+        let history = History::new(2);
+        let message = history
+            .push("general".into(), "a".into(), "A".into(), "hi".into(), None)
+            .await;
+        let react = |reaction: String, hwid: &'static str, add| {
+            let id = message.id.clone();
+            let history = &history;
+            async move { history.react(&id, &reaction, hwid, add).await.unwrap() }
+        };
+
+        assert!(matches!(
+            react("👍".into(), "a", true).await,
+            Reacted::Changed(_)
+        ));
+        assert_eq!(react("👍".into(), "a", true).await, Reacted::Unchanged);
+        let Reacted::Changed(reacted) = react("👍".into(), "b", true).await else {
+            panic!("the reaction of another client was not counted");
+        };
+        assert_eq!(reacted.reaction_counts(), [("👍".to_string(), 2)]);
+
+        react("👍".into(), "a", false).await;
+        let Reacted::Changed(reacted) = react("👍".into(), "b", false).await else {
+            panic!("the reaction was not removed");
+        };
+        assert!(reacted.reactions.is_empty());
+        assert_eq!(react(":x:".into(), "a", false).await, Reacted::Unchanged);
+
+        for n in 0..MAX_REACTIONS {
+            react(format!(":{n}:"), "a", true).await;
+        }
+        assert_eq!(react(":full:".into(), "a", true).await, Reacted::Full);
+        assert_eq!(history.react("unknown", ":x:", "a", true).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_history_unread() {
+        let history = History::new(10);
+        let push = |room: &str, hwid: &str| {
+            history.push(room.into(), hwid.into(), "A".into(), "x".into(), None)
+        };
+        let read = push("general", "b").await;
+        push("general", "a").await;
+        push("general", "b").await;
+        push("rust", "b").await;
+        let latest = push("random", "b").await;
+
+        let markers = HashMap::from([
+            ("general".to_string(), ReadMarker::from(&read)),
+            ("random".to_string(), ReadMarker::from(&latest)),
+        ]);
+        // Our own messages and rooms we never read are not counted
+        assert_eq!(
+            history.unread("a", &markers).await,
+            [("general".to_string(), 1)]
+        );
+        assert_eq!(history.latest("rust").await.unwrap().room, "rust");
+    }
+}
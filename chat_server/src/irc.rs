@@ -0,0 +1,883 @@
+use crate::{
+    audit::{Account, AuditEvent},
+    commands::is_command,
+    event_handler::EventHandler,
+    limits::Rejection,
+    moderation::{self, Action, Permission},
+    types::{Client, Outgoing, ServerContext},
+    utils::{is_alphanumeric_with_symbols, unix_timestamp, IRC_RESERVED},
+};
+use chat_shared::protocols::client::{ChangeUsername, ChatMessage};
+use futures::StreamExt;
+use std::{net::IpAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{tcp::OwnedWriteHalf, TcpListener, TcpStream},
+    sync::mpsc,
+    time::{interval_at, sleep_until, Instant, MissedTickBehavior},
+};
+use tokio_util::codec::{FramedRead, LinesCodec, LinesCodecError};
+use tracing::{field, Instrument, Span};
+
+/// RFC 1459 limits a line to 512 bytes, including the trailing CRLF.
+const MAX_LINE_LENGTH: usize = 512;
+
+/// A single line of the IRC protocol, e.g. `:nick PRIVMSG #general :Hello there`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct IrcMessage {
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']).trim_start();
+
+        let prefix = match rest.strip_prefix(':') {
+            Some(stripped) => {
+                let (prefix, remaining) = stripped.split_once(' ')?;
+                rest = remaining.trim_start();
+                Some(prefix.to_string())
+            }
+            None => None,
+        };
+
+        let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if command.is_empty() {
+            return None;
+        }
+
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start_matches(' ');
+            if rest.is_empty() {
+                break;
+            }
+
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+
+            let (param, remaining) = rest.split_once(' ').unwrap_or((rest, ""));
+            params.push(param.to_string());
+            rest = remaining;
+        }
+
+        Some(Self {
+            prefix,
+            command: command.to_ascii_uppercase(),
+            params,
+        })
+    }
+}
+
+pub fn is_valid_nick(nick: &str) -> bool {
+    !nick.is_empty()
+        && nick.len() <= 32
+        && !nick.starts_with(|c: char| c.is_ascii_digit() || c == '-')
+        && is_alphanumeric_with_symbols(nick)
+        && !nick.contains(IRC_RESERVED)
+}
+
+/// IRC lines must never contain line breaks, otherwise a client could inject commands.
+fn sanitize(content: &str) -> String {
+    content.replace(['\r', '\n'], " ")
+}
+
+/// Accepts IRC clients until the server shuts down.
+pub async fn listen(listener: TcpListener, context: Arc<ServerContext>) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = context.shutdown.cancelled() => break,
+        };
+
+        match accepted {
+            Ok((stream, peer_addr)) => {
+                let span = tracing::info_span!(
+                    "connection",
+                    protocol = "irc",
+                    peer = %peer_addr,
+                    hwid = field::Empty,
+                    session = field::Empty,
+                );
+                span.in_scope(|| tracing::info!("Connected"));
+                let connection = IrcConnection::handle(stream, context.clone());
+                context.tasks.spawn(connection.instrument(span));
+            }
+            Err(why) => tracing::error!("Error accepting IRC connection! {why}"),
+        }
+    }
+}
+
+struct IrcConnection {
+    stream: OwnedWriteHalf,
+    context: Arc<ServerContext>,
+    nick: Option<String>,
+    user: Option<String>,
+    address: IpAddr,
+    /// The HWID this connection is registered with in the client list
+    hwid: Option<String>,
+    session_token: String,
+    /// The room as the IRC client knows it, rooms are mapped to `#room` channels.
+    /// It lags behind the client list until the events of the room change are delivered.
+    room: Option<String>,
+    /// Gets the fields which are only known after registering
+    span: Span,
+}
+
+impl IrcConnection {
+    async fn handle(stream: TcpStream, context: Arc<ServerContext>) {
+        let Ok(address) = stream.peer_addr().map(|addr| addr.ip()) else {
+            return;
+        };
+        let (read_stream, mut write_stream) = stream.into_split();
+
+        // Held until the connection is closed
+        let _guard = match context.connection_limiter.acquire(address) {
+            Ok(guard) => guard,
+            Err(rejection) => {
+                tracing::info!("Rejected connection: {rejection}");
+                let _ = write_stream
+                    .write_all(format!("ERROR :{rejection}\r\n").as_bytes())
+                    .await;
+                return;
+            }
+        };
+
+        let mut lines = FramedRead::new(
+            read_stream,
+            LinesCodec::new_with_max_length(MAX_LINE_LENGTH),
+        );
+        let (outbox, mut inbox) = mpsc::unbounded_channel();
+
+        let mut connection = IrcConnection {
+            stream: write_stream,
+            context,
+            nick: None,
+            user: None,
+            address,
+            hwid: None,
+            session_token: uuid::Uuid::new_v4().to_string(),
+            room: None,
+            span: Span::current(),
+        };
+
+        if connection.reject_banned().await {
+            return;
+        }
+
+        // Existing connections keep their timeouts when the config is reloaded
+        let config = connection.context.config();
+        let connections = &config.connections;
+        let auth_timeout = Duration::from_secs(connections.auth_timeout);
+        let idle_timeout = Duration::from_secs(connections.idle_timeout);
+        let mut deadline = Instant::now() + auth_timeout;
+
+        let heartbeat = &config.heartbeat;
+        let heartbeat_timeout = Duration::from_secs(heartbeat.timeout);
+        let mut alive_until = Instant::now() + heartbeat_timeout;
+        let mut heartbeat = interval_at(
+            Instant::now() + Duration::from_secs(heartbeat.interval),
+            Duration::from_secs(heartbeat.interval),
+        );
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let keep_open = tokio::select! {
+                _ = heartbeat.tick() => {
+                    let token = unix_timestamp().to_string();
+                    connection.deliver(Outgoing::Ping { token }).await
+                }
+                _ = sleep_until(alive_until) => {
+                    tracing::info!("Client stopped responding");
+                    let _ = connection.send("ERROR :Connection timed out, no heartbeat received").await;
+                    false
+                }
+                // Registered clients may idle unless a timeout is configured
+                _ = sleep_until(deadline), if connection.hwid.is_none() || !idle_timeout.is_zero() => {
+                    let rejection = match connection.hwid {
+                        Some(_) => Rejection::IdleTimeout,
+                        None => Rejection::AuthTimeout,
+                    };
+                    connection.context.connection_limiter.metrics.record(rejection);
+                    if rejection == Rejection::AuthTimeout {
+                        connection.context.metrics.auth_failure("irc", "timeout");
+                    }
+                    let _ = connection.send(&format!("ERROR :{rejection}")).await;
+                    false
+                }
+                line = lines.next() => match line {
+                    Some(Ok(line)) => {
+                        connection.context.metrics.received_irc(&line);
+                        alive_until = Instant::now() + heartbeat_timeout;
+                        let message = IrcMessage::parse(&line);
+                        // Heartbeats keep the connection alive, but don't count as activity
+                        let is_heartbeat = message
+                            .as_ref()
+                            .is_some_and(|m| matches!(m.command.as_str(), "PING" | "PONG"));
+
+                        let keep_open = match message {
+                            Some(message) => {
+                                let span = tracing::info_span!(
+                                    "message",
+                                    command = %message.command,
+                                    bytes = line.len()
+                                );
+                                connection
+                                    .handle_message(message, &outbox)
+                                    .instrument(span)
+                                    .await
+                            }
+                            None => true,
+                        };
+                        // Unregistered connections have to register within the first deadline
+                        if connection.hwid.is_some() && !is_heartbeat {
+                            deadline = Instant::now() + idle_timeout;
+                        }
+                        keep_open
+                    }
+                    Some(Err(LinesCodecError::MaxLineLengthExceeded)) => {
+                        connection.reply("417", ":Input line was too long").await
+                    }
+                    Some(Err(why)) => {
+                        tracing::error!("Unable to read from IRC stream! {why}");
+                        false
+                    }
+                    None => false,
+                },
+                Some(event) = inbox.recv() => connection.deliver(event).await,
+                _ = connection.context.shutdown.cancelled() => {
+                    // Registered clients are told why in their outbox
+                    while let Ok(event) = inbox.try_recv() {
+                        if !connection.deliver(event).await {
+                            break;
+                        }
+                    }
+                    false
+                }
+            };
+
+            if !keep_open {
+                break;
+            }
+        }
+
+        if let Some(hwid) = &connection.hwid {
+            let clients = &connection.context.connected_clients;
+            let client =
+                EventHandler::handle_disconnect(clients, hwid, &connection.session_token).await;
+            if let Some(client) = client {
+                let audit = &connection.context.audit;
+                audit
+                    .record(Some(Account::from(&client)), None, AuditEvent::Logout)
+                    .await;
+            }
+            let rate_limiter = &connection.context.rate_limiter;
+            rate_limiter
+                .forget_connection(&connection.session_token)
+                .await;
+            let followers = &connection.context.followers;
+            followers.forget_connection(&connection.session_token).await;
+            connection.context.mailbox.logout(hwid).await;
+        }
+        tracing::info!("Client disconnected");
+    }
+
+    /// Returns `false` if the connection should be closed.
+    async fn handle_message(
+        &mut self,
+        message: IrcMessage,
+        outbox: &mpsc::UnboundedSender<Outgoing>,
+    ) -> bool {
+        let command = message.command.as_str();
+
+        match command {
+            "NICK" => self.handle_nick(message.params).await,
+            "USER" => {
+                if self.hwid.is_some() {
+                    self.reply("462", ":You may not reregister").await
+                } else if message.params.len() < 4 {
+                    self.reply("461", "USER :Not enough parameters").await
+                } else {
+                    self.user = message.params.into_iter().next();
+                    self.try_register(outbox).await
+                }
+            }
+            "PING" => {
+                let token = message.params.first().cloned().unwrap_or_default();
+                let server_name = self.context.config().irc.server_name.clone();
+                self.send(&format!(":{server_name} PONG {server_name} :{token}"))
+                    .await
+            }
+            "PONG" | "CAP" => true,
+            "QUIT" => {
+                let _ = self.send("ERROR :Closing link").await;
+                false
+            }
+            _ if self.hwid.is_none() => self.reply("451", ":You have not registered").await,
+            "JOIN" => self.handle_join(message.params).await,
+            "PART" => self.handle_part(message.params).await,
+            "NAMES" => {
+                let channel = message
+                    .params
+                    .first()
+                    .cloned()
+                    .or_else(|| self.room.as_ref().map(|room| format!("#{room}")));
+                match channel {
+                    Some(channel) => self.handle_names(&channel).await,
+                    None => true,
+                }
+            }
+            "PRIVMSG" => self.handle_privmsg(message.params).await,
+            _ => {
+                self.reply("421", &format!("{command} :Unknown command"))
+                    .await
+            }
+        }
+    }
+
+    async fn handle_nick(&mut self, params: Vec<String>) -> bool {
+        let Some(nick) = params.into_iter().next() else {
+            return self.reply("431", ":No nickname given").await;
+        };
+
+        if !is_valid_nick(&nick) {
+            return self
+                .reply("432", &format!("{nick} :Erroneous nickname"))
+                .await;
+        }
+
+        // Once registered, the rename is announced to everyone like any other
+        if let Some(hwid) = &self.hwid {
+            let action = Action::Message { bytes: nick.len() };
+            if !moderation::throttle(&self.context, hwid, action).await
+                || !moderation::authorize(&self.context, hwid, Permission::ChangeUsername).await
+            {
+                return true;
+            }
+
+            let change_username = ChangeUsername {
+                hwid: hwid.clone(),
+                new_username: nick.clone(),
+            };
+            return match EventHandler::handle_change_username(change_username, &self.context).await
+            {
+                Ok(()) => true,
+                Err(_) => {
+                    self.reply("433", &format!("{nick} :Nickname is already in use"))
+                        .await
+                }
+            };
+        }
+
+        // IRC users are identified by the nick they registered with, which must stay unique
+        let nick_hwid = format!("irc:{nick}");
+        let clients = self.context.connected_clients.lock().await;
+        let in_use = clients.contains_key(&nick_hwid)
+            || EventHandler::is_name_taken(&self.context, &clients, &nick_hwid, &nick).await;
+        drop(clients);
+        if in_use {
+            return self
+                .reply("433", &format!("{nick} :Nickname is already in use"))
+                .await;
+        }
+
+        self.nick = Some(nick);
+        true
+    }
+
+    async fn try_register(&mut self, outbox: &mpsc::UnboundedSender<Outgoing>) -> bool {
+        let (Some(nick), Some(_)) = (self.nick.clone(), &self.user) else {
+            return true;
+        };
+
+        let hwid = format!("irc:{nick}");
+        self.hwid = Some(hwid.clone());
+        self.span
+            .record("hwid", hwid.as_str())
+            .record("session", self.session_token.as_str());
+        if self.reject_banned().await {
+            return false;
+        }
+
+        let client = Client {
+            name: nick.clone(),
+            hwid: hwid.clone(),
+            session_token: self.session_token.clone(),
+            room: None,
+            address: self.address,
+        };
+        // Checked again under the same lock as the insert, someone might have taken the nick since
+        let mut clients = self.context.connected_clients.lock().await;
+        if clients.contains_key(&hwid)
+            || EventHandler::is_name_taken(&self.context, &clients, &hwid, &nick).await
+        {
+            drop(clients);
+            self.hwid = None;
+            self.nick = None;
+            return self
+                .reply("433", &format!("{nick} :Nickname is already in use"))
+                .await;
+        }
+        clients.insert(hwid.clone(), (outbox.clone(), client));
+        drop(clients);
+
+        tracing::info!(username = nick, "Registered");
+        let event = AuditEvent::Login {
+            address: self.address,
+            protocol: "irc".to_string(),
+            resumed: false,
+        };
+        let account = Account::new(&hwid, &nick);
+        self.context.audit.record(Some(account), None, event).await;
+
+        let config = self.context.config();
+        let server_name = &config.irc.server_name;
+        let welcome = format!(":Welcome to the {server_name} IRC bridge, {nick}");
+        if !self.reply("001", &welcome).await || !self.send_motd(&config.motd).await {
+            return false;
+        }
+
+        // Like native clients, IRC users are put into the default room without further checks
+        let room = self.context.config().default_room.clone();
+        if let Err(why) =
+            EventHandler::join_room(&self.context.connected_clients, &hwid, &room).await
+        {
+            tracing::error!(room, "Unable to join the default room! {why}");
+        }
+        EventHandler::welcome(&self.context, &hwid, &nick).await;
+        true
+    }
+
+    async fn send_motd(&mut self, motd: &str) -> bool {
+        if motd.is_empty() {
+            return self.reply("422", ":MOTD File is missing").await;
+        }
+
+        let server_name = self.context.config().irc.server_name.clone();
+        if !self
+            .reply("375", &format!(":- {server_name} Message of the day -"))
+            .await
+        {
+            return false;
+        }
+        for line in motd.lines() {
+            if !self.reply("372", &format!(":- {line}")).await {
+                return false;
+            }
+        }
+        self.reply("376", ":End of /MOTD command").await
+    }
+
+    async fn handle_join(&mut self, params: Vec<String>) -> bool {
+        let Some(channels) = params.first() else {
+            return self.reply("461", "JOIN :Not enough parameters").await;
+        };
+
+        let hwid = self.hwid.clone().unwrap_or_default();
+        if !moderation::throttle(&self.context, &hwid, Action::Join).await
+            || !moderation::authorize(&self.context, &hwid, Permission::JoinRooms).await
+        {
+            return true;
+        }
+
+        // Clients can only be in a single room, so joining multiple channels ends in the last one
+        for channel in channels.split(',') {
+            let joined = match channel.strip_prefix('#') {
+                Some(room) => EventHandler::join_room(&self.context.connected_clients, &hwid, room)
+                    .await
+                    .is_ok(),
+                None => false,
+            };
+
+            if !joined
+                && !self
+                    .reply("403", &format!("{channel} :No such channel"))
+                    .await
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    async fn handle_part(&mut self, params: Vec<String>) -> bool {
+        let Some(channels) = params.first() else {
+            return self.reply("461", "PART :Not enough parameters").await;
+        };
+
+        for channel in channels.split(',') {
+            if !self.is_current_channel(channel).await {
+                if !self
+                    .reply("442", &format!("{channel} :You're not on that channel"))
+                    .await
+                {
+                    return false;
+                }
+                continue;
+            }
+
+            let hwid = self.hwid.clone().unwrap_or_default();
+            EventHandler::leave_room(&self.context.connected_clients, &hwid).await;
+        }
+
+        true
+    }
+
+    async fn handle_names(&mut self, channel: &str) -> bool {
+        let room = channel.trim_start_matches('#');
+        let mut names = self
+            .context
+            .connected_clients
+            .lock()
+            .await
+            .values()
+            .filter(|(_, client)| client.room.as_deref() == Some(room))
+            .map(|(_, client)| client.name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+
+        self.reply("353", &format!("= {channel} :{}", names.join(" ")))
+            .await
+            && self
+                .reply("366", &format!("{channel} :End of /NAMES list"))
+                .await
+    }
+
+    async fn handle_privmsg(&mut self, params: Vec<String>) -> bool {
+        let mut params = params.into_iter();
+        let Some(target) = params.next() else {
+            return self.reply("411", ":No recipient given (PRIVMSG)").await;
+        };
+        let Some(content) = params.next().filter(|c| !c.is_empty()) else {
+            return self.reply("412", ":No text to send").await;
+        };
+        let hwid = self.hwid.clone().unwrap_or_default();
+
+        let action = Action::Message {
+            bytes: content.len(),
+        };
+        if !moderation::throttle(&self.context, &hwid, action).await {
+            return true;
+        }
+
+        if !target.starts_with('#') {
+            if !moderation::authorize(&self.context, &hwid, Permission::DirectMessages).await {
+                return true;
+            }
+
+            let context = &self.context;
+            return match EventHandler::direct_message(context, &hwid, &target, content).await {
+                Ok(()) => true,
+                Err(_) => {
+                    self.reply("401", &format!("{target} :No such nick/channel"))
+                        .await
+                }
+            };
+        }
+
+        if !self.is_current_channel(&target).await {
+            return self
+                .reply("404", &format!("{target} :Cannot send to channel"))
+                .await;
+        }
+
+        // CTCP ACTION is what IRC clients send for /me
+        let content = match content
+            .strip_prefix("\x01ACTION ")
+            .and_then(|action| action.strip_suffix('\x01'))
+        {
+            Some(action) => format!("/me {action}"),
+            None => content,
+        };
+
+        if !is_command(&content)
+            && !moderation::authorize(&self.context, &hwid, Permission::SendMessages).await
+        {
+            return true;
+        }
+
+        let message = ChatMessage {
+            hwid,
+            content,
+            reply_to: String::new(),
+        };
+        EventHandler::handle_send_message(message, &self.context).await;
+        true
+    }
+
+    /// Translates an event of the chat into IRC and sends it to the client.
+    async fn deliver(&mut self, event: Outgoing) -> bool {
+        let server_name = self.context.config().irc.server_name.clone();
+        let nick = self.nick.clone().unwrap_or_default();
+
+        let line = match event {
+            Outgoing::Message {
+                username,
+                room,
+                content,
+                ..
+            } => format!(
+                ":{username}!{username}@{server_name} PRIVMSG #{room} :{}",
+                sanitize(&content)
+            ),
+            Outgoing::Action {
+                username,
+                room,
+                content,
+            } => format!(
+                ":{username}!{username}@{server_name} PRIVMSG #{room} :\x01ACTION {}\x01",
+                sanitize(&content)
+            ),
+            Outgoing::DirectMessage {
+                username, content, ..
+            } => format!(
+                ":{username}!{username}@{server_name} PRIVMSG {nick} :{}",
+                sanitize(&content)
+            ),
+            Outgoing::System { content } => {
+                for line in content.lines() {
+                    if !self
+                        .send(&format!(":{server_name} NOTICE {nick} :{}", sanitize(line)))
+                        .await
+                    {
+                        return false;
+                    }
+                }
+                return true;
+            }
+            Outgoing::UserJoined { username, room } => {
+                let line = format!(":{username}!{username}@{server_name} JOIN #{room}");
+                if !username.eq(&nick) {
+                    return self.send(&line).await;
+                }
+
+                // Leaving the previous room is announced before, see `EventHandler::join_room`
+                self.room = Some(room.clone());
+                let channel = format!("#{room}");
+                return self.send(&line).await
+                    && self
+                        .reply("331", &format!("{channel} :No topic is set"))
+                        .await
+                    && self.handle_names(&channel).await;
+            }
+            Outgoing::UserLeft { username, room } => {
+                if username.eq(&nick) && self.room.as_deref() == Some(&room) {
+                    self.room = None;
+                }
+                format!(":{username}!{username}@{server_name} PART #{room}")
+            }
+            Outgoing::UsernameChanged {
+                old_username,
+                new_username,
+            } => {
+                if old_username.eq(&nick) {
+                    self.nick = Some(new_username.clone());
+                }
+                format!(":{old_username}!{old_username}@{server_name} NICK {new_username}")
+            }
+            Outgoing::ThreadMessage {
+                thread,
+                room,
+                username,
+                content,
+                ..
+            } => format!(
+                ":{server_name} NOTICE #{room} :[thread {thread}] {username}: {}",
+                sanitize(&content)
+            ),
+            // IRC clients highlight their nick themselves, they only miss mentions in other rooms
+            Outgoing::Mention {
+                room,
+                username,
+                content,
+                missed,
+                ..
+            } => {
+                if !missed && self.current_room().await.as_ref() == Some(&room) {
+                    return true;
+                }
+                format!(
+                    ":{server_name} NOTICE {nick} :{username} mentioned you in #{room}: {}",
+                    sanitize(&content)
+                )
+            }
+            // IRC has no message ids, so they are only mentioned in notices
+            Outgoing::MessageSent { .. } => return true,
+            Outgoing::MessageEdited {
+                id, room, content, ..
+            } => format!(
+                ":{server_name} NOTICE #{room} :Message {id} was edited: {}",
+                sanitize(&content)
+            ),
+            Outgoing::MessageDeleted { id, room } => {
+                format!(":{server_name} NOTICE #{room} :Message {id} was deleted")
+            }
+            Outgoing::ReactionsUpdated {
+                id,
+                room,
+                reactions,
+            } => {
+                let reactions = reactions
+                    .iter()
+                    .map(|(reaction, count)| format!("{reaction} {count}"))
+                    .collect::<Vec<_>>();
+                let reactions = match reactions.is_empty() {
+                    true => "none".to_string(),
+                    false => reactions.join(", "),
+                };
+                format!(":{server_name} NOTICE #{room} :Reactions to message {id}: {reactions}")
+            }
+            Outgoing::Welcome { unread, pending } => {
+                for (room, count) in unread {
+                    let line = format!(":{server_name} NOTICE {nick} :{count} unread in #{room}");
+                    if !self.send(&line).await {
+                        return false;
+                    }
+                }
+                if pending == 0 {
+                    return true;
+                }
+                format!(
+                    ":{server_name} NOTICE {nick} :{pending} messages arrived while you were away"
+                )
+            }
+            // Files can only be transferred by native clients, IRC users only learn about them
+            Outgoing::FileShared {
+                id,
+                room,
+                username,
+                name,
+                size,
+                ..
+            } => format!(
+                ":{server_name} NOTICE #{room} :{username} shared {} ({size} bytes), file id {id}",
+                sanitize(&name)
+            ),
+            Outgoing::UploadOffset { .. } | Outgoing::FileChunk { .. } => return true,
+            // IRC clients get a NAMES reply when they join instead
+            Outgoing::RoomMembers { .. } => return true,
+            Outgoing::Ping { token } => format!("PING :{token}"),
+            Outgoing::Pong { token } => format!(":{server_name} PONG {server_name} :{token}"),
+            Outgoing::Disconnect { reason } => {
+                let _ = self.send(&format!("ERROR :Closing link ({reason})")).await;
+                return false;
+            }
+            Outgoing::Shutdown {
+                reason,
+                reconnect_after,
+            } => {
+                let reconnect_after = reconnect_after.as_secs();
+                let line =
+                    format!("ERROR :Closing link ({reason}, reconnect in {reconnect_after}s)");
+                let _ = self.send(&line).await;
+                return false;
+            }
+        };
+
+        self.send(&line).await
+    }
+
+    /// The room this client is in according to the client list.
+    async fn current_room(&self) -> Option<String> {
+        let hwid = self.hwid.as_ref()?;
+        let lock = self.context.connected_clients.lock().await;
+        lock.get(hwid).and_then(|(_, client)| client.room.clone())
+    }
+
+    /// Closes the connection if the IP or the account is banned, returns `true` if it is.
+    async fn reject_banned(&mut self) -> bool {
+        let moderation = &self.context.moderation;
+        let Some(ban) = moderation
+            .find_ban(self.hwid.as_deref(), self.address)
+            .await
+        else {
+            return false;
+        };
+
+        tracing::info!("Rejected banned client");
+        self.context.metrics.auth_failure("irc", "banned");
+        let account = match (&self.hwid, &self.nick) {
+            (Some(hwid), Some(nick)) => Some(Account::new(hwid, nick)),
+            _ => None,
+        };
+        let event = AuditEvent::LoginRejected {
+            address: self.address,
+            protocol: "irc".to_string(),
+            reason: "banned".to_string(),
+        };
+        self.context.audit.record(account, None, event).await;
+        let _ = self
+            .send(&format!("ERROR :You are banned {}", ban.describe()))
+            .await;
+        true
+    }
+
+    async fn is_current_channel(&self, channel: &str) -> bool {
+        let room = self.current_room().await;
+        channel
+            .strip_prefix('#')
+            .is_some_and(|c| room.as_deref() == Some(c))
+    }
+
+    /// Sends a numeric reply, e.g. `:rust_chat 001 nick :Welcome`.
+    async fn reply(&mut self, numeric: &str, content: &str) -> bool {
+        let nick = self.nick.clone().unwrap_or_else(|| "*".to_string());
+        let server_name = self.context.config().irc.server_name.clone();
+        let line = format!(":{server_name} {numeric} {nick} {content}");
+        self.send(&line).await
+    }
+
+    async fn send(&mut self, line: &str) -> bool {
+        let line = format!("{line}\r\n");
+        match self.stream.write_all(line.as_bytes()).await {
+            Ok(_) => {
+                self.context.metrics.sent_irc(&line);
+                true
+            }
+            Err(why) => {
+                tracing::warn!("Unable to write to IRC stream! {why}");
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_nick, IrcMessage};
+
+    #[test]
+    fn test_parse_irc_message() {
+        let message = IrcMessage::parse(":nick!user@host PRIVMSG #general :Hello there\r\n");
+        assert_eq!(
+            message,
+            Some(IrcMessage {
+                prefix: Some("nick!user@host".to_string()),
+                command: "PRIVMSG".to_string(),
+                params: vec!["#general".to_string(), "Hello there".to_string()],
+            })
+        );
+
+        let message = IrcMessage::parse("user guest 0 * :Real Name").unwrap();
+        assert_eq!(message.command, "USER");
+        assert_eq!(message.params, vec!["guest", "0", "*", "Real Name"]);
+
+        assert_eq!(IrcMessage::parse(""), None);
+        assert_eq!(IrcMessage::parse(":prefix-only"), None);
+    }
+
+    #[test]
+    fn test_valid_nick() {
+        assert!(is_valid_nick("Phill030"));
+        assert!(is_valid_nick("[away]_nick"));
+        assert!(!is_valid_nick("0nick"));
+        assert!(!is_valid_nick("#channel"));
+        assert!(!is_valid_nick("with space"));
+        assert!(!is_valid_nick(""));
+    }
+}
@@ -0,0 +1,22 @@
+//! The chat server, also usable as a library to run it with additional commands, see
+//! [`server::Server::with_commands`].
+
+pub mod audit;
+pub mod cli;
+pub mod commands;
+pub mod event_handler;
+pub mod files;
+pub mod history;
+pub mod irc;
+pub mod limits;
+pub mod mailbox;
+pub mod mentions;
+pub mod metrics;
+pub mod moderation;
+pub mod reload;
+pub mod server;
+pub mod sessions;
+pub mod shutdown;
+pub mod threads;
+pub mod types;
+pub mod utils;
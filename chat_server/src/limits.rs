@@ -0,0 +1,232 @@
+use crate::types::ConnectionConfig;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Why a connection was closed before or shortly after it was accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    ServerFull,
+    TooManyFromIp,
+    AuthTimeout,
+    IdleTimeout,
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            Rejection::ServerFull => "The server is full",
+            Rejection::TooManyFromIp => "Too many connections from your IP",
+            Rejection::AuthTimeout => "Authentication took too long",
+            Rejection::IdleTimeout => "Connection timed out",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConnectionMetrics {
+    pub accepted: AtomicU64,
+    pub server_full: AtomicU64,
+    pub too_many_from_ip: AtomicU64,
+    pub auth_timeouts: AtomicU64,
+    pub idle_timeouts: AtomicU64,
+}
+
+impl ConnectionMetrics {
+    pub fn record(&self, rejection: Rejection) {
+        let counter = match rejection {
+            Rejection::ServerFull => &self.server_full,
+            Rejection::TooManyFromIp => &self.too_many_from_ip,
+            Rejection::AuthTimeout => &self.auth_timeouts,
+            Rejection::IdleTimeout => &self.idle_timeouts,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// e.g. "accepted: 10, server full: 0, ..."
+    pub fn summary(&self) -> String {
+        format!(
+            "accepted: {}, server full: {}, too many from IP: {}, auth timeouts: {}, idle timeouts: {}",
+            self.accepted.load(Ordering::Relaxed),
+            self.server_full.load(Ordering::Relaxed),
+            self.too_many_from_ip.load(Ordering::Relaxed),
+            self.auth_timeouts.load(Ordering::Relaxed),
+            self.idle_timeouts.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Counts the open connections of both listeners, globally and per IP.
+pub struct ConnectionLimiter {
+    config: RwLock<ConnectionConfig>,
+    total: Arc<AtomicUsize>,
+    // A std mutex, since it is released when a guard is dropped
+    per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    pub metrics: ConnectionMetrics,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: ConnectionConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            total: Arc::new(AtomicUsize::new(0)),
+            per_ip: Arc::new(Mutex::new(HashMap::new())),
+            metrics: ConnectionMetrics::default(),
+        }
+    }
+
+    /// Reserves a slot for the connection, which is freed again once the guard is dropped.
+    pub fn acquire(&self, address: IpAddr) -> Result<ConnectionGuard, Rejection> {
+        let result = self.try_acquire(address);
+        match &result {
+            Ok(_) => {
+                self.metrics.accepted.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(rejection) => self.metrics.record(*rejection),
+        }
+        result
+    }
+
+    fn try_acquire(&self, address: IpAddr) -> Result<ConnectionGuard, Rejection> {
+        let config = self.config.read().unwrap().clone();
+        let mut per_ip = self.per_ip.lock().unwrap();
+
+        if self.total.load(Ordering::SeqCst) >= config.max_connections {
+            return Err(Rejection::ServerFull);
+        }
+
+        let count = per_ip.entry(address).or_default();
+        if *count >= config.max_connections_per_ip {
+            return Err(Rejection::TooManyFromIp);
+        }
+
+        *count += 1;
+        self.total.fetch_add(1, Ordering::SeqCst);
+
+        Ok(ConnectionGuard {
+            address,
+            total: self.total.clone(),
+            per_ip: self.per_ip.clone(),
+        })
+    }
+
+    /// Applies new limits, connections which are already open are kept.
+    pub fn reconfigure(&self, config: ConnectionConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.total.load(Ordering::SeqCst)
+    }
+}
+
+pub struct ConnectionGuard {
+    address: IpAddr,
+    total: Arc<AtomicUsize>,
+    per_ip: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut per_ip = self.per_ip.lock().unwrap();
+        if let Some(count) = per_ip.get_mut(&self.address) {
+            *count -= 1;
+            if *count == 0 {
+                per_ip.remove(&self.address);
+            }
+        }
+        self.total.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// When a connection last chatted. Heartbeats keep a connection open, but clients which only
+/// send heartbeats become idle.
+pub struct Activity {
+    last: Instant,
+}
+
+impl Activity {
+    pub fn new() -> Self {
+        Self {
+            last: Instant::now(),
+        }
+    }
+
+    /// Records a received message, returns whether the connection has been idle for longer than
+    /// `idle_timeout`. A zero timeout keeps idle connections open.
+    pub fn record(&mut self, is_heartbeat: bool, idle_timeout: Duration) -> bool {
+        if !is_heartbeat {
+            self.last = Instant::now();
+            return false;
+        }
+        !idle_timeout.is_zero() && self.last.elapsed() >= idle_timeout
+    }
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Activity, ConnectionLimiter, Rejection};
+    use crate::types::ConnectionConfig;
+    use std::{net::IpAddr, sync::atomic::Ordering, time::Duration};
+    use tokio::time;
+
+    #[test]
+    fn test_connection_limits() {
+        let limiter = ConnectionLimiter::new(ConnectionConfig {
+            max_connections: 3,
+            max_connections_per_ip: 2,
+            ..Default::default()
+        });
+        let first: IpAddr = "10.0.0.1".parse().unwrap();
+        let second: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let a = limiter.acquire(first).unwrap();
+        let _b = limiter.acquire(first).unwrap();
+        assert_eq!(limiter.acquire(first).err(), Some(Rejection::TooManyFromIp));
+
+        let _c = limiter.acquire(second).unwrap();
+        assert_eq!(limiter.acquire(second).err(), Some(Rejection::ServerFull));
+
+        drop(a);
+        assert_eq!(limiter.open_connections(), 2);
+        assert!(limiter.acquire(first).is_ok());
+
+        let metrics = &limiter.metrics;
+        assert_eq!(metrics.accepted.load(Ordering::Relaxed), 4);
+        assert_eq!(metrics.too_many_from_ip.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.server_full.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_activity() {
+        let idle_timeout = Duration::from_secs(60);
+        let mut activity = Activity::new();
+
+        time::advance(Duration::from_secs(45)).await;
+        assert!(!activity.record(true, idle_timeout));
+        assert!(!activity.record(false, idle_timeout));
+        // Only chatting resets the timer, heartbeats don't
+        time::advance(Duration::from_secs(45)).await;
+        assert!(!activity.record(true, idle_timeout));
+        time::advance(Duration::from_secs(15)).await;
+        assert!(activity.record(true, idle_timeout));
+
+        // Idle connections are kept open unless a timeout is configured
+        assert!(!activity.record(true, Duration::ZERO));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{history::StoredMessage, types::MailboxConfig, utils::unix_timestamp};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    sync::RwLock,
+};
+use tokio::sync::Mutex;
+
+/// Something which was sent to an account while it was offline.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Pending {
+    DirectMessage {
+        username: String,
+        content: String,
+        sent_at: u64,
+    },
+    Mention {
+        id: String,
+        room: String,
+        username: String,
+        content: String,
+        mention: String,
+        sent_at: u64,
+    },
+}
+
+/// How far an account has read a room, the messages sent after it are unread.
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub struct ReadMarker {
+    /// Compared first, since message ids start over when the server restarts
+    pub sent_at: u64,
+    pub id: u64,
+}
+
+impl From<&StoredMessage> for ReadMarker {
+    fn from(message: &StoredMessage) -> Self {
+        Self {
+            sent_at: message.sent_at,
+            id: message.id.parse().unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[serde(default)]
+struct AccountMailbox {
+    /// The name the account used last, to find it while it is offline
+    name: String,
+    /// Unix timestamp of the last login or logout
+    last_seen: u64,
+    pending: VecDeque<Pending>,
+    read: HashMap<String, ReadMarker>,
+}
+
+/// Everything which is persisted, keyed by HWID.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[serde(default)]
+struct MailboxState {
+    accounts: HashMap<String, AccountMailbox>,
+}
+
+/// Keeps direct messages and mentions for offline accounts, and how far accounts read each room.
+pub struct Mailbox {
+    state: Mutex<MailboxState>,
+    config: RwLock<MailboxConfig>,
+}
+
+impl Mailbox {
+    pub async fn load(config: MailboxConfig) -> io::Result<Self> {
+        let state = match config.enabled {
+            true => match tokio::fs::read(&config.file).await {
+                Ok(contents) => serde_json::from_slice(&contents)
+                    .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?,
+                Err(why) if why.kind() == io::ErrorKind::NotFound => MailboxState::default(),
+                Err(why) => return Err(why),
+            },
+            false => MailboxState::default(),
+        };
+
+        Ok(Self {
+            state: Mutex::new(state),
+            config: RwLock::new(config),
+        })
+    }
+
+    /// Applies a new capacity, the file is only read on startup.
+    pub fn reconfigure(&self, config: MailboxConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    fn enabled(&self) -> bool {
+        self.config.read().unwrap().enabled
+    }
+
+    async fn save(&self, state: &MailboxState) {
+        let contents = match serde_json::to_vec(state) {
+            Ok(contents) => contents,
+            Err(why) => {
+                tracing::error!("Unable to serialize the mailbox! {why}");
+                return;
+            }
+        };
+
+        let file = self.config.read().unwrap().file.clone();
+        if let Err(why) = tokio::fs::write(&file, contents).await {
+            tracing::error!("Unable to write {}! {why}", file.display());
+        }
+    }
+
+    /// Remembers the name of the account and takes everything which was sent while it was away.
+    pub async fn login(&self, hwid: &str, name: &str) -> Vec<Pending> {
+        if !self.enabled() {
+            return Vec::new();
+        }
+
+        let mut state = self.state.lock().await;
+        let account = state.accounts.entry(hwid.to_string()).or_default();
+        account.name = name.to_string();
+        account.last_seen = unix_timestamp();
+        let pending = account.pending.drain(..).collect();
+
+        self.save(&state).await;
+        pending
+    }
+
+    /// Also saves the read markers, which are only kept in memory while the account is online.
+    pub async fn logout(&self, hwid: &str) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut state = self.state.lock().await;
+        if let Some(account) = state.accounts.get_mut(hwid) {
+            account.last_seen = unix_timestamp();
+            self.save(&state).await;
+        }
+    }
+
+    /// The old name is free for other accounts from now on.
+    pub async fn rename(&self, hwid: &str, name: &str) {
+        let mut state = self.state.lock().await;
+        if let Some(account) = state.accounts.get_mut(hwid) {
+            account.name = name.to_string();
+            self.save(&state).await;
+        }
+    }
+
+    /// The account which owns the name, see `EventHandler::is_name_taken`. Names were not
+    /// unique in older mailboxes, those go to the account which used the name most recently.
+    pub async fn account_named(&self, name: &str) -> Option<String> {
+        let state = self.state.lock().await;
+        let accounts = state.accounts.iter();
+        accounts
+            .filter(|(_, account)| account.name.eq(name))
+            .max_by_key(|(_, account)| account.last_seen)
+            .map(|(hwid, _)| hwid.clone())
+    }
+
+    /// Keeps something for an offline account, dropping the oldest if its mailbox is full.
+    /// Returns `false` if the account is unknown.
+    pub async fn store(&self, hwid: &str, pending: Pending) -> bool {
+        let capacity = self.config.read().unwrap().capacity;
+        let mut state = self.state.lock().await;
+        let Some(account) = state.accounts.get_mut(hwid) else {
+            return false;
+        };
+        if capacity == 0 {
+            return true;
+        }
+
+        if account.pending.len() >= capacity {
+            account.pending.pop_front();
+        }
+        account.pending.push_back(pending);
+        self.save(&state).await;
+        true
+    }
+
+    /// Markers only ever move forward, marking an older message read changes nothing.
+    pub async fn mark_read(&self, hwid: &str, room: &str, marker: ReadMarker) {
+        let mut state = self.state.lock().await;
+        let Some(account) = state.accounts.get_mut(hwid) else {
+            return;
+        };
+
+        let read = account.read.entry(room.to_string()).or_insert(marker);
+        *read = marker.max(*read);
+    }
+
+    pub async fn read_markers(&self, hwid: &str) -> HashMap<String, ReadMarker> {
+        let state = self.state.lock().await;
+        let account = state.accounts.get(hwid);
+        account.map(|a| a.read.clone()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mailbox, Pending, ReadMarker};
+    use crate::types::MailboxConfig;
+
+    fn direct_message(content: &str) -> Pending {
+        Pending::DirectMessage {
+            username: "Alice".to_string(),
+            content: content.to_string(),
+            sent_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mailbox() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = MailboxConfig {
+            file: directory.path().join("mailbox.json"),
+            capacity: 2,
+            ..Default::default()
+        };
+        let mailbox = Mailbox::load(config.clone()).await.unwrap();
+
+        assert!(!mailbox.store("bob", direct_message("unknown")).await);
+        assert!(mailbox.login("bob", "Bob").await.is_empty());
+        mailbox.logout("bob").await;
+        assert_eq!(mailbox.account_named("Bob").await.as_deref(), Some("bob"));
+
+        for content in ["1", "2", "3"] {
+            assert!(mailbox.store("bob", direct_message(content)).await);
+        }
+        let marker = ReadMarker { sent_at: 5, id: 2 };
+        mailbox.mark_read("bob", "general", marker).await;
+        mailbox
+            .mark_read("bob", "general", ReadMarker { sent_at: 4, id: 9 })
+            .await;
+        mailbox.logout("bob").await;
+
+        // Everything survives a restart
+        let mailbox = Mailbox::load(config).await.unwrap();
+        let pending = mailbox.login("bob", "Bobby").await;
+        assert_eq!(pending, [direct_message("2"), direct_message("3")]);
+        assert!(mailbox.login("bob", "Bobby").await.is_empty());
+        assert_eq!(mailbox.read_markers("bob").await["general"], marker);
+        assert_eq!(mailbox.account_named("Bob").await, None);
+    }
+}
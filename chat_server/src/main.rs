@@ -1,26 +1,64 @@
 extern crate chat_macro;
 
-use config::config::ConfigManager;
-use server::Server;
-
-pub mod config;
-pub mod event_handler;
-pub mod server;
-pub mod types;
-pub mod utils;
+use chat_server::{
+    audit,
+    cli::{AuditCommand, Cli, Command},
+    reload,
+    server::Server,
+    types::Config,
+};
+use chat_shared::{config, logging};
+use clap::Parser;
+use std::process;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    let cli = Cli::parse();
 
     // let db = Surreal::new::<Mem>(()).await.unwrap();
     // db.use_ns("chat").use_db("clients").await.unwrap();
     // let db_client = Arc::new(db);
 
-    let config = ConfigManager::initialize_or_create().await.unwrap();
-    Server::create(config.endpoint)?;
+    // The logger needs the config, so errors are printed directly
+    let path = cli.config_path();
+    if cli.write_config {
+        if let Err(why) = config::write_default::<Config>(&path).await {
+            eprintln!("{why}");
+            process::exit(1);
+        }
+        println!("Wrote the default config to {}", path.display());
+        return Ok(());
+    }
+
+    // Inspecting the audit log must not depend on a config the server could start with
+    if let Some(Command::Audit(args)) = cli.command.clone() {
+        let file = match args.file {
+            Some(file) => file,
+            None => audit::configured_file(&path).await,
+        };
+        let code = match args.command {
+            AuditCommand::Verify => audit::run_verify(&file).await,
+            AuditCommand::Query(query) => audit::run_query(&file, &query.into()).await,
+        };
+        process::exit(code);
+    }
+
+    let mut config = match config::load::<Config>(&path).await {
+        Ok(config) => config,
+        Err(why) => {
+            eprintln!("{why}");
+            process::exit(1);
+        }
+    };
+    cli.apply(&mut config);
+    if let Err(why) = logging::init(&config.log, cli.log_filters().as_deref()) {
+        eprintln!("{why}");
+        process::exit(1);
+    }
 
-    Ok(())
+    let server = Server::create(config).await?;
+    reload::watch(path, cli, server.context.clone());
+    server.listen().await
 }
 
 #[cfg(test)]
@@ -33,8 +71,11 @@ mod tests {
     #[tokio::test]
     async fn test_server_serialization() {
         let x = BroadcastMessage {
+            id: "ID".to_string(),
             username: "USERNAME".to_string(),
             content: "CONTENT".to_string(),
+            timestamp: "1700000000".to_string(),
+            reply_to: String::new(),
         };
         let serialized = x.serialize().await.unwrap();
         let deserialized = BroadcastMessage::deserialize(&serialized).await.unwrap();
@@ -46,6 +87,7 @@ mod tests {
         let x = ChatMessage {
             hwid: "HWID".to_string(),
             content: "CONTENT".to_string(),
+            reply_to: "ID".to_string(),
         };
         let serialized = x.serialize().await.unwrap();
         let deserialized = ChatMessage::deserialize(&serialized).await.unwrap();
@@ -0,0 +1,89 @@
+/// Punctuation which ends a sentence rather than a username, e.g. in "thanks @bob!".
+const TRAILING: &[char] = &['.', ',', ':', ';', '!', '?', ')', '\'', '"'];
+
+/// A mention in a chat message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mention {
+    /// `@username`, possibly followed by punctuation
+    User(String),
+    /// `@room` or `@here`, everyone in the room of the message
+    Room(String),
+}
+
+impl Mention {
+    /// Whether the client with this name in this room is mentioned, given the room of the message.
+    pub fn matches(&self, username: &str, room: Option<&str>, message_room: &str) -> bool {
+        match self {
+            Mention::User(_) => self.usernames().contains(&username),
+            Mention::Room(_) => room == Some(message_room),
+        }
+    }
+
+    /// The names a user mention could refer to, with and without trailing punctuation.
+    pub fn usernames(&self) -> Vec<&str> {
+        match self {
+            Mention::User(name) => {
+                let trimmed = name.trim_end_matches(TRAILING);
+                match trimmed.eq(name) {
+                    true => vec![name.as_str()],
+                    false => vec![name.as_str(), trimmed],
+                }
+            }
+            Mention::Room(_) => Vec::new(),
+        }
+    }
+
+    /// How the mention was written, e.g. `@here`.
+    pub fn text(&self) -> String {
+        match self {
+            Mention::User(name) => format!("@{}", name.trim_end_matches(TRAILING)),
+            Mention::Room(keyword) => format!("@{keyword}"),
+        }
+    }
+}
+
+/// The mentions at the start of words, each only once.
+pub fn parse(content: &str) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+    for word in content.split_whitespace() {
+        let Some(name) = word.strip_prefix('@').filter(|name| !name.is_empty()) else {
+            continue;
+        };
+
+        let mention = match name.trim_end_matches(TRAILING) {
+            keyword @ ("room" | "here") => Mention::Room(keyword.to_string()),
+            "" => continue,
+            _ => Mention::User(name.to_string()),
+        };
+        if !mentions.contains(&mention) {
+            mentions.push(mention);
+        }
+    }
+
+    mentions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Mention};
+
+    #[test]
+    fn test_mentions() {
+        let mentions = parse("@bob, @here look at this! mail@example.com @bob, @ @!");
+        assert_eq!(
+            mentions,
+            [
+                Mention::User("bob,".to_string()),
+                Mention::Room("here".to_string())
+            ]
+        );
+
+        assert!(mentions[0].matches("bob", None, "general"));
+        assert!(mentions[0].matches("bob,", None, "general"));
+        assert!(!mentions[0].matches("bobby", None, "general"));
+        assert_eq!(mentions[0].text(), "@bob");
+        assert_eq!(mentions[0].usernames(), ["bob,", "bob"]);
+        assert!(mentions[1].matches("alice", Some("general"), "general"));
+        assert!(!mentions[1].matches("alice", Some("rust"), "general"));
+    }
+}
@@ -0,0 +1,344 @@
+use crate::types::ServerContext;
+use chat_shared::error::DeserializerError;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+/// Upper bounds of the broadcast latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05, 0.25,
+];
+
+/// Requests larger than this are answered without reading the rest.
+const MAX_REQUEST_SIZE: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// IRC commands which get their own label, anything else is counted as `other`.
+const IRC_COMMANDS: &[&str] = &[
+    "NICK", "USER", "PING", "PONG", "CAP", "QUIT", "JOIN", "PART", "NAMES", "PRIVMSG",
+];
+
+/// A counter per set of labels, e.g. `protocol="native",type="ping"`.
+#[derive(Debug, Default)]
+struct Family(Mutex<BTreeMap<String, u64>>);
+
+impl Family {
+    fn add(&self, labels: String, amount: u64) {
+        *self.0.lock().unwrap().entry(labels).or_default() += amount;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+        for (labels, value) in self.0.lock().unwrap().iter() {
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    }
+}
+
+/// Buckets are cumulative, like Prometheus expects them.
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            if seconds <= *bound {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            let value = bucket.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {value}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)).as_secs_f64();
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {sum}\n{name}_count {count}");
+    }
+}
+
+/// Counters of the traffic of both listeners, exposed on `/metrics` if enabled.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    messages_received: Family,
+    messages_sent: Family,
+    bytes_received: Family,
+    bytes_sent: Family,
+    deserialize_errors: Family,
+    auth_failures: Family,
+    broadcast_latency: Histogram,
+}
+
+impl Metrics {
+    /// `kind` is the message type, e.g. `chat_message`, or the IRC command.
+    pub fn received(&self, protocol: &str, kind: &str, bytes: usize) {
+        self.messages_received
+            .add(format!("protocol=\"{protocol}\",type=\"{kind}\""), 1);
+        self.bytes_received
+            .add(format!("protocol=\"{protocol}\""), bytes as u64);
+    }
+
+    pub fn sent(&self, protocol: &str, kind: &str, bytes: usize) {
+        self.messages_sent
+            .add(format!("protocol=\"{protocol}\",type=\"{kind}\""), 1);
+        self.bytes_sent
+            .add(format!("protocol=\"{protocol}\""), bytes as u64);
+    }
+
+    pub fn received_irc(&self, line: &str) {
+        let command = irc_command(line).to_ascii_uppercase();
+        let kind = match IRC_COMMANDS.contains(&command.as_str()) {
+            true => command.as_str(),
+            false => "other",
+        };
+        // The line terminator was stripped by the codec
+        self.received("irc", kind, line.len() + 2);
+    }
+
+    /// `line` includes the line terminator.
+    pub fn sent_irc(&self, line: &str) {
+        self.sent("irc", irc_command(line), line.len());
+    }
+
+    pub fn deserialize_error(&self, error: &DeserializerError) {
+        self.deserialize_errors
+            .add(format!("kind=\"{}\"", error.kind()), 1);
+    }
+
+    /// `reason` is one of `invalid`, `banned` or `timeout`.
+    pub fn auth_failure(&self, protocol: &str, reason: &str) {
+        self.auth_failures
+            .add(format!("protocol=\"{protocol}\",reason=\"{reason}\""), 1);
+    }
+
+    /// How long it took to hand a message to every member of the room.
+    pub fn broadcast(&self, latency: Duration) {
+        self.broadcast_latency.observe(latency);
+    }
+
+    fn render(&self, out: &mut String) {
+        self.messages_received.render(
+            out,
+            "chat_messages_received_total",
+            "Messages received from clients.",
+        );
+        self.messages_sent
+            .render(out, "chat_messages_sent_total", "Messages sent to clients.");
+        self.bytes_received.render(
+            out,
+            "chat_received_bytes_total",
+            "Bytes of the messages received from clients.",
+        );
+        self.bytes_sent.render(
+            out,
+            "chat_sent_bytes_total",
+            "Bytes of the messages sent to clients.",
+        );
+        self.deserialize_errors.render(
+            out,
+            "chat_deserialize_errors_total",
+            "Messages which could not be deserialized.",
+        );
+        self.auth_failures.render(
+            out,
+            "chat_auth_failures_total",
+            "Connections which failed to authenticate.",
+        );
+        self.broadcast_latency.render(
+            out,
+            "chat_broadcast_latency_seconds",
+            "Time to hand a message to every member of the room.",
+        );
+    }
+}
+
+/// The command of an IRC line, skipping its prefix.
+fn irc_command(line: &str) -> &str {
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some(prefix) if prefix.starts_with(':') => words.next().unwrap_or_default(),
+        Some(command) => command,
+        None => "",
+    }
+}
+
+/// All metrics in the Prometheus text format.
+pub async fn render(context: &ServerContext) -> String {
+    let mut out = String::new();
+    let clients = context.connected_clients.lock().await.len();
+    let _ = writeln!(
+        out,
+        "# HELP chat_connected_clients Authenticated clients.\n\
+         # TYPE chat_connected_clients gauge\n\
+         chat_connected_clients {clients}"
+    );
+    let connections = context.connection_limiter.open_connections();
+    let _ = writeln!(
+        out,
+        "# HELP chat_open_connections Open connections, including unauthenticated ones.\n\
+         # TYPE chat_open_connections gauge\n\
+         chat_open_connections {connections}"
+    );
+
+    let metrics = &context.connection_limiter.metrics;
+    let _ = writeln!(
+        out,
+        "# HELP chat_connections_total Connections by how they ended up.\n\
+         # TYPE chat_connections_total counter"
+    );
+    for (result, counter) in [
+        ("accepted", &metrics.accepted),
+        ("server_full", &metrics.server_full),
+        ("too_many_from_ip", &metrics.too_many_from_ip),
+        ("auth_timeout", &metrics.auth_timeouts),
+        ("idle_timeout", &metrics.idle_timeouts),
+    ] {
+        let value = counter.load(Ordering::Relaxed);
+        let _ = writeln!(out, "chat_connections_total{{result=\"{result}\"}} {value}");
+    }
+
+    context.metrics.render(&mut out);
+    out
+}
+
+/// Serves the metrics on `GET /metrics` until the server shuts down.
+pub async fn listen(listener: TcpListener, context: Arc<ServerContext>) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = context.shutdown.cancelled() => break,
+        };
+
+        match accepted {
+            Ok((stream, _)) => {
+                tokio::spawn(respond(stream, context.clone()));
+            }
+            Err(why) => tracing::error!("Error accepting metrics connection! {why}"),
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, context: Arc<ServerContext>) {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    // Only the request line matters, the headers are read so the client isn't reset
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        match timeout(REQUEST_TIMEOUT, stream.read(&mut buffer)).await {
+            Ok(Ok(0)) | Ok(Err(_)) | Err(_) => return,
+            Ok(Ok(read)) => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let request_line = request.lines().next().unwrap_or_default();
+    let (status, body) = match route(request_line) {
+        Route::Metrics => ("200 OK", render(&context).await),
+        Route::NotFound => ("404 Not Found", "Not Found\n".to_string()),
+        Route::MethodNotAllowed => ("405 Method Not Allowed", "Method Not Allowed\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    );
+    if let Err(why) = stream.write_all(response.as_bytes()).await {
+        tracing::warn!("Unable to answer metrics request! {why}");
+    }
+    let _ = stream.shutdown().await;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    Metrics,
+    NotFound,
+    MethodNotAllowed,
+}
+
+/// e.g. `GET /metrics HTTP/1.1`
+fn route(request_line: &str) -> Route {
+    let mut parts = request_line.split(' ');
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next());
+    let path = target.map(|target| target.split('?').next().unwrap_or_default());
+
+    match (method, path) {
+        ("GET", Some("/metrics")) => Route::Metrics,
+        ("GET", _) => Route::NotFound,
+        _ => Route::MethodNotAllowed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{irc_command, route, Metrics, Route};
+    use chat_shared::error::DeserializerError;
+    use std::time::Duration;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("GET /metrics HTTP/1.1"), Route::Metrics);
+        assert_eq!(route("GET /metrics?name[]=x HTTP/1.1"), Route::Metrics);
+        assert_eq!(route("GET / HTTP/1.1"), Route::NotFound);
+        assert_eq!(route("POST /metrics HTTP/1.1"), Route::MethodNotAllowed);
+        assert_eq!(route(""), Route::MethodNotAllowed);
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::default();
+        metrics.received("native", "chat_message", 20);
+        metrics.received("native", "chat_message", 30);
+        metrics.received_irc("privmsg #general :hi");
+        metrics.received_irc("WHOIS someone");
+        metrics.sent_irc(":rust_chat 001 nick :Welcome\r\n");
+        metrics.deserialize_error(&DeserializerError::InvalidBufferLength);
+        metrics.broadcast(Duration::from_micros(300));
+
+        let mut out = String::new();
+        metrics.render(&mut out);
+        let lines = out.lines().collect::<Vec<_>>();
+        for expected in [
+            "chat_messages_received_total{protocol=\"native\",type=\"chat_message\"} 2",
+            "chat_messages_received_total{protocol=\"irc\",type=\"PRIVMSG\"} 1",
+            "chat_messages_received_total{protocol=\"irc\",type=\"other\"} 1",
+            "chat_received_bytes_total{protocol=\"native\"} 50",
+            "chat_messages_sent_total{protocol=\"irc\",type=\"001\"} 1",
+            "chat_deserialize_errors_total{kind=\"invalid_buffer_length\"} 1",
+            "chat_broadcast_latency_seconds_bucket{le=\"0.00025\"} 0",
+            "chat_broadcast_latency_seconds_bucket{le=\"0.0005\"} 1",
+            "chat_broadcast_latency_seconds_bucket{le=\"+Inf\"} 1",
+            "chat_broadcast_latency_seconds_count 1",
+            "# TYPE chat_broadcast_latency_seconds histogram",
+        ] {
+            assert!(lines.contains(&expected), "{expected} missing in\n{out}");
+        }
+
+        assert_eq!(irc_command("ERROR :Closing link"), "ERROR");
+        assert_eq!(irc_command(""), "");
+    }
+}
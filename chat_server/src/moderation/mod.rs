@@ -0,0 +1,381 @@
+use crate::{
+    audit::{Account, AuditEvent},
+    commands::CommandError,
+    event_handler::EventHandler,
+    types::{Client, ModerationConfig, Outgoing, ServerContext},
+    utils::{expires_at, format_duration, unix_timestamp},
+};
+use std::{collections::HashMap, io, net::IpAddr, sync::RwLock};
+use tokio::sync::Mutex;
+
+pub mod rate_limit;
+pub mod roles;
+
+pub use rate_limit::{Action, RateLimiter, Verdict};
+pub use roles::{Permission, Role};
+
+/// A ban or a mute, together with who issued it and why.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Sanction {
+    pub username: String,
+    pub reason: String,
+    pub issued_by: String,
+    /// Unix timestamp the sanction ends at, `None` if it is permanent
+    pub until: Option<u64>,
+}
+
+impl Sanction {
+    pub fn is_active(&self) -> bool {
+        self.until.is_none_or(|until| until > unix_timestamp())
+    }
+
+    /// e.g. "for 4m 30s (Spamming)" or "permanently (Spamming)"
+    pub fn describe(&self) -> String {
+        let duration = match self.until {
+            Some(until) => format!(
+                "for {}",
+                format_duration(until.saturating_sub(unix_timestamp()))
+            ),
+            None => "permanently".to_string(),
+        };
+
+        if self.reason.is_empty() {
+            duration
+        } else {
+            format!("{duration} ({})", self.reason)
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+pub struct AccountRoles {
+    /// Used in every room without a role of its own
+    pub global: Option<Role>,
+    pub rooms: HashMap<String, Role>,
+}
+
+/// Everything which is persisted, keyed by HWID (respectively IP).
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
+#[serde(default)]
+struct ModerationState {
+    roles: HashMap<String, AccountRoles>,
+    banned_accounts: HashMap<String, Sanction>,
+    banned_ips: HashMap<IpAddr, Sanction>,
+    mutes: HashMap<String, Sanction>,
+}
+
+pub struct Moderation {
+    state: Mutex<ModerationState>,
+    config: RwLock<ModerationConfig>,
+}
+
+impl Moderation {
+    pub async fn load(config: ModerationConfig) -> io::Result<Self> {
+        let state = match tokio::fs::read(&config.file).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => ModerationState::default(),
+            Err(why) => return Err(why),
+        };
+
+        Ok(Self {
+            state: Mutex::new(state),
+            config: RwLock::new(config),
+        })
+    }
+
+    /// Applies new owners and the default role, the file is only read on startup.
+    pub fn reconfigure(&self, config: ModerationConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    async fn save(&self, state: &ModerationState) {
+        let contents = match serde_json::to_vec_pretty(state) {
+            Ok(contents) => contents,
+            Err(why) => {
+                tracing::error!("Unable to serialize moderation state! {why}");
+                return;
+            }
+        };
+
+        let file = self.config.read().unwrap().file.clone();
+        if let Err(why) = tokio::fs::write(&file, contents).await {
+            tracing::error!("Unable to write {}! {why}", file.display());
+        }
+    }
+
+    /// The role of an account in `room`, falling back to its global role.
+    pub async fn role_of(&self, hwid: &str, room: Option<&str>) -> Role {
+        let default_role = {
+            let config = self.config.read().unwrap();
+            if config.owners.iter().any(|owner| owner.eq(hwid)) {
+                return Role::Owner;
+            }
+            config.default_role
+        };
+
+        let state = self.state.lock().await;
+        let Some(roles) = state.roles.get(hwid) else {
+            return default_role;
+        };
+
+        room.and_then(|room| roles.rooms.get(room))
+            .or(roles.global.as_ref())
+            .copied()
+            .unwrap_or(default_role)
+    }
+
+    /// Assigns a role in `room`, or globally if no room is given.
+    pub async fn set_role(&self, hwid: &str, room: Option<&str>, role: Role) {
+        let mut state = self.state.lock().await;
+        let roles = state.roles.entry(hwid.to_string()).or_default();
+
+        match room {
+            Some(room) => {
+                roles.rooms.insert(room.to_string(), role);
+            }
+            None => roles.global = Some(role),
+        }
+        self.save(&state).await;
+    }
+
+    /// Whether the role of the client in its current room grants the permission, for global
+    /// permissions its global role.
+    pub async fn has_permission(&self, client: &Client, permission: Permission) -> bool {
+        self.has_permission_in(client, client.room.as_deref(), permission)
+            .await
+    }
+
+    /// Like `has_permission`, but for `room` instead of the room the client is in.
+    pub async fn has_permission_in(
+        &self,
+        client: &Client,
+        room: Option<&str>,
+        permission: Permission,
+    ) -> bool {
+        let room = room.filter(|_| !permission.is_global());
+        self.role_of(&client.hwid, room).await.has(permission)
+    }
+
+    /// Like `has_permission`, but also takes mutes into account.
+    pub async fn check(&self, client: &Client, permission: Permission) -> Result<(), CommandError> {
+        self.check_in(client, client.room.as_deref(), permission)
+            .await
+    }
+
+    /// Like `check`, but for `room` instead of the room the client is in.
+    pub async fn check_in(
+        &self,
+        client: &Client,
+        room: Option<&str>,
+        permission: Permission,
+    ) -> Result<(), CommandError> {
+        if !self.has_permission_in(client, room, permission).await {
+            return Err(CommandError::PermissionDenied);
+        }
+
+        if matches!(
+            permission,
+            Permission::SendMessages | Permission::DirectMessages
+        ) {
+            if let Some(mute) = self.active_mute(&client.hwid).await {
+                return Err(CommandError::Failed(format!(
+                    "You are muted {}",
+                    mute.describe()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moderators may only act on clients with a lower global role than their own, since
+    /// sanctions apply to the whole server.
+    pub async fn outranks(&self, actor: &Client, target: &Client) -> bool {
+        self.role_of(&actor.hwid, None).await > self.role_of(&target.hwid, None).await
+    }
+
+    /// Bans the account and, if given, the IP it connected from.
+    pub async fn ban(&self, hwid: &str, ip: Option<IpAddr>, sanction: Sanction) {
+        let mut state = self.state.lock().await;
+        if let Some(ip) = ip {
+            state.banned_ips.insert(ip, sanction.clone());
+        }
+        state.banned_accounts.insert(hwid.to_string(), sanction);
+        self.save(&state).await;
+    }
+
+    /// Lifts all bans of the username, returns `false` if there were none.
+    pub async fn unban(&self, username: &str) -> bool {
+        let mut state = self.state.lock().await;
+        let count = state.banned_accounts.len() + state.banned_ips.len();
+        state
+            .banned_accounts
+            .retain(|_, ban| !ban.username.eq(username));
+        state.banned_ips.retain(|_, ban| !ban.username.eq(username));
+
+        let lifted = count != state.banned_accounts.len() + state.banned_ips.len();
+        if lifted {
+            self.save(&state).await;
+        }
+        lifted
+    }
+
+    /// Returns the active ban of the account or IP, if there is one.
+    pub async fn find_ban(&self, hwid: Option<&str>, ip: IpAddr) -> Option<Sanction> {
+        let state = self.state.lock().await;
+        hwid.and_then(|hwid| state.banned_accounts.get(hwid))
+            .into_iter()
+            .chain(state.banned_ips.get(&ip))
+            .find(|ban| ban.is_active())
+            .cloned()
+    }
+
+    pub async fn mute(&self, hwid: &str, sanction: Sanction) {
+        let mut state = self.state.lock().await;
+        state.mutes.insert(hwid.to_string(), sanction);
+        self.save(&state).await;
+    }
+
+    pub async fn unmute(&self, hwid: &str) -> bool {
+        let mut state = self.state.lock().await;
+        let unmuted = state.mutes.remove(hwid).is_some();
+        if unmuted {
+            self.save(&state).await;
+        }
+        unmuted
+    }
+
+    async fn active_mute(&self, hwid: &str) -> Option<Sanction> {
+        let mut state = self.state.lock().await;
+        match state.mutes.get(hwid) {
+            Some(mute) if mute.is_active() => Some(mute.clone()),
+            Some(_) => {
+                // Expired mutes are cleaned up the next time they are looked at
+                state.mutes.remove(hwid);
+                self.save(&state).await;
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+/// Checks the permission before an event is handled and tells the client if it is denied.
+pub async fn authorize(context: &ServerContext, hwid: &str, permission: Permission) -> bool {
+    let Some((_, client)) = context.connected_clients.lock().await.get(hwid).cloned() else {
+        return false;
+    };
+
+    match context.moderation.check(&client, permission).await {
+        Ok(()) => true,
+        Err(why) => {
+            let event = Outgoing::System {
+                content: why.to_string(),
+            };
+            EventHandler::send_to(&context.connected_clients, hwid, event).await;
+            false
+        }
+    }
+}
+
+/// Counts the action against the rate limits of the client and escalates if they are exceeded.
+/// Returns `false` if the action must be dropped.
+pub async fn throttle(context: &ServerContext, hwid: &str, action: Action) -> bool {
+    let Some((_, client)) = context.connected_clients.lock().await.get(hwid).cloned() else {
+        return false;
+    };
+
+    let clients = &context.connected_clients;
+    let content = match context
+        .rate_limiter
+        .check(&client.session_token, hwid, action)
+        .await
+    {
+        Verdict::Allowed => return true,
+        Verdict::Warned => "You are sending too fast, slow down or you will be muted".to_string(),
+        Verdict::Muted(duration) => {
+            let mute = Sanction {
+                username: client.name.clone(),
+                reason: "Flooding".to_string(),
+                issued_by: "Server".to_string(),
+                until: Some(expires_at(duration)),
+            };
+            tracing::info!("Muted {} {} for flooding", client.name, client.address);
+            let content = format!("You were muted {}", mute.describe());
+            let event = AuditEvent::Mute {
+                reason: mute.reason.clone(),
+                until: mute.until,
+            };
+            context.moderation.mute(hwid, mute).await;
+            let target = Some(Account::from(&client));
+            context.audit.record(None, target, event).await;
+            content
+        }
+        Verdict::Disconnected => {
+            tracing::info!(
+                "Disconnected {} {} for flooding",
+                client.name,
+                client.address
+            );
+            let reason = "You were disconnected for flooding".to_string();
+            EventHandler::disconnect(clients, hwid, reason).await;
+            let target = Some(Account::from(&client));
+            let event = AuditEvent::FloodDisconnect;
+            context.audit.record(None, target, event).await;
+            return false;
+        }
+    };
+
+    EventHandler::send_to(clients, hwid, Outgoing::System { content }).await;
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Moderation, Permission, Role};
+    use crate::types::{Client, ModerationConfig};
+
+    fn client(hwid: &str, room: &str) -> Client {
+        Client {
+            name: hwid.to_string(),
+            hwid: hwid.to_string(),
+            session_token: String::new(),
+            room: Some(room.to_string()),
+            address: "127.0.0.1".parse().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_room_roles() {
+        let directory = tempfile::tempdir().unwrap();
+        let config = ModerationConfig {
+            file: directory.path().join("moderation.json"),
+            ..Default::default()
+        };
+        let moderation = Moderation::load(config).await.unwrap();
+        moderation
+            .set_role("alice", Some("rust"), Role::Admin)
+            .await;
+        let alice = client("alice", "rust");
+        let bob = client("bob", "rust");
+
+        // Deleting messages is limited to the room, bans and roles are not
+        assert!(
+            moderation
+                .has_permission(&alice, Permission::DeleteMessages)
+                .await
+        );
+        assert!(!moderation.has_permission(&alice, Permission::Ban).await);
+        assert!(
+            !moderation
+                .has_permission(&alice, Permission::ManageRoles)
+                .await
+        );
+        assert!(!moderation.outranks(&alice, &bob).await);
+
+        moderation.set_role("alice", None, Role::Moderator).await;
+        assert!(moderation.has_permission(&alice, Permission::Kick).await);
+        assert!(moderation.outranks(&alice, &bob).await);
+    }
+}
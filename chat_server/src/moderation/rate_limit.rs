@@ -0,0 +1,390 @@
+use crate::types::RateLimitConfig;
+use std::{
+    collections::HashMap,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+/// Refills continuously at `rate` tokens per second, up to `capacity`.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    rate: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(capacity: f64, rate: f64, now: Instant) -> Self {
+        Self {
+            capacity,
+            tokens: capacity,
+            rate,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    fn has(&mut self, amount: f64, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= amount
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount;
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+
+    /// Keeps the tokens collected so far, as long as they fit.
+    fn resize(&mut self, capacity: f64, rate: f64, now: Instant) {
+        self.refill(now);
+        self.capacity = capacity;
+        self.rate = rate;
+        self.tokens = self.tokens.min(capacity);
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Buckets {
+    messages: TokenBucket,
+    bytes: TokenBucket,
+    joins: TokenBucket,
+    chunks: TokenBucket,
+}
+
+impl Buckets {
+    fn new(config: &RateLimitConfig, now: Instant) -> Self {
+        let bytes_per_second = config.bytes_per_second as f64;
+        let joins_per_minute = config.joins_per_minute as f64;
+        let chunks_per_second = config.chunks_per_second as f64;
+
+        Self {
+            messages: TokenBucket::new(
+                config.message_burst as f64,
+                config.messages_per_second,
+                now,
+            ),
+            bytes: TokenBucket::new(bytes_per_second, bytes_per_second, now),
+            joins: TokenBucket::new(joins_per_minute, joins_per_minute / 60.0, now),
+            chunks: TokenBucket::new(chunks_per_second, chunks_per_second, now),
+        }
+    }
+
+    fn resize(&mut self, config: &RateLimitConfig, now: Instant) {
+        let bytes_per_second = config.bytes_per_second as f64;
+        let joins_per_minute = config.joins_per_minute as f64;
+        let chunks_per_second = config.chunks_per_second as f64;
+
+        self.messages
+            .resize(config.message_burst as f64, config.messages_per_second, now);
+        self.bytes.resize(bytes_per_second, bytes_per_second, now);
+        self.joins
+            .resize(joins_per_minute, joins_per_minute / 60.0, now);
+        self.chunks
+            .resize(chunks_per_second, chunks_per_second, now);
+    }
+
+    fn has(&mut self, action: Action, now: Instant) -> bool {
+        match action {
+            Action::Message { bytes } => {
+                self.messages.has(1.0, now) && self.bytes.has(bytes as f64, now)
+            }
+            Action::Join => self.joins.has(1.0, now),
+            Action::Chunk => self.chunks.has(1.0, now),
+        }
+    }
+
+    fn take(&mut self, action: Action) {
+        match action {
+            Action::Message { bytes } => {
+                self.messages.take(1.0);
+                self.bytes.take(bytes as f64);
+            }
+            Action::Join => self.joins.take(1.0),
+            Action::Chunk => self.chunks.take(1.0),
+        }
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.messages.is_full(now)
+            && self.bytes.is_full(now)
+            && self.joins.is_full(now)
+            && self.chunks.is_full(now)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Account {
+    buckets: Buckets,
+    /// Violations since the account last behaved for `strike_reset` seconds
+    strikes: u32,
+    last_violation: Option<Instant>,
+}
+
+/// Something a client does which costs tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Chat messages, commands, renames, ...
+    Message {
+        bytes: usize,
+    },
+    Join,
+    /// A chunk of an upload or download, which has a bucket of its own
+    Chunk,
+}
+
+/// How to respond to an action, escalating with every violation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed,
+    Warned,
+    Muted(Duration),
+    Disconnected,
+}
+
+/// Limits every connection (keyed by its session token) and every account (keyed by its HWID),
+/// so reconnecting does not refill the buckets.
+pub struct RateLimiter {
+    config: RwLock<RateLimitConfig>,
+    connections: Mutex<HashMap<String, Buckets>>,
+    accounts: Mutex<HashMap<String, Account>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            connections: Mutex::new(HashMap::new()),
+            accounts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn check(&self, session_token: &str, hwid: &str, action: Action) -> Verdict {
+        self.check_at(session_token, hwid, action, Instant::now())
+            .await
+    }
+
+    async fn check_at(
+        &self,
+        session_token: &str,
+        hwid: &str,
+        action: Action,
+        now: Instant,
+    ) -> Verdict {
+        let config = self.config.read().unwrap().clone();
+        if !config.enabled {
+            return Verdict::Allowed;
+        }
+
+        let mut connections = self.connections.lock().await;
+        let mut accounts = self.accounts.lock().await;
+
+        let connection = connections
+            .entry(session_token.to_string())
+            .or_insert_with(|| Buckets::new(&config, now));
+        let account = accounts.entry(hwid.to_string()).or_insert_with(|| Account {
+            buckets: Buckets::new(&config, now),
+            strikes: 0,
+            last_violation: None,
+        });
+
+        if connection.has(action, now) && account.buckets.has(action, now) {
+            connection.take(action);
+            account.buckets.take(action);
+            return Verdict::Allowed;
+        }
+
+        let strike_reset = Duration::from_secs(config.strike_reset);
+        if account
+            .last_violation
+            .is_some_and(|last| now.saturating_duration_since(last) >= strike_reset)
+        {
+            account.strikes = 0;
+        }
+        account.strikes += 1;
+        account.last_violation = Some(now);
+
+        match account.strikes {
+            strikes if strikes <= config.warnings => Verdict::Warned,
+            strikes if strikes == config.warnings + 1 => {
+                Verdict::Muted(Duration::from_secs(config.mute_duration))
+            }
+            _ => Verdict::Disconnected,
+        }
+    }
+
+    /// Drops the buckets of a closed connection and of accounts which calmed down.
+    pub async fn forget_connection(&self, session_token: &str) {
+        self.connections.lock().await.remove(session_token);
+
+        let now = Instant::now();
+        let strike_reset = Duration::from_secs(self.config.read().unwrap().strike_reset);
+        self.accounts.lock().await.retain(|_, account| {
+            let calm = account
+                .last_violation
+                .is_none_or(|last| now.saturating_duration_since(last) >= strike_reset);
+            !(calm && account.buckets.is_full(now))
+        });
+    }
+
+    /// Applies new limits to all buckets, strikes are kept.
+    pub async fn reconfigure(&self, config: RateLimitConfig) {
+        let now = Instant::now();
+        let mut connections = self.connections.lock().await;
+        let mut accounts = self.accounts.lock().await;
+
+        for buckets in connections.values_mut() {
+            buckets.resize(&config, now);
+        }
+        for account in accounts.values_mut() {
+            account.buckets.resize(&config, now);
+        }
+        *self.config.write().unwrap() = config;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, RateLimiter, Verdict};
+    use crate::types::RateLimitConfig;
+    use std::time::{Duration, Instant};
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            messages_per_second: 1.0,
+            message_burst: 2,
+            warnings: 1,
+            mute_duration: 60,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_escalation() {
+        let limiter = limiter();
+        let message = Action::Message { bytes: 10 };
+        let now = Instant::now();
+
+        let verdicts = [
+            Verdict::Allowed,
+            Verdict::Allowed,
+            Verdict::Warned,
+            Verdict::Muted(Duration::from_secs(60)),
+            Verdict::Disconnected,
+        ];
+        for verdict in verdicts {
+            assert_eq!(
+                limiter.check_at("session", "hwid", message, now).await,
+                verdict
+            );
+        }
+
+        // One token is refilled every second
+        let later = now + Duration::from_secs(1);
+        assert_eq!(
+            limiter.check_at("session", "hwid", message, later).await,
+            Verdict::Allowed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_survives_reconnect() {
+        let limiter = limiter();
+        let message = Action::Message { bytes: 10 };
+        let now = Instant::now();
+
+        limiter.check_at("first", "hwid", message, now).await;
+        limiter.check_at("first", "hwid", message, now).await;
+        limiter.forget_connection("first").await;
+
+        assert_eq!(
+            limiter.check_at("second", "hwid", message, now).await,
+            Verdict::Warned
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_bytes() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            bytes_per_second: 100,
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        let large = Action::Message { bytes: 101 };
+        assert_eq!(
+            limiter.check_at("session", "hwid", large, now).await,
+            Verdict::Warned
+        );
+        let small = Action::Message { bytes: 100 };
+        assert_eq!(
+            limiter.check_at("session", "hwid", small, now).await,
+            Verdict::Allowed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_chunks() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            messages_per_second: 1.0,
+            message_burst: 2,
+            chunks_per_second: 2,
+            ..Default::default()
+        });
+        let message = Action::Message { bytes: 10 };
+        let now = Instant::now();
+
+        // Transfers don't use up the messages and the other way around
+        for action in [Action::Chunk, Action::Chunk, message, message] {
+            assert_eq!(
+                limiter.check_at("session", "hwid", action, now).await,
+                Verdict::Allowed
+            );
+        }
+        assert_eq!(
+            limiter
+                .check_at("session", "hwid", Action::Chunk, now)
+                .await,
+            Verdict::Warned
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_reconfigure() {
+        let limiter = limiter();
+        let message = Action::Message { bytes: 10 };
+        let now = Instant::now();
+
+        limiter.check_at("session", "hwid", message, now).await;
+        limiter.check_at("session", "hwid", message, now).await;
+        assert_eq!(
+            limiter.check_at("session", "hwid", message, now).await,
+            Verdict::Warned
+        );
+
+        // The buckets stay empty, but refill faster
+        limiter
+            .reconfigure(RateLimitConfig {
+                messages_per_second: 10.0,
+                message_burst: 5,
+                warnings: 1,
+                ..Default::default()
+            })
+            .await;
+        let later = now + Duration::from_millis(200);
+        assert_eq!(
+            limiter.check_at("session", "hwid", message, later).await,
+            Verdict::Allowed
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{fmt::Display, str::FromStr};
+
+/// Roles are ordered, every role outranks the ones declared before it.
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Guest,
+    Member,
+    Moderator,
+    Admin,
+    Owner,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    SendMessages,
+    DirectMessages,
+    JoinRooms,
+    ChangeUsername,
+    Kick,
+    Mute,
+    DeleteMessages,
+    Ban,
+    ManageRoles,
+    ViewStats,
+}
+
+impl Role {
+    pub fn permissions(&self) -> &'static [Permission] {
+        use Permission::*;
+
+        match self {
+            Role::Guest => &[SendMessages],
+            Role::Member => &[SendMessages, DirectMessages, JoinRooms, ChangeUsername],
+            Role::Moderator => &[
+                SendMessages,
+                DirectMessages,
+                JoinRooms,
+                ChangeUsername,
+                Kick,
+                Mute,
+                DeleteMessages,
+            ],
+            Role::Admin | Role::Owner => &[
+                SendMessages,
+                DirectMessages,
+                JoinRooms,
+                ChangeUsername,
+                Kick,
+                Mute,
+                DeleteMessages,
+                Ban,
+                ManageRoles,
+                ViewStats,
+            ],
+        }
+    }
+
+    pub fn has(&self, permission: Permission) -> bool {
+        self.permissions().contains(&permission)
+    }
+}
+
+impl Permission {
+    /// Kicks, bans, mutes, roles and stats affect the whole server, so a role in a room doesn't
+    /// grant them.
+    pub fn is_global(&self) -> bool {
+        use Permission::*;
+
+        matches!(self, Kick | Mute | Ban | ManageRoles | ViewStats)
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Role::Guest => "guest",
+            Role::Member => "member",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "guest" => Ok(Role::Guest),
+            "member" => Ok(Role::Member),
+            "moderator" | "mod" => Ok(Role::Moderator),
+            "admin" => Ok(Role::Admin),
+            "owner" => Ok(Role::Owner),
+            _ => Err("expected guest, member, moderator, admin or owner".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Permission, Role};
+
+    #[test]
+    fn test_role_permissions() {
+        assert!(Role::Guest.has(Permission::SendMessages));
+        assert!(!Role::Guest.has(Permission::JoinRooms));
+        assert!(Role::Moderator.has(Permission::Mute));
+        assert!(!Role::Moderator.has(Permission::Ban));
+        assert!(Role::Owner.has(Permission::ManageRoles));
+        assert!(Role::Owner > Role::Admin && Role::Member > Role::Guest);
+        assert_eq!("Mod".parse::<Role>(), Ok(Role::Moderator));
+        assert!(Permission::Ban.is_global() && !Permission::DeleteMessages.is_global());
+    }
+}
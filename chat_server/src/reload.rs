@@ -0,0 +1,272 @@
+use crate::{
+    audit::AuditEvent,
+    cli::Cli,
+    types::{Config, ServerContext},
+};
+use chat_shared::{
+    config,
+    logging::{self, LogConfig},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{
+    sync::mpsc,
+    time::{interval, MissedTickBehavior},
+};
+
+/// How often the config file is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Settings which are only read on startup, changing them requires a restart.
+const RESTART_REQUIRED: &[&str] = &[
+    "audit.enabled",
+    "audit.file",
+    "connections.sessions_file",
+    "endpoint",
+    "files.directory",
+    "files.enabled",
+    "history_size",
+    "irc.enabled",
+    "irc.endpoint",
+    "log.file",
+    "log.format",
+    "log.max_files",
+    "log.rotation",
+    "mailbox.enabled",
+    "mailbox.file",
+    "metrics.enabled",
+    "metrics.endpoint",
+    "moderation.file",
+];
+
+/// The outcome of comparing the running config with the reloaded one.
+#[derive(Debug)]
+pub struct Reload {
+    /// The reloaded config, with the settings which require a restart left as they were
+    pub config: Config,
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
+impl Reload {
+    pub fn new(current: &Config, mut config: Config) -> Self {
+        let changed = changed_keys(current, &config);
+        let (restart_required, applied): (Vec<_>, Vec<_>) = changed
+            .into_iter()
+            .partition(|key| RESTART_REQUIRED.contains(&key.as_str()));
+
+        config.audit = current.audit.clone();
+        config.connections.sessions_file = current.connections.sessions_file.clone();
+        config.endpoint = current.endpoint;
+        config.files.enabled = current.files.enabled;
+        config.files.directory = current.files.directory.clone();
+        config.history_size = current.history_size;
+        config.irc.enabled = current.irc.enabled;
+        config.irc.endpoint = current.irc.endpoint;
+        config.metrics.enabled = current.metrics.enabled;
+        config.metrics.endpoint = current.metrics.endpoint;
+        config.mailbox.enabled = current.mailbox.enabled;
+        config.mailbox.file = current.mailbox.file.clone();
+        config.moderation.file = current.moderation.file.clone();
+        config.log = LogConfig {
+            level: config.log.level,
+            ..current.log.clone()
+        };
+
+        Self {
+            config,
+            applied,
+            restart_required,
+        }
+    }
+}
+
+/// Reloads the config whenever the file changes or the server receives SIGHUP. Limits, rate
+/// limits, the MOTD and the log level apply right away. Webhooks are not covered, the server
+/// does not send any.
+pub fn watch(path: PathBuf, cli: Cli, context: Arc<ServerContext>) {
+    let (requests, mut inbox) = mpsc::unbounded_channel();
+
+    #[cfg(unix)]
+    tokio::spawn(forward_hangups(requests.clone()));
+    tokio::spawn(poll_changes(path.clone(), requests));
+
+    tokio::spawn(async move {
+        while inbox.recv().await.is_some() {
+            reload(&path, &cli, &context).await;
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn forward_hangups(requests: mpsc::UnboundedSender<()>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(why) => {
+            tracing::error!("Unable to listen for SIGHUP! {why}");
+            return;
+        }
+    };
+
+    while hangups.recv().await.is_some() {
+        tracing::info!("Received SIGHUP, reloading the config");
+        if requests.send(()).is_err() {
+            break;
+        }
+    }
+}
+
+async fn poll_changes(path: PathBuf, requests: mpsc::UnboundedSender<()>) {
+    let mut last_modified = modified(&path).await;
+    let mut ticker = interval(POLL_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        let current = modified(&path).await;
+        // A removed file is not a reason to fall back to the defaults
+        if current.is_none() || current == last_modified {
+            continue;
+        }
+
+        last_modified = current;
+        tracing::info!("{} changed, reloading the config", path.display());
+        if requests.send(()).is_err() {
+            break;
+        }
+    }
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
+
+/// Applies the changes of the config file, an invalid file keeps the current config.
+pub async fn reload(path: &Path, cli: &Cli, context: &ServerContext) {
+    let mut config = match config::load::<Config>(path).await {
+        Ok(config) => config,
+        Err(why) => {
+            tracing::error!("Keeping the current config, {why}");
+            return;
+        }
+    };
+    cli.apply(&mut config);
+
+    let reload = Reload::new(&context.config(), config);
+    for key in &reload.restart_required {
+        tracing::warn!("Restart the server to apply the change of `{key}`");
+    }
+    if reload.applied.is_empty() {
+        tracing::info!("The config did not change");
+        return;
+    }
+
+    let config = reload.config;
+    logging::configure(&config.log.level);
+    context
+        .rate_limiter
+        .reconfigure(config.rate_limit.clone())
+        .await;
+    context
+        .connection_limiter
+        .reconfigure(config.connections.clone());
+    context.moderation.reconfigure(config.moderation.clone());
+    context.mailbox.reconfigure(config.mailbox.clone());
+    context.files.reconfigure(config.files.clone());
+    context
+        .sessions
+        .reconfigure(Duration::from_secs(config.connections.resume_timeout));
+    *context.config.write().unwrap() = Arc::new(config);
+    context
+        .audit
+        .record(
+            None,
+            None,
+            AuditEvent::ConfigReload {
+                changed: reload.applied.clone(),
+            },
+        )
+        .await;
+
+    tracing::info!("Applied the changes of {}", reload.applied.join(", "));
+}
+
+/// The dotted keys of all settings which differ, e.g. `rate_limit.message_burst`.
+fn changed_keys(current: &Config, new: &Config) -> Vec<String> {
+    let (Ok(current), Ok(new)) = (toml::Value::try_from(current), toml::Value::try_from(new))
+    else {
+        return Vec::new();
+    };
+
+    let mut keys = Vec::new();
+    compare("", &current, &new, &mut keys);
+    keys
+}
+
+fn compare(prefix: &str, current: &toml::Value, new: &toml::Value, keys: &mut Vec<String>) {
+    match (current, new) {
+        (toml::Value::Table(current), toml::Value::Table(new)) => {
+            let mut names = current.keys().chain(new.keys()).collect::<Vec<_>>();
+            names.sort();
+            names.dedup();
+
+            for name in names {
+                let key = match prefix {
+                    "" => name.clone(),
+                    _ => format!("{prefix}.{name}"),
+                };
+                match (current.get(name), new.get(name)) {
+                    (Some(current), Some(new)) => compare(&key, current, new, keys),
+                    _ => keys.push(key),
+                }
+            }
+        }
+        (current, new) if current != new => keys.push(prefix.to_string()),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reload;
+    use crate::types::Config;
+
+    #[test]
+    fn test_reload_changes() {
+        let current = Config::default();
+        let mut config = Config {
+            endpoint: "0.0.0.0:9000".parse().unwrap(),
+            ..Default::default()
+        };
+        config.log.level = "debug".to_string();
+        config.log.file = Some("server.log".into());
+        config.rate_limit.message_burst = 10;
+        config.irc.server_name = "chat.example.com".to_string();
+        config.motd = "Welcome!".to_string();
+
+        let reload = Reload::new(&current, config);
+        assert_eq!(reload.restart_required, ["endpoint", "log.file"]);
+        assert_eq!(
+            reload.applied,
+            [
+                "irc.server_name",
+                "log.level",
+                "motd",
+                "rate_limit.message_burst"
+            ]
+        );
+        assert_eq!(reload.config.endpoint, current.endpoint);
+        assert_eq!(reload.config.log.file, None);
+        assert_eq!(reload.config.log.level, "debug");
+        assert_eq!(reload.config.rate_limit.message_burst, 10);
+        assert_eq!(reload.config.motd, "Welcome!");
+
+        let reload = Reload::new(&current, current.clone());
+        assert!(reload.applied.is_empty() && reload.restart_required.is_empty());
+    }
+}
@@ -1,150 +1,1188 @@
 use crate::{
+    audit::{Account, AuditEvent, AuditLog},
+    commands::{is_command, CommandRegistry},
     event_handler::EventHandler,
+    files::FileStore,
+    history::History,
+    irc,
+    limits::{Activity, ConnectionLimiter, Rejection},
+    mailbox::Mailbox,
+    metrics::{self, Metrics},
+    moderation::{self, Action, Moderation, Permission, RateLimiter},
+    sessions::Sessions,
+    shutdown,
+    threads::Followers,
     types,
-    utils::{check_username, write_to_stream},
+    utils::{check_username, join_pages, unix_timestamp, write_to_stream},
 };
 use chat_shared::{
+    error::DeserializerError,
     protocols::{
-        client::{ChangeUsername, ChatMessage, ClientMessageType},
-        server::AuthenticateToken,
+        client::{
+            self, AddReaction, ChangeUsername, ChatMessage, ClientMessageType, DeleteMessage,
+            DownloadChunk, EditMessage, FetchThread, FollowThread, MarkRead, RemoveReaction,
+            StartUpload, UnfollowThread, UploadChunk,
+        },
+        server::{
+            AuthenticateToken, BroadcastMessage, DirectMessage, FileChunk, FileShared, Mention,
+            MessageDeleted, MessageEdited, MessageSent, Ping, Pong, ReactionsUpdated, RoomMembers,
+            ServerMessageType, ServerShutdown, SystemMessage, ThreadMessage, UploadOffset,
+            UserJoined, UserLeft, UsernameChanged, Welcome,
+        },
     },
-    types::Deserialize,
+    types::{Deserialize, Serialize},
+    utils::{max_content_length, read_frame},
 };
 use std::{
     collections::HashMap,
-    io::Read,
-    net::{SocketAddr, TcpListener, TcpStream},
-    sync::Arc,
+    net::IpAddr,
+    sync::{Arc, RwLock},
+    time::Duration,
 };
-use tokio::sync::Mutex;
-use types::Client;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::{mpsc, Mutex},
+    time::{interval, timeout, MissedTickBehavior},
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{field, Instrument, Span};
+use types::{Client, Config, Outgoing, ServerContext};
 
-const BUFFER_SIZE: usize = 2048;
+/// Told to every client when the server stops.
+const SHUTDOWN_REASON: &str = "The server is shutting down";
 
 pub struct Server {
-    pub connected_clients: Arc<Mutex<HashMap<String, (TcpStream, Client)>>>,
+    pub context: Arc<ServerContext>,
     pub tcp_listener: TcpListener,
 }
 
 impl Server {
-    pub fn create(endpoint: SocketAddr) -> std::io::Result<Server> {
+    pub async fn create(config: Config) -> std::io::Result<Server> {
+        Self::with_commands(config, CommandRegistry::with_builtin()).await
+    }
+
+    /// Creates a server which offers the commands of `commands`, e.g. the built-in ones with
+    /// team-specific commands registered on top.
+    pub async fn with_commands(
+        config: Config,
+        commands: CommandRegistry,
+    ) -> std::io::Result<Server> {
         let connected_clients = Arc::new(Mutex::new(HashMap::new()));
-        let tcp_listener = TcpListener::bind(endpoint)?;
-        log::info!("Server started @ {:#?}", endpoint);
-
-        for stream in tcp_listener.incoming() {
-            match stream {
-                Ok(stream) => {
-                    let connected_clients = connected_clients.clone();
-                    log::info!("{} connected,", stream.peer_addr()?);
-
-                    // Each client get's a custom thread
-                    tokio::spawn(async move {
-                        let mut current_client: Option<(String, String)> = None;
-
-                        {
-                            let connected_clients = connected_clients.clone();
-
-                            // We need the HWID here so we can identify the client
-                            while current_client.is_none() {
-                                log::info!("Waiting for HWID...");
-                                if let Some(c) = EventHandler::handle_auth(&stream).await {
-                                    current_client = Some(c);
-                                } else {
-                                    // Remove connection to client?
-                                    return;
-                                }
-                            }
-
-                            // TODO: Check if HWID already exists, if not create entry with UUID
-                            let (client_hwid, client_username) = current_client.clone().unwrap();
-                            log::info!("Found Hwid [{}]", client_hwid);
-
-                            let session_token = uuid::Uuid::new_v4().to_string();
-                            let username = check_username(&client_username);
-                            let client = Client {
-                                session_token: session_token.clone(),
-                                hwid: client_hwid.clone(),
-                                name: username,
-                            };
-
-                            let message = AuthenticateToken {
-                                token: session_token,
-                            };
-                            write_to_stream(&stream, &message).await.unwrap();
-
-                            connected_clients
-                                .lock()
-                                .await
-                                .insert(client_hwid, (stream.try_clone().unwrap(), client));
-
-                            log::info!(
-                                "Connected clients: {:#?}",
-                                connected_clients.lock().await.len()
-                            );
-                            Self::handle_connection(&stream, &connected_clients).await;
-                        }
+        let tcp_listener = TcpListener::bind(config.endpoint).await?;
+        tracing::info!("Server started @ {:#?}", config.endpoint);
+
+        let context = ServerContext {
+            connected_clients,
+            commands,
+            moderation: Moderation::load(config.moderation.clone()).await?,
+            history: History::new(config.history_size),
+            rate_limiter: RateLimiter::new(config.rate_limit.clone()),
+            connection_limiter: ConnectionLimiter::new(config.connections.clone()),
+            sessions: Sessions::load(
+                config.connections.sessions_file.clone(),
+                Duration::from_secs(config.connections.resume_timeout),
+            )
+            .await?,
+            followers: Followers::default(),
+            metrics: Metrics::default(),
+            audit: AuditLog::open(&config.audit).await?,
+            mailbox: Mailbox::load(config.mailbox.clone()).await?,
+            files: FileStore::load(config.files.clone()).await?,
+            config: RwLock::new(Arc::new(config)),
+            shutdown: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+        };
+
+        Ok(Server {
+            context: Arc::new(context),
+            tcp_listener,
+        })
+    }
 
-                        // This will trigger after the client is disconnected & removes them from the HashMap
-                        let mut connected_locked = connected_clients.lock().await;
-                        connected_locked.remove(&current_client.clone().unwrap().0);
+    /// Accepts clients until SIGINT or SIGTERM is received, then shuts down gracefully.
+    pub async fn listen(self) -> std::io::Result<()> {
+        let irc_config = self.context.config().irc.clone();
+        if irc_config.enabled {
+            let irc_listener = TcpListener::bind(irc_config.endpoint).await?;
+            tracing::info!("IRC bridge started @ {:#?}", irc_config.endpoint);
+            tokio::spawn(irc::listen(irc_listener, self.context.clone()));
+        }
+
+        let metrics_config = self.context.config().metrics.clone();
+        if metrics_config.enabled {
+            let metrics_listener = TcpListener::bind(metrics_config.endpoint).await?;
+            tracing::info!(
+                "Metrics served @ http://{}/metrics",
+                metrics_config.endpoint
+            );
+            tokio::spawn(metrics::listen(metrics_listener, self.context.clone()));
+        }
 
-                        log::info!("Client disconnected");
-                    });
-                    // We do not join the threads to keep concurrency
+        self.context
+            .audit
+            .record(None, None, AuditEvent::ServerStart)
+            .await;
+        let signal = shutdown::signal();
+        tokio::pin!(signal);
+
+        let stopped_by = loop {
+            let accepted = tokio::select! {
+                accepted = self.tcp_listener.accept() => accepted,
+                name = &mut signal => {
+                    tracing::info!("Received {name}, shutting down");
+                    break name;
                 }
-                Err(why) => {
-                    log::error!("Error accepting client connection");
-                    log::error!("{}", why);
+            };
+
+            match accepted {
+                Ok((stream, peer_addr)) => {
+                    // Every log line of the connection carries these fields
+                    let span = tracing::info_span!(
+                        "connection",
+                        protocol = "native",
+                        peer = %peer_addr,
+                        hwid = field::Empty,
+                        session = field::Empty,
+                    );
+                    span.in_scope(|| tracing::info!("Connected"));
+
+                    // Each client get's a custom task
+                    let context = self.context.clone();
+                    self.context
+                        .tasks
+                        .spawn(Self::handle_client(stream, context).instrument(span));
+                    // We do not join the tasks to keep concurrency
                 }
+                Err(why) => tracing::error!("Error accepting client connection! {why}"),
+            }
+        };
+
+        drop(self.tcp_listener);
+        shutdown::drain(&self.context, SHUTDOWN_REASON).await;
+        let event = AuditEvent::ServerStop {
+            reason: format!("Received {stopped_by}"),
+        };
+        self.context.audit.record(None, None, event).await;
+        tracing::info!("Server stopped");
+        Ok(())
+    }
+
+    async fn handle_client(stream: TcpStream, context: Arc<ServerContext>) {
+        let Ok(address) = stream.peer_addr().map(|addr| addr.ip()) else {
+            return;
+        };
+        let (mut read_stream, mut write_stream) = stream.into_split();
+        let connected_clients = &context.connected_clients;
+
+        // Held until the connection is closed
+        let _guard = match context.connection_limiter.acquire(address) {
+            Ok(guard) => guard,
+            Err(rejection) => {
+                Self::reject(&mut write_stream, address, rejection).await;
+                return;
             }
+        };
+
+        if Self::reject_banned(&mut write_stream, &context, None, address).await {
+            return;
         }
 
-        Ok(Server {
-            connected_clients,
-            tcp_listener,
-        })
+        // We need the HWID here so we can identify the client
+        tracing::debug!("Waiting for authentication");
+        let config = context.config();
+        let auth_timeout = Duration::from_secs(config.connections.auth_timeout);
+        let auth =
+            EventHandler::handle_auth(&mut read_stream, config.buffer_size, &context.metrics);
+        let auth = tokio::select! {
+            auth = timeout(auth_timeout, auth) => auth,
+            _ = context.shutdown.cancelled() => return,
+        };
+        let Some((client_hwid, client_username, resume_token)) = (match auth {
+            Ok(auth) => auth,
+            Err(_) => {
+                context
+                    .connection_limiter
+                    .metrics
+                    .record(Rejection::AuthTimeout);
+                context.metrics.auth_failure("native", "timeout");
+                Self::reject(&mut write_stream, address, Rejection::AuthTimeout).await;
+                return;
+            }
+        }) else {
+            return;
+        };
+
+        // TODO: Check if HWID already exists, if not create entry with UUID
+        Span::current().record("hwid", client_hwid.as_str());
+        let account = Account::new(&client_hwid, &client_username);
+        if Self::reject_banned(&mut write_stream, &context, Some(account), address).await {
+            return;
+        }
+
+        // Resumed sessions keep their name and room, but get a new token nonetheless
+        let resumed = match resume_token {
+            Some(token) => context.sessions.resume(&token, &client_hwid).await,
+            None => None,
+        };
+        let session_token = uuid::Uuid::new_v4().to_string();
+        Span::current().record("session", session_token.as_str());
+        let requested = match &resumed {
+            Some(session) => session.name.clone(),
+            None => check_username(&client_username),
+        };
+
+        // The name is claimed under the same lock as the insert, so nobody can take it meanwhile
+        let (outbox, inbox) = mpsc::unbounded_channel();
+        let mut lock = connected_clients.lock().await;
+        let client = Client {
+            session_token: session_token.clone(),
+            hwid: client_hwid.clone(),
+            name: EventHandler::free_username(&context, &lock, &client_hwid, &requested).await,
+            room: None,
+            address,
+        };
+        lock.insert(client_hwid.clone(), (outbox.clone(), client.clone()));
+        drop(lock);
+
+        tracing::info!(
+            username = %client.name,
+            resumed = resumed.is_some(),
+            "Authenticated"
+        );
+        let message = AuthenticateToken {
+            token: session_token.clone(),
+        };
+        let event = AuditEvent::Login {
+            address,
+            protocol: "native".to_string(),
+            resumed: resumed.is_some(),
+        };
+        let account = Account::from(&client);
+        context
+            .audit
+            .record(Some(account.clone()), None, event)
+            .await;
+        if !Self::write(&mut write_stream, &message, &context).await {
+            EventHandler::handle_disconnect(connected_clients, &client_hwid, &session_token).await;
+            context
+                .audit
+                .record(Some(account), None, AuditEvent::Logout)
+                .await;
+            return;
+        }
+
+        context
+            .tasks
+            .spawn(Self::forward_outgoing(write_stream, inbox, context.clone()).in_current_span());
+        let heartbeat = tokio::spawn(
+            Self::send_heartbeats(outbox, config.heartbeat.interval).in_current_span(),
+        );
+
+        // Only the client itself knew the name it asked for
+        if !client.name.eq(&client_username) {
+            let event = Outgoing::UsernameChanged {
+                old_username: client_username,
+                new_username: client.name.clone(),
+            };
+            EventHandler::send_to(connected_clients, &client_hwid, event).await;
+        }
+        let clients = connected_clients.lock().await.len();
+        tracing::info!(clients, "Client connected");
+        let default_room = &config.default_room;
+        let room = resumed
+            .as_ref()
+            .and_then(|session| session.room.as_deref())
+            .unwrap_or(default_room);
+        if let Err(why) = EventHandler::join_room(connected_clients, &client_hwid, room).await {
+            tracing::error!(room, "Unable to join room! {why}");
+        }
+        if resumed.is_some() {
+            let event = Outgoing::System {
+                content: "Resumed your previous session".to_string(),
+            };
+            EventHandler::send_to(connected_clients, &client_hwid, event).await;
+        }
+        // Read again, the config might have been reloaded while the client authenticated
+        let motd = context.config().motd.clone();
+        if !motd.is_empty() {
+            let event = Outgoing::System { content: motd };
+            EventHandler::send_to(connected_clients, &client_hwid, event).await;
+        }
+        EventHandler::welcome(&context, &client_hwid, &account.username).await;
+
+        Self::handle_connection(read_stream, &client_hwid, &session_token, &context).await;
+        heartbeat.abort();
+
+        // This will trigger after the client is disconnected & removes them from the HashMap
+        let account =
+            match EventHandler::handle_disconnect(connected_clients, &client_hwid, &session_token)
+                .await
+            {
+                Some(client) => {
+                    let account = Account::from(&client);
+                    context.sessions.save(client).await;
+                    account
+                }
+                // Renames are audited, so the name of the login is enough
+                None => account,
+            };
+        context
+            .audit
+            .record(Some(account), None, AuditEvent::Logout)
+            .await;
+        context.rate_limiter.forget_connection(&session_token).await;
+        context.followers.forget_connection(&session_token).await;
+        context.mailbox.logout(&client_hwid).await;
+
+        tracing::info!("Client disconnected");
     }
 
     async fn handle_connection(
-        mut stream: &TcpStream,
-        clients: &Arc<Mutex<HashMap<String, (TcpStream, Client)>>>,
+        mut stream: OwnedReadHalf,
+        client_hwid: &str,
+        session_token: &str,
+        context: &ServerContext,
     ) {
-        let mut buffer = [0; BUFFER_SIZE];
+        let clients = &context.connected_clients;
+        let mut activity = Activity::new();
 
         loop {
-            match stream.read(&mut buffer) {
-                Ok(bytes_read) => {
-                    if bytes_read == 0 {
-                        log::info!("Client disconnected");
-                        break;
-                    }
-
-                    match ClientMessageType::from(buffer[0]) {
-                        ClientMessageType::ChangeUsername => {
-                            if let Ok(msg) = ChangeUsername::deserialize(&buffer).await {
-                                EventHandler::handle_change_username(msg);
-                            }
-                        }
-                        ClientMessageType::ChatMessage => {
-                            if let Ok(msg) = ChatMessage::deserialize(&buffer).await {
-                                EventHandler::handle_send_message(msg, clients)
-                                    .await
-                                    .unwrap();
-                            }
-                        }
+            let config = context.config();
+            let idle_timeout = Duration::from_secs(config.connections.idle_timeout);
+            let heartbeat_timeout = Duration::from_secs(config.heartbeat.timeout);
+            let read = read_frame(&mut stream, config.buffer_size);
+            let read = tokio::select! {
+                read = timeout(heartbeat_timeout, read) => read,
+                // The outbox is still written, which tells the client why
+                _ = context.shutdown.cancelled() => break,
+            };
+            let Ok(frame) = read else {
+                tracing::info!("Client stopped responding");
+                let reason = "Connection timed out, no heartbeat received".to_string();
+                // Dead connections are usually lost, not closed on purpose
+                if let Some(client) = EventHandler::disconnect(clients, client_hwid, reason).await {
+                    context.sessions.save(client).await;
+                }
+                break;
+            };
 
-                        _ => EventHandler::handle_unknown_message(),
-                    }
+            let is_heartbeat = frame.as_ref().is_ok_and(|frame| {
+                frame.as_ref().is_some_and(|buffer| {
+                    matches!(
+                        ClientMessageType::from(buffer[0]),
+                        ClientMessageType::Ping | ClientMessageType::Pong
+                    )
+                })
+            });
+            if activity.record(is_heartbeat, idle_timeout) {
+                context
+                    .connection_limiter
+                    .metrics
+                    .record(Rejection::IdleTimeout);
+                let reason = Rejection::IdleTimeout.to_string();
+                EventHandler::disconnect(clients, client_hwid, reason).await;
+                break;
+            }
 
-                    // Clear the buffer
-                    buffer = [0; BUFFER_SIZE];
+            // Kicked clients are removed from the list, but their connection might still be open
+            let is_current_session = clients
+                .lock()
+                .await
+                .get(client_hwid)
+                .is_some_and(|(_, c)| c.session_token.eq(session_token));
+            if !is_current_session {
+                break;
+            }
+
+            let buffer = match frame {
+                Ok(Some(buffer)) => buffer,
+                Ok(None) => {
+                    tracing::info!("Client disconnected");
+                    break;
                 }
                 Err(why) => {
-                    log::error!("{}", why);
+                    tracing::error!("{}", why);
+                    context.metrics.deserialize_error(&why);
+                    break;
+                }
+            };
+
+            let message_type = ClientMessageType::from(buffer[0]);
+            context
+                .metrics
+                .received("native", message_type.name(), buffer.len());
+            let span = tracing::info_span!(
+                "message",
+                r#type = message_type.name(),
+                bytes = buffer.len()
+            );
+            Self::handle_message(&buffer, message_type, client_hwid, context)
+                .instrument(span)
+                .await;
+        }
+    }
+
+    async fn handle_message(
+        buffer: &[u8],
+        message_type: ClientMessageType,
+        client_hwid: &str,
+        context: &ServerContext,
+    ) {
+        let clients = &context.connected_clients;
+        match message_type {
+            ClientMessageType::ChangeUsername => {
+                if let Some(mut msg) = Self::decode::<ChangeUsername>(buffer, context).await {
+                    msg.hwid = client_hwid.to_string();
+                    let action = Action::Message {
+                        bytes: msg.new_username.len(),
+                    };
+                    if !moderation::throttle(context, client_hwid, action).await
+                        || !moderation::authorize(context, client_hwid, Permission::ChangeUsername)
+                            .await
+                    {
+                        return;
+                    }
+
+                    if let Err(why) = EventHandler::handle_change_username(msg, context).await {
+                        let event = Outgoing::System {
+                            content: why.to_string(),
+                        };
+                        EventHandler::send_to(clients, client_hwid, event).await;
+                    }
+                }
+            }
+            ClientMessageType::ChatMessage => {
+                if let Some(mut msg) = Self::decode::<ChatMessage>(buffer, context).await {
+                    // Never trust the HWID sent along with the message
+                    msg.hwid = client_hwid.to_string();
+
+                    let action = Action::Message {
+                        bytes: msg.content.len(),
+                    };
+                    if !moderation::throttle(context, client_hwid, action).await {
+                        return;
+                    }
+
+                    // Commands check their permissions themselves
+                    if !is_command(&msg.content)
+                        && !moderation::authorize(context, client_hwid, Permission::SendMessages)
+                            .await
+                    {
+                        return;
+                    }
+                    EventHandler::handle_send_message(msg, context).await;
+                }
+            }
+
+            ClientMessageType::EditMessage => {
+                if let Some(mut msg) = Self::decode::<EditMessage>(buffer, context).await {
+                    msg.hwid = client_hwid.to_string();
+                    let action = Action::Message {
+                        bytes: msg.content.len(),
+                    };
+                    if !moderation::throttle(context, client_hwid, action).await
+                        || !moderation::authorize(context, client_hwid, Permission::SendMessages)
+                            .await
+                    {
+                        return;
+                    }
+
+                    if let Err(why) = EventHandler::handle_edit_message(msg, context).await {
+                        let event = Outgoing::System {
+                            content: why.to_string(),
+                        };
+                        EventHandler::send_to(clients, client_hwid, event).await;
+                    }
+                }
+            }
+            ClientMessageType::DeleteMessage => {
+                if let Some(mut msg) = Self::decode::<DeleteMessage>(buffer, context).await {
+                    msg.hwid = client_hwid.to_string();
+                    let action = Action::Message {
+                        bytes: msg.id.len(),
+                    };
+                    if !moderation::throttle(context, client_hwid, action).await {
+                        return;
+                    }
+                    if let Err(why) = EventHandler::handle_delete_message(msg, context).await {
+                        let event = Outgoing::System {
+                            content: why.to_string(),
+                        };
+                        EventHandler::send_to(clients, client_hwid, event).await;
+                    }
+                }
+            }
+
+            ClientMessageType::FetchThread => {
+                if let Some(mut msg) = Self::decode::<FetchThread>(buffer, context).await {
+                    msg.hwid = client_hwid.to_string();
+                    let action = Action::Message {
+                        bytes: msg.id.len(),
+                    };
+                    if !moderation::throttle(context, client_hwid, action).await {
+                        return;
+                    }
+                    if let Err(why) = EventHandler::handle_fetch_thread(msg, context).await {
+                        let event = Outgoing::System {
+                            content: why.to_string(),
+                        };
+                        EventHandler::send_to(clients, client_hwid, event).await;
+                    }
+                }
+            }
+            ClientMessageType::FollowThread | ClientMessageType::UnfollowThread => {
+                let id = match message_type {
+                    ClientMessageType::FollowThread => {
+                        Self::decode::<FollowThread>(buffer, context)
+                            .await
+                            .map(|m| m.id)
+                    }
+                    _ => Self::decode::<UnfollowThread>(buffer, context)
+                        .await
+                        .map(|m| m.id),
+                };
+                let Some(id) = id else {
+                    return;
+                };
+                let action = Action::Message { bytes: id.len() };
+                if !moderation::throttle(context, client_hwid, action).await {
+                    return;
+                }
+
+                let follow = message_type == ClientMessageType::FollowThread;
+                if let Err(why) =
+                    EventHandler::handle_follow_thread(client_hwid, &id, follow, context).await
+                {
+                    let event = Outgoing::System {
+                        content: why.to_string(),
+                    };
+                    EventHandler::send_to(clients, client_hwid, event).await;
+                }
+            }
+
+            ClientMessageType::AddReaction | ClientMessageType::RemoveReaction => {
+                let reaction = match message_type {
+                    ClientMessageType::AddReaction => Self::decode::<AddReaction>(buffer, context)
+                        .await
+                        .map(|m| (m.id, m.reaction)),
+                    _ => Self::decode::<RemoveReaction>(buffer, context)
+                        .await
+                        .map(|m| (m.id, m.reaction)),
+                };
+                let Some((id, reaction)) = reaction else {
+                    return;
+                };
+
+                let action = Action::Message {
+                    bytes: reaction.len(),
+                };
+                if !moderation::throttle(context, client_hwid, action).await
+                    || !moderation::authorize(context, client_hwid, Permission::SendMessages).await
+                {
+                    return;
+                }
+
+                let add = message_type == ClientMessageType::AddReaction;
+                if let Err(why) =
+                    EventHandler::handle_reaction(client_hwid, &id, &reaction, add, context).await
+                {
+                    let event = Outgoing::System {
+                        content: why.to_string(),
+                    };
+                    EventHandler::send_to(clients, client_hwid, event).await;
+                }
+            }
+
+            ClientMessageType::MarkRead => {
+                if let Some(mut msg) = Self::decode::<MarkRead>(buffer, context).await {
+                    msg.hwid = client_hwid.to_string();
+                    let action = Action::Message {
+                        bytes: msg.room.len() + msg.id.len(),
+                    };
+                    if !moderation::throttle(context, client_hwid, action).await {
+                        return;
+                    }
+                    if let Err(why) = EventHandler::handle_mark_read(msg, context).await {
+                        let event = Outgoing::System {
+                            content: why.to_string(),
+                        };
+                        EventHandler::send_to(clients, client_hwid, event).await;
+                    }
+                }
+            }
+
+            ClientMessageType::StartUpload => {
+                if let Some(mut msg) = Self::decode::<StartUpload>(buffer, context).await {
+                    msg.hwid = client_hwid.to_string();
+                    let action = Action::Message {
+                        bytes: msg.name.len(),
+                    };
+                    if !moderation::throttle(context, client_hwid, action).await
+                        || !moderation::authorize(context, client_hwid, Permission::SendMessages)
+                            .await
+                    {
+                        return;
+                    }
+
+                    if let Err(why) = EventHandler::handle_start_upload(msg, context).await {
+                        let event = Outgoing::System {
+                            content: why.to_string(),
+                        };
+                        EventHandler::send_to(clients, client_hwid, event).await;
+                    }
+                }
+            }
+            // Chunks have a bucket of their own, so transfers don't use up the messages
+            ClientMessageType::UploadChunk => {
+                if let Some(mut msg) = Self::decode::<UploadChunk>(buffer, context).await {
+                    msg.hwid = client_hwid.to_string();
+                    if !moderation::throttle(context, client_hwid, Action::Chunk).await {
+                        return;
+                    }
+                    if let Err(why) = EventHandler::handle_upload_chunk(msg, context).await {
+                        let event = Outgoing::System {
+                            content: why.to_string(),
+                        };
+                        EventHandler::send_to(clients, client_hwid, event).await;
+                    }
+                }
+            }
+            ClientMessageType::DownloadChunk => {
+                if let Some(mut msg) = Self::decode::<DownloadChunk>(buffer, context).await {
+                    msg.hwid = client_hwid.to_string();
+                    if !moderation::throttle(context, client_hwid, Action::Chunk).await {
+                        return;
+                    }
+                    if let Err(why) = EventHandler::handle_download_chunk(msg, context).await {
+                        let event = Outgoing::System {
+                            content: why.to_string(),
+                        };
+                        EventHandler::send_to(clients, client_hwid, event).await;
+                    }
+                }
+            }
+
+            ClientMessageType::Ping => {
+                if let Some(ping) = Self::decode::<client::Ping>(buffer, context).await {
+                    let action = Action::Message {
+                        bytes: ping.token.len(),
+                    };
+                    if !moderation::throttle(context, client_hwid, action).await {
+                        return;
+                    }
+                    let event = Outgoing::Pong { token: ping.token };
+                    EventHandler::send_to(clients, client_hwid, event).await;
+                }
+            }
+            // Receiving anything is enough to know the client is alive
+            ClientMessageType::Pong => {}
+
+            _ => {
+                let why = DeserializerError::InvalidMessageType;
+                context.metrics.deserialize_error(&why);
+                EventHandler::handle_unknown_message()
+            }
+        }
+    }
+
+    /// Deserializes a frame of the client, failures are only counted.
+    async fn decode<T: Deserialize>(buffer: &[u8], context: &ServerContext) -> Option<T> {
+        match T::deserialize(buffer).await {
+            Ok(message) => Some(message),
+            Err(why) => {
+                tracing::warn!("Received an invalid message! {why}");
+                context.metrics.deserialize_error(&why);
+                None
+            }
+        }
+    }
+
+    /// Writes every event of the client's outbox to its stream, until the client is removed.
+    async fn forward_outgoing(
+        mut stream: OwnedWriteHalf,
+        mut inbox: mpsc::UnboundedReceiver<Outgoing>,
+        context: Arc<ServerContext>,
+    ) {
+        while let Some(event) = inbox.recv().await {
+            let max_length = max_content_length(context.config().buffer_size);
+            let written = match event {
+                Outgoing::Message {
+                    id,
+                    username,
+                    content,
+                    sent_at,
+                    reply_to,
+                    ..
+                } => {
+                    let message = BroadcastMessage {
+                        id,
+                        username,
+                        content,
+                        timestamp: sent_at.to_string(),
+                        reply_to: reply_to.unwrap_or_default(),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::ThreadMessage {
+                    thread,
+                    room,
+                    id,
+                    username,
+                    content,
+                    sent_at,
+                    reply_to,
+                } => {
+                    let message = ThreadMessage {
+                        thread,
+                        room,
+                        id,
+                        username,
+                        content,
+                        timestamp: sent_at.to_string(),
+                        reply_to: reply_to.unwrap_or_default(),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::Mention {
+                    id,
+                    room,
+                    username,
+                    content,
+                    mention,
+                    sent_at,
+                    ..
+                } => {
+                    let message = Mention {
+                        id,
+                        room,
+                        username,
+                        content,
+                        mention,
+                        timestamp: sent_at.to_string(),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::MessageSent { id, room, sent_at } => {
+                    let message = MessageSent {
+                        id,
+                        room,
+                        timestamp: sent_at.to_string(),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::MessageEdited {
+                    id,
+                    room,
+                    content,
+                    edited_at,
+                } => {
+                    let message = MessageEdited {
+                        id,
+                        room,
+                        content,
+                        edited_at: edited_at.to_string(),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::ReactionsUpdated {
+                    id,
+                    room,
+                    reactions,
+                } => {
+                    let reactions = reactions
+                        .iter()
+                        .map(|(reaction, count)| format!("{reaction}={count}"))
+                        .collect::<Vec<_>>();
+                    // Reactions are short and limited, so only tiny buffers get the notice of `write`
+                    let message = ReactionsUpdated {
+                        id,
+                        room,
+                        reactions: reactions.join(" "),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::Action {
+                    username, content, ..
+                } => {
+                    let message = SystemMessage {
+                        content: format!("* {username} {content}"),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::DirectMessage {
+                    username,
+                    content,
+                    sent_at,
+                } => {
+                    let message = DirectMessage {
+                        username,
+                        content,
+                        timestamp: sent_at.to_string(),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::Welcome { unread, pending } => {
+                    let unread = unread
+                        .iter()
+                        .map(|(room, count)| format!("{room}={count}"))
+                        .collect::<Vec<_>>();
+                    let mut pending = pending.to_string();
+                    let mut written = true;
+                    for unread in join_pages(&unread, max_length) {
+                        let message = Welcome {
+                            unread,
+                            pending: std::mem::replace(&mut pending, "0".to_string()),
+                        };
+                        written = Self::write(&mut stream, &message, &context).await;
+                        if !written {
+                            break;
+                        }
+                    }
+                    written
+                }
+                Outgoing::UploadOffset {
+                    id,
+                    sha256,
+                    offset,
+                    chunk_size,
+                } => {
+                    let message = UploadOffset {
+                        id,
+                        sha256,
+                        offset: offset.to_string(),
+                        chunk_size: chunk_size.to_string(),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::FileShared {
+                    id,
+                    room,
+                    username,
+                    name,
+                    size,
+                    sha256,
+                    uploaded_at,
+                } => {
+                    let message = FileShared {
+                        id,
+                        room,
+                        username,
+                        name,
+                        size: size.to_string(),
+                        sha256,
+                        timestamp: uploaded_at.to_string(),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::FileChunk {
+                    id,
+                    name,
+                    size,
+                    sha256,
+                    offset,
+                    data,
+                } => {
+                    let message = FileChunk {
+                        id,
+                        name,
+                        size: size.to_string(),
+                        sha256,
+                        offset: offset.to_string(),
+                        data: hex::encode(data),
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::System { content } => {
+                    Self::write(&mut stream, &SystemMessage { content }, &context).await
+                }
+                Outgoing::UserJoined { username, room } => {
+                    Self::write(&mut stream, &UserJoined { username, room }, &context).await
+                }
+                Outgoing::UserLeft { username, room } => {
+                    Self::write(&mut stream, &UserLeft { username, room }, &context).await
+                }
+                Outgoing::UsernameChanged {
+                    old_username,
+                    new_username,
+                } => {
+                    let message = UsernameChanged {
+                        old_username,
+                        new_username,
+                    };
+                    Self::write(&mut stream, &message, &context).await
+                }
+                Outgoing::MessageDeleted { id, room } => {
+                    Self::write(&mut stream, &MessageDeleted { id, room }, &context).await
+                }
+                Outgoing::RoomMembers { room, usernames } => {
+                    let total = usernames.len().to_string();
+                    let mut written = true;
+                    for usernames in join_pages(&usernames, max_length) {
+                        let message = RoomMembers {
+                            room: room.clone(),
+                            usernames,
+                            total: total.clone(),
+                        };
+                        written = Self::write(&mut stream, &message, &context).await;
+                        if !written {
+                            break;
+                        }
+                    }
+                    written
+                }
+                Outgoing::Ping { token } => {
+                    Self::write(&mut stream, &Ping { token }, &context).await
+                }
+                Outgoing::Pong { token } => {
+                    Self::write(&mut stream, &Pong { token }, &context).await
+                }
+                Outgoing::Disconnect { reason } => {
+                    Self::write(&mut stream, &SystemMessage { content: reason }, &context).await;
+                    break;
+                }
+                Outgoing::Shutdown {
+                    reason,
+                    reconnect_after,
+                } => {
+                    let message = ServerShutdown {
+                        reason,
+                        reconnect_after: reconnect_after.as_secs().to_string(),
+                    };
+                    Self::write(&mut stream, &message, &context).await;
                     break;
                 }
+            };
+
+            if !written {
+                break;
+            }
+        }
+    }
+
+    /// Pings the client every `interval` seconds, it has to answer before the heartbeat timeout.
+    async fn send_heartbeats(outbox: mpsc::UnboundedSender<Outgoing>, interval_secs: u64) {
+        let mut ticker = interval(Duration::from_secs(interval_secs));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            let event = Outgoing::Ping {
+                token: unix_timestamp().to_string(),
+            };
+            if outbox.send(event).is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn reject(stream: &mut OwnedWriteHalf, address: IpAddr, rejection: Rejection) {
+        tracing::info!(%address, "Rejected connection: {rejection}");
+        let message = SystemMessage {
+            content: rejection.to_string(),
+        };
+        let _ = write_to_stream(stream, &message).await;
+    }
+
+    /// Tells banned clients why they can't connect, returns `true` if the client is banned.
+    async fn reject_banned(
+        stream: &mut OwnedWriteHalf,
+        context: &ServerContext,
+        account: Option<Account>,
+        address: IpAddr,
+    ) -> bool {
+        let hwid = account.as_ref().and_then(|account| account.hwid.as_deref());
+        let Some(ban) = context.moderation.find_ban(hwid, address).await else {
+            return false;
+        };
+
+        tracing::info!("Rejected banned client {address}");
+        context.metrics.auth_failure("native", "banned");
+        let event = AuditEvent::LoginRejected {
+            address,
+            protocol: "native".to_string(),
+            reason: "banned".to_string(),
+        };
+        context.audit.record(account, None, event).await;
+        let message = SystemMessage {
+            content: format!("You are banned {}", ban.describe()),
+        };
+        let _ = write_to_stream(stream, &message).await;
+        true
+    }
+
+    /// Writes the message and counts it, returns `false` if the connection is lost.
+    async fn write<T>(stream: &mut OwnedWriteHalf, message: &T, context: &ServerContext) -> bool
+    where
+        T: Serialize + Deserialize,
+    {
+        let Ok(mut frame) = message.serialize().await else {
+            tracing::error!("Unable to serialize message");
+            return false;
+        };
+        // The client would drop the connection over a frame longer than its buffer, it is told
+        // what was left out instead
+        let buffer_size = context.config().buffer_size;
+        if frame.len() - 5 > buffer_size {
+            let message_type = ServerMessageType::from(frame[0]).name();
+            tracing::warn!(
+                bytes = frame.len(),
+                message_type,
+                "Left out a message which is too long"
+            );
+            let notice = SystemMessage {
+                content: format!(
+                    "The server left out a {message_type} which is longer than {buffer_size} bytes"
+                ),
+            };
+            let Ok(notice) = notice.serialize().await else {
+                return false;
+            };
+            frame = notice;
+        }
+
+        if let Err(why) = stream.write_all(&frame).await {
+            tracing::warn!("Unable to write to stream! {why}");
+            return false;
+        }
+        let message_type = ServerMessageType::from(frame[0]);
+        context
+            .metrics
+            .sent("native", message_type.name(), frame.len());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Server;
+    use crate::{
+        commands::CommandRegistry,
+        types::{Client, Config, Outgoing},
+    };
+    use chat_shared::{
+        protocols::{
+            client::Ping,
+            server::{ServerMessageType, SystemMessage, Welcome},
+        },
+        types::{Deserialize, Serialize},
+        utils::read_frame,
+    };
+    use std::{collections::HashMap, sync::atomic::Ordering, time::Duration};
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+        sync::mpsc,
+        time::{sleep, timeout},
+    };
+
+    /// A server with a single client, and the client's end of its connection.
+    async fn connected(name: &str, config: Config) -> (Server, TcpStream, TcpStream) {
+        let server = Server::with_commands(config, CommandRegistry::default())
+            .await
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let remote = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (local, address) = listener.accept().await.unwrap();
+
+        let client = Client {
+            name: name.to_string(),
+            hwid: "hwid".to_string(),
+            session_token: "token".to_string(),
+            room: None,
+            address: address.ip(),
+        };
+        let (outbox, _) = mpsc::unbounded_channel();
+        *server.context.connected_clients.lock().await =
+            HashMap::from([(client.hwid.clone(), (outbox, client))]);
+        (server, local, remote)
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_timeout() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = Config::in_directory(directory.path());
+        config.heartbeat.timeout = 1;
+        let (server, local, _remote) = connected("Alice", config).await;
+
+        // The client stays connected, but never sends anything
+        let (read_stream, _) = local.into_split();
+        let context = &server.context;
+        let connection = Server::handle_connection(read_stream, "hwid", "token", context);
+        timeout(Duration::from_secs(3), connection).await.unwrap();
+        assert!(context.connected_clients.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = Config::in_directory(directory.path());
+        config.heartbeat.timeout = 5;
+        config.connections.idle_timeout = 1;
+        let (server, local, mut remote) = connected("Bob", config).await;
+
+        // Heartbeats keep the connection alive, but the client is idle nonetheless
+        tokio::spawn(async move {
+            let ping = Ping {
+                token: "token".to_string(),
+            };
+            let frame = ping.serialize().await.unwrap();
+            while remote.write_all(&frame).await.is_ok() {
+                sleep(Duration::from_millis(200)).await;
+            }
+        });
+        let (read_stream, _) = local.into_split();
+        let context = &server.context;
+        let connection = Server::handle_connection(read_stream, "hwid", "token", context);
+        timeout(Duration::from_secs(3), connection).await.unwrap();
+        let metrics = &context.connection_limiter.metrics;
+        assert_eq!(metrics.idle_timeouts.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_long_events() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut config = Config::in_directory(directory.path());
+        config.buffer_size = 256;
+        let (server, local, mut remote) = connected("Carol", config).await;
+
+        let (outbox, inbox) = mpsc::unbounded_channel();
+        let unread = (0..6).map(|n| (format!("room-{n:020}"), n)).collect();
+        outbox
+            .send(Outgoing::Welcome { unread, pending: 2 })
+            .unwrap();
+        let reactions = (0..10).map(|n| (format!(":{n:030}:"), 1)).collect();
+        outbox
+            .send(Outgoing::ReactionsUpdated {
+                id: "1".to_string(),
+                room: "general".to_string(),
+                reactions,
+            })
+            .unwrap();
+        drop(outbox);
+        let (_, write_stream) = local.into_split();
+        Server::forward_outgoing(write_stream, inbox, server.context.clone()).await;
+
+        // Every room arrives, the reactions which don't fit are replaced by a notice
+        let mut welcomes = Vec::new();
+        while let Some(frame) = read_frame(&mut remote, 256).await.unwrap() {
+            if ServerMessageType::from(frame[0]) != ServerMessageType::Welcome {
+                let notice = SystemMessage::deserialize(&frame).await.unwrap();
+                assert!(notice.content.contains("reactions_updated"));
+                break;
             }
+            welcomes.push(Welcome::deserialize(&frame).await.unwrap());
         }
+        let rooms = welcomes.iter().flat_map(|w| w.unread.split_whitespace());
+        assert_eq!(rooms.count(), 6);
+        let pending = welcomes.iter().map(|w| w.pending.as_str());
+        assert_eq!(pending.collect::<Vec<_>>(), ["2", "0", "0"]);
     }
 }
@@ -0,0 +1,129 @@
+use crate::{types::Client, utils::unix_timestamp};
+use std::{collections::HashMap, io, path::PathBuf, sync::RwLock, time::Duration};
+use tokio::sync::Mutex;
+
+/// What is restored when a client resumes its session.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SavedSession {
+    pub hwid: String,
+    pub name: String,
+    pub room: Option<String>,
+    /// Unix timestamp after which the session can't be resumed anymore
+    pub expires_at: u64,
+}
+
+/// Sessions of lost connections, keyed by their session token, until they expire. They are
+/// written to a file, so clients can resume them after the server restarted.
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, SavedSession>>,
+    file: PathBuf,
+    timeout: RwLock<Duration>,
+}
+
+impl Sessions {
+    pub async fn load(file: PathBuf, timeout: Duration) -> io::Result<Self> {
+        let sessions = match tokio::fs::read(&file).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(why) => return Err(why),
+        };
+
+        Ok(Self {
+            sessions: Mutex::new(sessions),
+            file,
+            timeout: RwLock::new(timeout),
+        })
+    }
+
+    /// Applies to sessions which are saved from now on.
+    pub fn reconfigure(&self, timeout: Duration) {
+        *self.timeout.write().unwrap() = timeout;
+    }
+
+    async fn persist(&self, sessions: &HashMap<String, SavedSession>) {
+        let contents = match serde_json::to_vec(sessions) {
+            Ok(contents) => contents,
+            Err(why) => {
+                tracing::error!("Unable to serialize the sessions! {why}");
+                return;
+            }
+        };
+
+        if let Err(why) = tokio::fs::write(&self.file, contents).await {
+            tracing::error!("Unable to write {}! {why}", self.file.display());
+        }
+    }
+
+    pub async fn save(&self, client: Client) {
+        let timeout = *self.timeout.read().unwrap();
+        let mut sessions = self.sessions.lock().await;
+        let now = unix_timestamp();
+        sessions.retain(|_, session| session.expires_at > now);
+
+        let session = SavedSession {
+            hwid: client.hwid,
+            name: client.name,
+            room: client.room,
+            expires_at: now.saturating_add(timeout.as_secs()),
+        };
+        sessions.insert(client.session_token, session);
+        self.persist(&sessions).await;
+    }
+
+    /// Takes the session if it hasn't expired and belongs to the HWID.
+    pub async fn resume(&self, token: &str, hwid: &str) -> Option<SavedSession> {
+        let mut sessions = self.sessions.lock().await;
+        let session = sessions.remove(token)?;
+        self.persist(&sessions).await;
+
+        (session.expires_at > unix_timestamp() && session.hwid.eq(hwid)).then_some(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sessions;
+    use crate::types::Client;
+    use std::time::Duration;
+
+    fn client() -> Client {
+        Client {
+            name: "Phill030".to_string(),
+            hwid: "hwid".to_string(),
+            session_token: "token".to_string(),
+            room: Some("rust".to_string()),
+            address: "127.0.0.1".parse().unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resume_session() {
+        let directory = tempfile::tempdir().unwrap();
+        let file = directory.path().join("sessions.json");
+        let sessions = Sessions::load(file.clone(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        sessions.save(client()).await;
+
+        assert_eq!(sessions.resume("token", "other").await, None);
+        sessions.save(client()).await;
+        // Sessions survive a restart
+        let sessions = Sessions::load(file.clone(), Duration::from_secs(60))
+            .await
+            .unwrap();
+        let session = sessions.resume("token", "hwid").await.unwrap();
+        assert_eq!(session.room.as_deref(), Some("rust"));
+        // Sessions can only be resumed once
+        assert_eq!(sessions.resume("token", "hwid").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_expired_session() {
+        let directory = tempfile::tempdir().unwrap();
+        let file = directory.path().join("sessions.json");
+        let sessions = Sessions::load(file, Duration::ZERO).await.unwrap();
+        sessions.save(client()).await;
+        assert_eq!(sessions.resume("token", "hwid").await, None);
+    }
+}
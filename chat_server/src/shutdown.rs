@@ -0,0 +1,64 @@
+use crate::{
+    event_handler::EventHandler,
+    types::{Outgoing, ServerContext},
+};
+use std::time::Duration;
+use tokio::time::timeout;
+
+/// Resolves with the name of the signal once the server should stop.
+pub async fn signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => tokio::select! {
+                name = ctrl_c() => name,
+                _ = terminate.recv() => "SIGTERM",
+            },
+            Err(why) => {
+                tracing::error!("Unable to listen for SIGTERM! {why}");
+                ctrl_c().await
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    ctrl_c().await
+}
+
+async fn ctrl_c() -> &'static str {
+    if let Err(why) = tokio::signal::ctrl_c().await {
+        tracing::error!("Unable to listen for SIGINT! {why}");
+        // Never stop because of it
+        std::future::pending::<()>().await;
+    }
+    "SIGINT"
+}
+
+/// Tells every client why the server stops, then waits until their connections are closed.
+/// Moderation changes are written by the connection which made them, so they are waited for too.
+pub async fn drain(context: &ServerContext, reason: &str) {
+    let config = context.config();
+    let event = Outgoing::Shutdown {
+        reason: reason.to_string(),
+        reconnect_after: Duration::from_secs(config.shutdown.reconnect_after),
+    };
+    EventHandler::broadcast(&context.connected_clients, None, event).await;
+
+    // Connections stop reading, but still write the events which are left in their outbox
+    context.shutdown.cancel();
+    context.tasks.close();
+
+    let connections = context.tasks.len();
+    if connections > 0 {
+        tracing::info!("Waiting for {connections} tasks of open connections...");
+    }
+    let timeout_after = Duration::from_secs(config.shutdown.timeout);
+    if timeout(timeout_after, context.tasks.wait()).await.is_err() {
+        tracing::warn!(
+            "{} tasks did not finish in time, closing their connections",
+            context.tasks.len()
+        );
+    }
+}
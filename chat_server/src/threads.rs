@@ -0,0 +1,83 @@
+use std::collections::{HashMap, HashSet};
+use tokio::sync::Mutex;
+
+/// How many threads a single connection can follow at once.
+pub const MAX_FOLLOWED_THREADS: usize = 50;
+
+/// Which connections follow which threads. Threads are keyed by the id of their first message,
+/// connections by their session token, so reconnecting clients have to follow them again.
+#[derive(Default)]
+pub struct Followers {
+    threads: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+impl Followers {
+    /// Returns `false` if the connection already follows too many threads.
+    pub async fn follow(&self, thread: &str, session_token: &str) -> bool {
+        let mut threads = self.threads.lock().await;
+        let followed = threads
+            .values()
+            .filter(|sessions| sessions.contains(session_token))
+            .count();
+        if followed >= MAX_FOLLOWED_THREADS {
+            return false;
+        }
+
+        threads
+            .entry(thread.to_string())
+            .or_default()
+            .insert(session_token.to_string());
+        true
+    }
+
+    /// Returns `false` if the thread was not followed.
+    pub async fn unfollow(&self, thread: &str, session_token: &str) -> bool {
+        let mut threads = self.threads.lock().await;
+        let Some(sessions) = threads.get_mut(thread) else {
+            return false;
+        };
+
+        let removed = sessions.remove(session_token);
+        if sessions.is_empty() {
+            threads.remove(thread);
+        }
+        removed
+    }
+
+    /// The session tokens of the connections following the thread.
+    pub async fn of(&self, thread: &str) -> HashSet<String> {
+        let threads = self.threads.lock().await;
+        threads.get(thread).cloned().unwrap_or_default()
+    }
+
+    pub async fn forget_connection(&self, session_token: &str) {
+        let mut threads = self.threads.lock().await;
+        threads.retain(|_, sessions| {
+            sessions.remove(session_token);
+            !sessions.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Followers, MAX_FOLLOWED_THREADS};
+
+    #[tokio::test]
+    async fn test_followers() {
+        let followers = Followers::default();
+        assert!(followers.follow("1", "a").await);
+        assert!(followers.follow("1", "b").await);
+        assert_eq!(followers.of("1").await.len(), 2);
+
+        assert!(followers.unfollow("1", "a").await);
+        assert!(!followers.unfollow("1", "a").await);
+        followers.forget_connection("b").await;
+        assert!(followers.of("1").await.is_empty());
+
+        for thread in 0..MAX_FOLLOWED_THREADS {
+            assert!(followers.follow(&thread.to_string(), "a").await);
+        }
+        assert!(!followers.follow("too many", "a").await);
+    }
+}
@@ -1,4 +1,35 @@
-use std::net::SocketAddr;
+use crate::{
+    audit::AuditLog,
+    commands::CommandRegistry,
+    files::FileStore,
+    history::History,
+    limits::ConnectionLimiter,
+    mailbox::Mailbox,
+    metrics::Metrics,
+    moderation::{Moderation, RateLimiter, Role},
+    sessions::Sessions,
+    threads::Followers,
+};
+use chat_shared::{
+    config::{check_buffer_size, check_endpoint, check_nonzero, ConfigFile, InvalidValue},
+    logging::LogConfig,
+    utils::{chunk_size, max_content_length},
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::sync::{mpsc::UnboundedSender, Mutex};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+
+/// All authenticated clients, keyed by their HWID.
+pub type ClientList = Arc<Mutex<HashMap<String, (Outbox, Client)>>>;
+
+/// Every connection drains its own `Outbox` and translates the events into its protocol.
+pub type Outbox = UnboundedSender<Outgoing>;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone, Eq, Hash)]
 pub struct Client {
@@ -8,12 +39,202 @@ pub struct Client {
     /// A Session-Token is a randomly generated String which changes on every reconnect.
     /// It can be used to validate the session of a client.
     pub session_token: String,
+    /// The room the client currently chats in, IRC users may leave every room
+    pub room: Option<String>,
+    /// The IP the client connected from
+    pub address: IpAddr,
+}
+
+/// Everything a connection needs to access, shared between all connections.
+pub struct ServerContext {
+    pub connected_clients: ClientList,
+    /// Replaced when the config file is reloaded, see `config()`
+    pub config: RwLock<Arc<Config>>,
+    pub commands: CommandRegistry,
+    pub moderation: Moderation,
+    pub history: History,
+    pub rate_limiter: RateLimiter,
+    pub connection_limiter: ConnectionLimiter,
+    pub sessions: Sessions,
+    pub followers: Followers,
+    pub metrics: Metrics,
+    pub audit: AuditLog,
+    pub mailbox: Mailbox,
+    pub files: FileStore,
+    /// Cancelled once the server stops, connections stop reading then
+    pub shutdown: CancellationToken,
+    /// Every task of a connection, so the shutdown can wait for them
+    pub tasks: TaskTracker,
+}
+
+impl ServerContext {
+    /// The current config, settings are read again for every use so reloads take effect.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
 }
 
-#[derive(serde::Deserialize, serde::Serialize, Debug)]
+/// Events which are delivered to connected clients, independent of the protocol they speak.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outgoing {
+    Message {
+        id: String,
+        username: String,
+        room: String,
+        content: String,
+        /// Unix timestamp
+        sent_at: u64,
+        reply_to: Option<String>,
+    },
+    /// A message of a thread, fetched or followed from outside of its room
+    ThreadMessage {
+        thread: String,
+        room: String,
+        id: String,
+        username: String,
+        content: String,
+        sent_at: u64,
+        reply_to: Option<String>,
+    },
+    /// The client was mentioned in a message, in its room or in another one
+    Mention {
+        id: String,
+        room: String,
+        username: String,
+        content: String,
+        mention: String,
+        sent_at: u64,
+        /// Kept while the client was offline
+        missed: bool,
+    },
+    /// The id of a message the client sent, it does not get the message itself
+    MessageSent {
+        id: String,
+        room: String,
+        sent_at: u64,
+    },
+    MessageEdited {
+        id: String,
+        room: String,
+        content: String,
+        edited_at: u64,
+    },
+    /// Every reaction to a message with how often it was given
+    ReactionsUpdated {
+        id: String,
+        room: String,
+        reactions: Vec<(String, usize)>,
+    },
+    /// A `/me` message, e.g. "* Phill030 waves"
+    Action {
+        username: String,
+        room: String,
+        content: String,
+    },
+    DirectMessage {
+        username: String,
+        content: String,
+        sent_at: u64,
+    },
+    /// Unread messages per room and how many direct messages and mentions were kept for the
+    /// client while it was offline, sent once after logging in
+    Welcome {
+        unread: Vec<(String, usize)>,
+        pending: usize,
+    },
+    /// Where an upload of the client continues
+    UploadOffset {
+        id: String,
+        sha256: String,
+        offset: u64,
+        chunk_size: usize,
+    },
+    /// A file was uploaded completely, sent to everyone in its room
+    FileShared {
+        id: String,
+        room: String,
+        username: String,
+        name: String,
+        size: u64,
+        sha256: String,
+        uploaded_at: u64,
+    },
+    /// A part of a file the client downloads
+    FileChunk {
+        id: String,
+        name: String,
+        size: u64,
+        sha256: String,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// A message from the server itself, e.g. the reply to a command
+    System {
+        content: String,
+    },
+    UserJoined {
+        username: String,
+        room: String,
+    },
+    UserLeft {
+        username: String,
+        room: String,
+    },
+    UsernameChanged {
+        old_username: String,
+        new_username: String,
+    },
+    MessageDeleted {
+        id: String,
+        room: String,
+    },
+    /// Sent to a client which joined a room
+    RoomMembers {
+        room: String,
+        usernames: Vec<String>,
+    },
+    /// Checks whether the client is still alive
+    Ping {
+        token: String,
+    },
+    /// The answer to a `Ping` of the client
+    Pong {
+        token: String,
+    },
+    /// Closes the connection after telling the client why
+    Disconnect {
+        reason: String,
+    },
+    /// Closes the connection because the server stops
+    Shutdown {
+        reason: String,
+        reconnect_after: Duration,
+    },
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
     pub endpoint: SocketAddr,
     pub buffer_size: usize,
+    /// The room clients are put into after authenticating
+    pub default_room: String,
+    /// Shown to clients after they logged in, empty for none
+    pub motd: String,
+    /// How many of the most recent messages of each room can be referenced by their id. They
+    /// are only kept in memory, so they are gone once the server restarts.
+    pub history_size: usize,
+    pub log: LogConfig,
+    pub moderation: ModerationConfig,
+    pub connections: ConnectionConfig,
+    pub heartbeat: HeartbeatConfig,
+    pub rate_limit: RateLimitConfig,
+    pub irc: IrcConfig,
+    pub shutdown: ShutdownConfig,
+    pub metrics: MetricsConfig,
+    pub audit: AuditConfig,
+    pub mailbox: MailboxConfig,
+    pub files: FilesConfig,
 }
 
 impl Default for Config {
@@ -21,6 +242,345 @@ impl Default for Config {
         Self {
             endpoint: "127.0.0.1:7878".parse().unwrap(),
             buffer_size: 2048,
+            default_room: "general".to_string(),
+            motd: String::new(),
+            history_size: 1000,
+            log: LogConfig::default(),
+            moderation: ModerationConfig::default(),
+            connections: ConnectionConfig::default(),
+            heartbeat: HeartbeatConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            irc: IrcConfig::default(),
+            shutdown: ShutdownConfig::default(),
+            metrics: MetricsConfig::default(),
+            audit: AuditConfig::default(),
+            mailbox: MailboxConfig::default(),
+            files: FilesConfig::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Keeps every file of a test in `directory`, which is created.
+    pub fn in_directory(directory: &std::path::Path) -> Self {
+        std::fs::create_dir_all(directory).unwrap();
+        let mut config = Self {
+            endpoint: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        };
+        config.moderation.file = directory.join("moderation.json");
+        config.audit.file = directory.join("audit.jsonl");
+        config.mailbox.file = directory.join("mailbox.json");
+        config.connections.sessions_file = directory.join("sessions.json");
+        config.files.directory = directory.join("files");
+        config
+    }
+}
+
+impl ConfigFile for Config {
+    fn validate(&self) -> Result<(), InvalidValue> {
+        check_endpoint("endpoint", self.endpoint)?;
+        check_buffer_size("buffer_size", self.buffer_size)?;
+        if self.default_room.is_empty() || self.default_room.contains(char::is_whitespace) {
+            return Err(InvalidValue::new("default_room", "must be a single word"));
+        }
+        if self.motd.len() > max_content_length(self.buffer_size) {
+            return Err(InvalidValue::new(
+                "motd",
+                "must fit into a single message, increase buffer_size or shorten it",
+            ));
+        }
+        self.log.validate("log")?;
+        if self.irc.enabled {
+            check_endpoint("irc.endpoint", self.irc.endpoint)?;
+        }
+        if self.metrics.enabled {
+            check_endpoint("metrics.endpoint", self.metrics.endpoint)?;
+        }
+
+        check_nonzero(
+            "connections.max_connections",
+            self.connections.max_connections,
+        )?;
+        check_nonzero(
+            "connections.max_connections_per_ip",
+            self.connections.max_connections_per_ip,
+        )?;
+        check_nonzero("connections.auth_timeout", self.connections.auth_timeout)?;
+
+        if self.files.enabled {
+            check_nonzero("files.max_file_size", self.files.max_file_size)?;
+            check_nonzero("files.max_size_per_user", self.files.max_size_per_user)?;
+            if chunk_size(self.buffer_size) == 0 {
+                return Err(InvalidValue::new(
+                    "buffer_size",
+                    "is too small for file transfers, use at least 1024 bytes or disable them",
+                ));
+            }
+        }
+
+        check_nonzero("heartbeat.interval", self.heartbeat.interval)?;
+        if self.heartbeat.timeout <= self.heartbeat.interval {
+            return Err(InvalidValue::new(
+                "heartbeat.timeout",
+                "must be longer than heartbeat.interval",
+            ));
+        }
+
+        if self.rate_limit.enabled {
+            let rate = self.rate_limit.messages_per_second;
+            if rate.is_nan() || rate <= 0.0 {
+                return Err(InvalidValue::new(
+                    "rate_limit.messages_per_second",
+                    "must be greater than 0",
+                ));
+            }
+            check_nonzero("rate_limit.message_burst", self.rate_limit.message_burst)?;
+            check_nonzero(
+                "rate_limit.bytes_per_second",
+                self.rate_limit.bytes_per_second,
+            )?;
+            check_nonzero(
+                "rate_limit.joins_per_minute",
+                self.rate_limit.joins_per_minute,
+            )?;
+            check_nonzero(
+                "rate_limit.chunks_per_second",
+                self.rate_limit.chunks_per_second,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct IrcConfig {
+    /// Whether the IRC-compatible listener should be started
+    pub enabled: bool,
+    pub endpoint: SocketAddr,
+    /// The name the bridge introduces itself with
+    pub server_name: String,
+}
+
+impl Default for IrcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "127.0.0.1:6667".parse().unwrap(),
+            server_name: "rust_chat".to_string(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Whether `/metrics` should be served in the Prometheus text format
+    pub enabled: bool,
+    /// Keep it on a private interface, the metrics are not authenticated
+    pub endpoint: SocketAddr,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "127.0.0.1:9100".parse().unwrap(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Whether logins, renames, moderation and config changes should be recorded
+    pub enabled: bool,
+    /// Entries are only ever appended, see `chat_server audit verify`
+    pub file: PathBuf,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: PathBuf::from("audit.jsonl"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct MailboxConfig {
+    /// Whether direct messages and mentions are kept for offline users and read markers are
+    /// remembered
+    pub enabled: bool,
+    pub file: PathBuf,
+    /// How much is kept for a single offline user, the oldest is dropped first
+    pub capacity: usize,
+}
+
+impl Default for MailboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: PathBuf::from("mailbox.json"),
+            capacity: 100,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct FilesConfig {
+    /// Whether clients can upload files to their room and download shared files
+    pub enabled: bool,
+    /// Holds the files, named after their id, and their index
+    pub directory: PathBuf,
+    /// In bytes
+    pub max_file_size: u64,
+    /// In bytes, of all files together including unfinished uploads
+    pub max_total_size: u64,
+    /// In bytes, of all files of one account including its unfinished uploads
+    pub max_size_per_user: u64,
+    /// Seconds shared files are kept after their upload finished, 0 keeps them forever
+    pub expire_after: u64,
+}
+
+impl Default for FilesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            directory: PathBuf::from("files"),
+            max_file_size: 10 * 1024 * 1024,
+            max_total_size: 1024 * 1024 * 1024,
+            max_size_per_user: 100 * 1024 * 1024,
+            expire_after: 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// Seconds the connections get to close before the server stops anyway
+    pub timeout: u64,
+    /// Seconds clients are asked to wait before reconnecting, e.g. the time a restart takes
+    pub reconnect_after: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 10,
+            reconnect_after: 5,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ModerationConfig {
+    /// Where roles, bans and mutes are persisted
+    pub file: PathBuf,
+    /// The role of accounts which were never assigned one
+    pub default_role: Role,
+    /// HWIDs of the accounts which always have the owner role
+    pub owners: Vec<String>,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::from("moderation.json"),
+            default_role: Role::Member,
+            owners: Vec::new(),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    /// Open connections of both listeners, including unauthenticated ones
+    pub max_connections: usize,
+    pub max_connections_per_ip: usize,
+    /// Seconds a connection may take to authenticate (or register via IRC)
+    pub auth_timeout: u64,
+    /// Seconds without chat activity after which a connection is closed, heartbeats don't
+    /// count as activity. 0 keeps idle connections open
+    pub idle_timeout: u64,
+    /// Seconds a lost session can be resumed for
+    pub resume_timeout: u64,
+    /// Where lost sessions are kept, so they can be resumed after a restart
+    pub sessions_file: PathBuf,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            max_connections: 1000,
+            max_connections_per_ip: 10,
+            auth_timeout: 10,
+            idle_timeout: 0,
+            resume_timeout: 120,
+            sessions_file: PathBuf::from("sessions.json"),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct HeartbeatConfig {
+    /// Seconds between the pings sent to every client
+    pub interval: u64,
+    /// Seconds without receiving anything after which a client is considered dead
+    pub timeout: u64,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: 30,
+            timeout: 90,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub messages_per_second: f64,
+    /// How many messages can be sent at once before the rate applies
+    pub message_burst: u32,
+    /// Also the largest message which can be sent at once
+    pub bytes_per_second: u32,
+    pub joins_per_minute: u32,
+    /// Chunks of uploads and downloads, their size is limited by `buffer_size`
+    pub chunks_per_second: u32,
+    /// Violations which are answered with a warning, the next one mutes and any further disconnects
+    pub warnings: u32,
+    /// In seconds
+    pub mute_duration: u64,
+    /// Seconds without violations after which the warnings start over
+    pub strike_reset: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            messages_per_second: 2.0,
+            message_burst: 5,
+            bytes_per_second: 4096,
+            joins_per_minute: 10,
+            chunks_per_second: 100,
+            warnings: 2,
+            mute_duration: 60,
+            strike_reset: 300,
         }
     }
 }
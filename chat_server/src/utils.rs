@@ -1,41 +1,183 @@
-use std::{io::Write, net::TcpStream};
-
 use chat_shared::{
     error::WriteToStreamError,
     types::{Deserialize, Serialize},
+    utils::MAX_FILE_NAME_LENGTH,
 };
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-pub async fn write_to_stream<T>(
-    mut stream: &TcpStream,
-    content: &T,
-) -> Result<bool, WriteToStreamError>
+pub async fn write_to_stream<W, T>(stream: &mut W, content: &T) -> Result<bool, WriteToStreamError>
 where
+    W: AsyncWrite + Unpin,
     T: Serialize + Deserialize,
 {
     let Ok(serialized) = &content.serialize().await else {
-        log::error!("Unable to serialize message");
+        tracing::error!("Unable to serialize message");
         return Ok(false);
     };
 
-    if stream.write_all(&serialized[..]).is_ok() {
-        log::info!("[✔] Message broadcasted!");
-        Ok(true)
-    } else {
-        log::warn!("[❌] There was an error broadcasting the message");
-        Ok(false)
+    match stream.write_all(&serialized[..]).await {
+        Ok(_) => {
+            tracing::trace!(bytes = serialized.len(), "Message written");
+            Ok(true)
+        }
+        Err(why) => {
+            tracing::warn!("Unable to write the message! {why}");
+            Ok(false)
+        }
     }
 }
 
 pub fn check_username(name: &str) -> String {
-    if name.is_empty() || name.len() > 32 || !is_alphanumeric_with_symbols(name) {
+    if !is_valid_username(name) {
         return format!("User{}", rand::prelude::random::<i16>());
     }
 
     name.to_string()
 }
 
+/// Characters with a meaning in IRC prefixes like `nick!user@host` and message targets, names
+/// containing them could not be shown to or addressed by IRC users.
+pub const IRC_RESERVED: [char; 8] = [':', '!', '@', '#', '&', ',', '*', '?'];
+
+/// Everyone shows up on the IRC bridge as well, so usernames are valid IRC nicks.
+pub fn is_valid_username(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && is_alphanumeric_with_symbols(name)
+        && !name.contains(IRC_RESERVED)
+}
+
+pub fn is_valid_room_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 32
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Either a shortcode like `:thumbs_up:` or a short run of emoji, which are never ASCII.
+pub fn is_valid_reaction(reaction: &str) -> bool {
+    let shortcode = reaction
+        .strip_prefix(':')
+        .and_then(|r| r.strip_suffix(':'))
+        .is_some_and(|name| {
+            !name.is_empty()
+                && name.len() <= 32
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "_+-".contains(c))
+        });
+    let emoji = !reaction.is_empty()
+        && reaction.len() <= 32
+        && reaction
+            .chars()
+            .all(|c| !c.is_ascii() && !c.is_whitespace() && !c.is_control());
+
+    shortcode || emoji
+}
+
+/// A plain file name, without directories or control characters.
+pub fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_FILE_NAME_LENGTH
+        && !matches!(name, "." | "..")
+        && !name
+            .chars()
+            .any(|c| c.is_control() || matches!(c, '/' | '\\'))
+}
+
 pub fn is_alphanumeric_with_symbols(input: &str) -> bool {
     input
         .chars()
         .all(|c| c.is_alphanumeric() || c.is_ascii_punctuation())
 }
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// The unix timestamp `duration` from now, long durations end at the end of time instead of
+/// overflowing.
+pub fn expires_at(duration: Duration) -> u64 {
+    unix_timestamp().saturating_add(duration.as_secs())
+}
+
+/// Formats seconds in a human readable way, e.g. "1h 5m" or "42s".
+pub fn format_duration(seconds: u64) -> String {
+    let units = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+
+    let parts = units
+        .iter()
+        .scan(seconds, |remaining, (unit, size)| {
+            let amount = *remaining / size;
+            *remaining %= size;
+            Some((amount, unit))
+        })
+        .filter(|(amount, _)| *amount > 0)
+        .take(2)
+        .map(|(amount, unit)| format!("{amount}{unit}"))
+        .collect::<Vec<_>>();
+
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Joins the items with spaces into as few pages of at most `max_length` bytes as possible, so
+/// long lists can be split over several frames. An empty list is a single empty page.
+pub fn join_pages(items: &[String], max_length: usize) -> Vec<String> {
+    let mut pages = vec![String::new()];
+    for item in items {
+        let page = pages.last_mut().expect("There is always a page");
+        if page.is_empty() {
+            page.push_str(item);
+        } else if page.len() + 1 + item.len() <= max_length {
+            page.push(' ');
+            page.push_str(item);
+        } else {
+            pages.push(item.clone());
+        }
+    }
+
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expires_at, is_valid_username, join_pages, unix_timestamp};
+    use chat_shared::arguments::DurationArg;
+    use std::time::Duration;
+
+    #[test]
+    fn test_join_pages() {
+        let items = ["alice", "bob", "carol", "dave"].map(str::to_string);
+        assert_eq!(join_pages(&items, 9), ["alice bob", "carol", "dave"]);
+        assert_eq!(join_pages(&items, 64), ["alice bob carol dave"]);
+        assert_eq!(join_pages(&[], 64), [""]);
+    }
+
+    #[test]
+    fn test_expires_at() {
+        let hour = expires_at(Duration::from_secs(3600));
+        assert!(hour >= unix_timestamp() + 3600);
+        // The longest duration a moderator can give
+        let DurationArg(longest) = format!("{}w", u64::MAX).parse().unwrap();
+        assert_eq!(expires_at(longest), u64::MAX);
+    }
+
+    #[test]
+    fn test_valid_username() {
+        assert!(is_valid_username("Phill030"));
+        assert!(is_valid_username("[away]_name"));
+        assert!(!is_valid_username("nick!user@host"));
+        assert!(!is_valid_username(":prefix"));
+        assert!(!is_valid_username("with space"));
+        assert!(!is_valid_username(""));
+    }
+}
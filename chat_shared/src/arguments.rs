@@ -0,0 +1,166 @@
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum ArgumentError {
+    #[error("Missing argument <{0}>")]
+    Missing(&'static str),
+    #[error("Invalid value '{value}' for <{name}>: {reason}")]
+    Invalid {
+        name: &'static str,
+        value: String,
+        reason: String,
+    },
+}
+
+/// The arguments of a command, consumed from left to right.
+#[derive(Debug)]
+pub struct Arguments {
+    remaining: String,
+}
+
+impl Arguments {
+    pub fn new(arguments: &str) -> Self {
+        Self {
+            remaining: arguments.trim().to_string(),
+        }
+    }
+
+    /// Parses the next whitespace separated argument.
+    pub fn required<T>(&mut self, name: &'static str) -> Result<T, ArgumentError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional(name)?.ok_or(ArgumentError::Missing(name))
+    }
+
+    pub fn optional<T>(&mut self, name: &'static str) -> Result<Option<T>, ArgumentError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        if self.remaining.is_empty() {
+            return Ok(None);
+        }
+
+        let (value, remaining) = self
+            .remaining
+            .split_once(char::is_whitespace)
+            .unwrap_or((&self.remaining, ""));
+        let parsed = value.parse::<T>().map_err(|why| ArgumentError::Invalid {
+            name,
+            value: value.to_string(),
+            reason: why.to_string(),
+        })?;
+
+        self.remaining = remaining.trim_start().to_string();
+        Ok(Some(parsed))
+    }
+
+    /// Parses the next argument, but only consumes it if it is valid.
+    pub fn try_optional<T: FromStr>(&mut self) -> Option<T> {
+        let (value, remaining) = self
+            .remaining
+            .split_once(char::is_whitespace)
+            .unwrap_or((&self.remaining, ""));
+        let parsed = value.parse::<T>().ok()?;
+
+        self.remaining = remaining.trim_start().to_string();
+        Some(parsed)
+    }
+
+    /// Takes all remaining text, which may be empty.
+    pub fn remaining(&mut self) -> String {
+        std::mem::take(&mut self.remaining)
+    }
+
+    /// Takes all remaining text, which must not be empty.
+    pub fn rest(&mut self, name: &'static str) -> Result<String, ArgumentError> {
+        if self.remaining.is_empty() {
+            return Err(ArgumentError::Missing(name));
+        }
+
+        Ok(std::mem::take(&mut self.remaining))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.remaining.is_empty()
+    }
+}
+
+/// A duration argument like `30s`, `10m`, `2h`, `1d` or `1w`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DurationArg(pub Duration);
+
+impl FromStr for DurationArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || "expected a duration like 30s, 10m, 2h, 1d or 1w".to_string();
+
+        let unit_start = s.find(|c: char| !c.is_ascii_digit()).ok_or_else(error)?;
+        let (amount, unit) = s.split_at(unit_start);
+        let amount = amount.parse::<u64>().map_err(|_| error())?;
+        let seconds = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 604800,
+            _ => return Err(error()),
+        };
+
+        Ok(Self(Duration::from_secs(amount.saturating_mul(seconds))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArgumentError, Arguments, DurationArg};
+    use std::time::Duration;
+
+    #[test]
+    fn test_typed_arguments() {
+        let mut arguments = Arguments::new("  Phill030 42   hello there ");
+        assert_eq!(
+            arguments.required::<String>("username").unwrap(),
+            "Phill030"
+        );
+        assert_eq!(arguments.optional::<u32>("count").unwrap(), Some(42));
+        assert_eq!(arguments.rest("message").unwrap(), "hello there");
+        assert!(arguments.is_empty());
+        assert_eq!(arguments.optional::<u32>("count").unwrap(), None);
+        assert_eq!(
+            arguments.required::<String>("username"),
+            Err(ArgumentError::Missing("username"))
+        );
+    }
+
+    #[test]
+    fn test_invalid_argument() {
+        let mut arguments = Arguments::new("many");
+        assert!(matches!(
+            arguments.required::<u32>("count"),
+            Err(ArgumentError::Invalid { name: "count", .. })
+        ));
+    }
+
+    #[test]
+    fn test_duration_argument() {
+        assert_eq!(
+            "90s".parse::<DurationArg>(),
+            Ok(DurationArg(Duration::from_secs(90)))
+        );
+        assert_eq!(
+            "2h".parse::<DurationArg>(),
+            Ok(DurationArg(Duration::from_secs(7200)))
+        );
+        assert!("h".parse::<DurationArg>().is_err());
+        assert!("10".parse::<DurationArg>().is_err());
+        assert!("10y".parse::<DurationArg>().is_err());
+
+        let mut arguments = Arguments::new("spamming links");
+        assert_eq!(arguments.try_optional::<DurationArg>(), None);
+        assert_eq!(arguments.remaining(), "spamming links");
+    }
+}
@@ -0,0 +1,264 @@
+//! Loading, validating and writing the TOML config files of the client and the server.
+
+use crate::error::ConfigError;
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    env,
+    io::ErrorKind,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+/// Smaller frames could not even hold a chat message with its HWID.
+pub const MIN_BUFFER_SIZE: usize = 256;
+pub const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// The directory below `$XDG_CONFIG_HOME` the config files are looked up in.
+const CONFIG_DIRECTORY: &str = "rust_chat";
+
+/// A config file which checks its values after being read.
+pub trait ConfigFile: Serialize + DeserializeOwned + Default {
+    /// Returns the first invalid value.
+    fn validate(&self) -> Result<(), InvalidValue>;
+}
+
+/// A value which parsed fine but can't be used, `key` is its dotted path like `heartbeat.interval`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidValue {
+    pub key: String,
+    pub reason: String,
+}
+
+impl InvalidValue {
+    pub fn new(key: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+pub fn check_buffer_size(key: &str, buffer_size: usize) -> Result<(), InvalidValue> {
+    if !(MIN_BUFFER_SIZE..=MAX_BUFFER_SIZE).contains(&buffer_size) {
+        return Err(InvalidValue::new(
+            key,
+            format!("must be between {MIN_BUFFER_SIZE} and {MAX_BUFFER_SIZE} bytes"),
+        ));
+    }
+    Ok(())
+}
+
+pub fn check_endpoint(key: &str, endpoint: SocketAddr) -> Result<(), InvalidValue> {
+    if endpoint.port() == 0 {
+        return Err(InvalidValue::new(key, "needs a port other than 0"));
+    }
+    Ok(())
+}
+
+/// Rejects zero, e.g. for timeouts and intervals.
+pub fn check_nonzero<T: Default + PartialEq>(key: &str, value: T) -> Result<(), InvalidValue> {
+    if value == T::default() {
+        return Err(InvalidValue::new(key, "must not be 0"));
+    }
+    Ok(())
+}
+
+/// The file in the working directory if it exists there, otherwise the one in
+/// `$XDG_CONFIG_HOME/rust_chat` (`~/.config/rust_chat` if unset).
+pub fn default_path(file_name: &str) -> PathBuf {
+    let local = PathBuf::from(file_name);
+    if local.exists() {
+        return local;
+    }
+
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")));
+
+    match config_home {
+        Some(config_home) => config_home.join(CONFIG_DIRECTORY).join(file_name),
+        None => local,
+    }
+}
+
+/// Reads and validates the config file, a missing file yields the defaults without creating it.
+pub async fn load<C: ConfigFile>(path: &Path) -> Result<C, ConfigError> {
+    let contents = match fs::read(path).await {
+        Ok(contents) => contents,
+        Err(why) if why.kind() == ErrorKind::NotFound => {
+            tracing::info!("{} does not exist, using the defaults", path.display());
+            return Ok(C::default());
+        }
+        Err(source) => {
+            return Err(ConfigError::IO {
+                path: path.to_path_buf(),
+                source,
+            })
+        }
+    };
+
+    let source = String::from_utf8(contents).map_err(|_| ConfigError::Encoding {
+        path: path.to_path_buf(),
+    })?;
+    parse(path, &source)
+}
+
+/// Parses and validates the contents of the config file at `path`.
+pub fn parse<C: ConfigFile>(path: &Path, source: &str) -> Result<C, ConfigError> {
+    let config: C = toml::from_str(source).map_err(|why| {
+        let offset = why.span().map_or(0, |span| span.start);
+        let (line, column) = position(source, offset);
+        ConfigError::Parse {
+            path: path.to_path_buf(),
+            line,
+            column,
+            message: why.message().to_string(),
+        }
+    })?;
+
+    config.validate().map_err(|invalid| ConfigError::Invalid {
+        path: path.to_path_buf(),
+        line: find_line(source, &invalid.key),
+        key: invalid.key,
+        reason: invalid.reason,
+    })?;
+
+    Ok(config)
+}
+
+/// Writes the defaults to `path`, creating its directory. Never overwrites an existing file.
+pub async fn write_default<C: ConfigFile>(path: &Path) -> Result<C, ConfigError> {
+    let config = C::default();
+    let contents = toml::to_string_pretty(&config)?;
+    let io_error = |source| ConfigError::IO {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    if let Some(directory) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(directory).await.map_err(io_error)?;
+    }
+
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await
+    {
+        Ok(file) => file,
+        Err(why) if why.kind() == ErrorKind::AlreadyExists => {
+            return Err(ConfigError::Exists(path.to_path_buf()))
+        }
+        Err(why) => return Err(io_error(why)),
+    };
+    file.write_all(contents.as_bytes())
+        .await
+        .map_err(io_error)?;
+
+    Ok(config)
+}
+
+/// The 1-based line and column of a byte offset.
+fn position(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.len() - before.rfind('\n').map_or(0, |newline| newline + 1) + 1;
+    (line, column)
+}
+
+/// The line a dotted key is defined on, either as `key = ...` in its table or as a `[key]` table.
+fn find_line(source: &str, key: &str) -> Option<usize> {
+    let (table, name) = key.rsplit_once('.').unwrap_or(("", key));
+    let mut current = "";
+
+    for (index, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('[') {
+            current = header.trim_end_matches(']').trim();
+            if current == key {
+                return Some(index + 1);
+            }
+            continue;
+        }
+
+        let assigns = line
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.trim_start().starts_with('='));
+        if current == table && assigns {
+            return Some(index + 1);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_buffer_size, check_nonzero, parse, ConfigFile, InvalidValue};
+    use crate::error::ConfigError;
+    use std::path::Path;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    #[serde(default)]
+    struct TestConfig {
+        buffer_size: usize,
+        heartbeat: Heartbeat,
+    }
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Default)]
+    #[serde(default)]
+    struct Heartbeat {
+        interval: u64,
+    }
+
+    impl Default for TestConfig {
+        fn default() -> Self {
+            Self {
+                buffer_size: 2048,
+                heartbeat: Heartbeat { interval: 30 },
+            }
+        }
+    }
+
+    impl ConfigFile for TestConfig {
+        fn validate(&self) -> Result<(), InvalidValue> {
+            check_buffer_size("buffer_size", self.buffer_size)?;
+            check_nonzero("heartbeat.interval", self.heartbeat.interval)
+        }
+    }
+
+    fn error(source: &str) -> String {
+        parse::<TestConfig>(Path::new("test.toml"), source)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = parse::<TestConfig>(Path::new("test.toml"), "buffer_size = 4096\n").unwrap();
+        assert_eq!(config.buffer_size, 4096);
+        assert_eq!(config.heartbeat.interval, 30);
+
+        assert_eq!(
+            error("buffer_size = 4096\n\n[heartbeat]\ninterval = \"soon\"\n"),
+            "test.toml:4:12: invalid type: string \"soon\", expected u64"
+        );
+        assert_eq!(
+            error("# comment\nbuffer_size = 12\n"),
+            "test.toml:2: invalid `buffer_size`, must be between 256 and 16777216 bytes"
+        );
+        assert_eq!(
+            error("[heartbeat]\ninterval = 0\n"),
+            "test.toml:2: invalid `heartbeat.interval`, must not be 0"
+        );
+        assert!(matches!(
+            parse::<TestConfig>(Path::new("test.toml"), "buffer_size = 1\n"),
+            Err(ConfigError::Invalid { line: Some(1), .. })
+        ));
+    }
+}
@@ -1,12 +1,71 @@
-use std::{error::Error, fmt::Debug, num::TryFromIntError, string::FromUtf8Error};
+use std::{
+    error::Error,
+    fmt::Debug,
+    num::TryFromIntError,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigError {
-    #[error("Unable to access file!")]
-    IO(#[from] tokio::io::Error),
+    #[error("Unable to access {}! {source}", path.display())]
+    IO {
+        path: PathBuf,
+        source: tokio::io::Error,
+    },
 
     #[error("Unable to convert config to string!")]
     TOML(#[from] toml::ser::Error),
+
+    #[error("{} is not valid UTF-8", path.display())]
+    Encoding { path: PathBuf },
+
+    #[error("{}:{line}:{column}: {message}", path.display())]
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+
+    #[error("{}: invalid `{key}`, {reason}", location(path, *line))]
+    Invalid {
+        path: PathBuf,
+        line: Option<usize>,
+        key: String,
+        reason: String,
+    },
+
+    #[error("{} already exists", .0.display())]
+    Exists(PathBuf),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum LoggingError {
+    #[error("Invalid log filters! {0}")]
+    Filter(#[from] tracing_subscriber::filter::ParseError),
+
+    #[error("Unable to create the log directory {}! {source}", path.display())]
+    Directory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("Unable to open the log file {}! {source}", path.display())]
+    File {
+        path: PathBuf,
+        source: tracing_appender::rolling::InitError,
+    },
+
+    #[error("The logger is already installed")]
+    Installed(#[from] tracing_subscriber::util::TryInitError),
+}
+
+fn location(path: &Path, line: Option<usize>) -> String {
+    match line {
+        Some(line) => format!("{}:{line}", path.display()),
+        None => path.display().to_string(),
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -33,6 +92,20 @@ pub enum DeserializerError {
     FromUtf8Error(#[from] FromUtf8Error),
 }
 
+impl DeserializerError {
+    /// The variant, e.g. `invalid_buffer_length`, for metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::IO(_) => "io",
+            Self::Type(_) => "type",
+            Self::InvalidMessageType => "invalid_message_type",
+            Self::InvalidBufferLength => "invalid_buffer_length",
+            Self::InvalidData => "invalid_data",
+            Self::FromUtf8Error(_) => "utf8",
+        }
+    }
+}
+
 #[derive(thiserror::Error)]
 pub enum WriteToStreamError {
     #[error("Unable to write to stream")]
@@ -1,4 +1,7 @@
+pub mod arguments;
+pub mod config;
 pub mod error;
+pub mod logging;
 pub mod protocols;
 pub mod types;
 pub mod utils;
@@ -0,0 +1,202 @@
+use crate::{config::InvalidValue, error::LoggingError};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, IsTerminal},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use tracing_appender::rolling::{self, RollingFileAppender};
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter,
+    Layer, Registry,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    /// One object per line, including the fields of the spans
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    Hourly,
+    Daily,
+    Weekly,
+    Never,
+}
+
+impl From<Rotation> for rolling::Rotation {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Hourly => Self::HOURLY,
+            Rotation::Daily => Self::DAILY,
+            Rotation::Weekly => Self::WEEKLY,
+            Rotation::Never => Self::NEVER,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct LogConfig {
+    /// Filters like `info` or `chat_server=debug`, --log-level and RUST_LOG take precedence
+    pub level: String,
+    pub format: LogFormat,
+    /// Logs are written to stderr unless a file is given
+    pub file: Option<PathBuf>,
+    /// Rotated files get the date appended, e.g. `server.log.2024-01-31`
+    pub rotation: Rotation,
+    /// How many rotated files are kept, 0 keeps all of them
+    pub max_files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            file: None,
+            rotation: Rotation::Daily,
+            max_files: 7,
+        }
+    }
+}
+
+impl LogConfig {
+    /// `prefix` is the table the config is part of, e.g. `log`.
+    pub fn validate(&self, prefix: &str) -> Result<(), InvalidValue> {
+        if let Err(why) = EnvFilter::try_new(&self.level) {
+            return Err(InvalidValue::new(
+                format!("{prefix}.level"),
+                why.to_string(),
+            ));
+        }
+        if self
+            .file
+            .as_ref()
+            .is_some_and(|file| file.file_name().is_none())
+        {
+            return Err(InvalidValue::new(
+                format!("{prefix}.file"),
+                "must be the path of a file",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Allows replacing the filters when the config is reloaded.
+struct Filter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// Filters given by --log-level or RUST_LOG take precedence over the config
+    fixed: bool,
+}
+
+static FILTER: OnceLock<Filter> = OnceLock::new();
+
+/// Installs the global subscriber, `log` records of dependencies are forwarded to it.
+pub fn init(config: &LogConfig, filters: Option<&str>) -> Result<(), LoggingError> {
+    let filter = EnvFilter::try_new(filters.unwrap_or(&config.level))?;
+    let (filter, handle) = reload::Layer::new(filter);
+
+    let (writer, ansi) = match &config.file {
+        Some(file) => (BoxMakeWriter::new(appender(config, file)?), false),
+        None => (BoxMakeWriter::new(io::stderr), io::stderr().is_terminal()),
+    };
+    let format = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(format)
+        .try_init()?;
+
+    let _ = FILTER.set(Filter {
+        handle,
+        fixed: filters.is_some(),
+    });
+    Ok(())
+}
+
+fn appender(config: &LogConfig, file: &Path) -> Result<RollingFileAppender, LoggingError> {
+    let directory = file.parent().unwrap_or(file);
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    // Old files are looked for in the directory right away
+    std::fs::create_dir_all(directory).map_err(|source| LoggingError::Directory {
+        path: directory.to_path_buf(),
+        source,
+    })?;
+
+    RollingFileAppender::builder()
+        .rotation(config.rotation.into())
+        .filename_prefix(file_name)
+        .max_log_files(config.max_files)
+        .build(directory)
+        .map_err(|source| LoggingError::File {
+            path: file.to_path_buf(),
+            source,
+        })
+}
+
+/// Applies the `level` of the config, unless the filters were given on startup.
+pub fn configure(filters: &str) {
+    let Some(filter) = FILTER.get() else {
+        return;
+    };
+    if filter.fixed {
+        return;
+    }
+
+    match EnvFilter::try_new(filters) {
+        Ok(new) => {
+            if let Err(why) = filter.handle.reload(new) {
+                tracing::error!("Unable to apply the log filters! {why}");
+            }
+        }
+        Err(why) => tracing::warn!("Ignoring the invalid log filters `{filters}`! {why}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogConfig, LogFormat};
+
+    #[test]
+    fn test_log_config() {
+        let config: LogConfig = toml::from_str(
+            r#"
+            level = "warn,chat_server=debug"
+            format = "json"
+            file = "logs/server.log"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.format, LogFormat::Json);
+        assert!(config.validate("log").is_ok());
+
+        let config = LogConfig {
+            level: "chat_server=loud".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(config.validate("log").unwrap_err().key, "log.level");
+
+        let config = LogConfig {
+            file: Some("logs/..".into()),
+            ..Default::default()
+        };
+        assert_eq!(config.validate("log").unwrap_err().key, "log.file");
+    }
+}
@@ -12,6 +12,20 @@ pub enum ClientMessageType {
     ChatMessage,
     ChangeUsername,
     RequestAuthentication,
+    Ping,
+    Pong,
+    ResumeSession,
+    EditMessage,
+    DeleteMessage,
+    FetchThread,
+    FollowThread,
+    UnfollowThread,
+    AddReaction,
+    RemoveReaction,
+    MarkRead,
+    StartUpload,
+    UploadChunk,
+    DownloadChunk,
     InvalidEvent,
 }
 
@@ -21,21 +35,62 @@ impl From<u8> for ClientMessageType {
             0 => Self::ChatMessage,
             1 => Self::ChangeUsername,
             2 => Self::RequestAuthentication,
+            3 => Self::Ping,
+            4 => Self::Pong,
+            5 => Self::ResumeSession,
+            6 => Self::EditMessage,
+            7 => Self::DeleteMessage,
+            8 => Self::FetchThread,
+            9 => Self::FollowThread,
+            10 => Self::UnfollowThread,
+            11 => Self::AddReaction,
+            12 => Self::RemoveReaction,
+            13 => Self::MarkRead,
+            14 => Self::StartUpload,
+            15 => Self::UploadChunk,
+            16 => Self::DownloadChunk,
             _ => Self::InvalidEvent,
         }
     }
 }
 
+impl ClientMessageType {
+    /// e.g. `chat_message`, for logs and metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ChatMessage => "chat_message",
+            Self::ChangeUsername => "change_username",
+            Self::RequestAuthentication => "request_authentication",
+            Self::Ping => "ping",
+            Self::Pong => "pong",
+            Self::ResumeSession => "resume_session",
+            Self::EditMessage => "edit_message",
+            Self::DeleteMessage => "delete_message",
+            Self::FetchThread => "fetch_thread",
+            Self::FollowThread => "follow_thread",
+            Self::UnfollowThread => "unfollow_thread",
+            Self::AddReaction => "add_reaction",
+            Self::RemoveReaction => "remove_reaction",
+            Self::MarkRead => "mark_read",
+            Self::StartUpload => "start_upload",
+            Self::UploadChunk => "upload_chunk",
+            Self::DownloadChunk => "download_chunk",
+            Self::InvalidEvent => "invalid",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
 #[Belonging(ClientMessageType)]
 pub struct ChatMessage {
     pub hwid: String,
     pub content: String,
+    /// The id of the message this one replies to, empty if it starts no thread
+    pub reply_to: String,
 }
 
 #[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
 #[Belonging(ClientMessageType)]
-
 pub struct ChangeUsername {
     pub hwid: String,
     pub new_username: String,
@@ -47,3 +102,134 @@ pub struct RequestAuthentication {
     pub hwid: String,
     pub name: String,
 }
+
+/// Sent periodically to check that the server is still alive, it answers with a `Pong`.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct Ping {
+    pub token: String,
+}
+
+/// The answer to a `Ping` of the server, echoing its token.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct Pong {
+    pub token: String,
+}
+
+/// Authenticates like `RequestAuthentication`, but asks the server to restore the session of
+/// a previous connection. The server falls back to a new session if the token is unknown.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct ResumeSession {
+    pub hwid: String,
+    pub name: String,
+    pub token: String,
+}
+
+/// Replaces the content of a message, only its author and moderators may edit it.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct EditMessage {
+    pub hwid: String,
+    pub id: String,
+    pub content: String,
+}
+
+/// Deletes a message for everyone, only its author and moderators may delete it.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct DeleteMessage {
+    pub hwid: String,
+    pub id: String,
+}
+
+/// Asks for the first message of a thread and all replies to it, any message of the thread
+/// can be given. They are sent as `ThreadMessage`s.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct FetchThread {
+    pub hwid: String,
+    pub id: String,
+}
+
+/// Receive the replies to a thread as `ThreadMessage`s, even when in another room.
+/// Followed threads are forgotten when the connection is closed.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct FollowThread {
+    pub hwid: String,
+    pub id: String,
+}
+
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct UnfollowThread {
+    pub hwid: String,
+    pub id: String,
+}
+
+/// Reacts to a message with an emoji or a shortcode like `:tada:`. Everyone in the room of the
+/// message receives the new counts as `ReactionsUpdated`.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct AddReaction {
+    pub hwid: String,
+    pub id: String,
+    pub reaction: String,
+}
+
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct RemoveReaction {
+    pub hwid: String,
+    pub id: String,
+    pub reaction: String,
+}
+
+/// Marks the messages of a room as read up to the given one, which become the unread counts of
+/// the `Welcome` after the next login.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct MarkRead {
+    pub hwid: String,
+    pub room: String,
+    /// Empty marks every message of the room as read
+    pub id: String,
+}
+
+/// Announces a file to share in the current room, answered with an `UploadOffset`. Starting the
+/// same file again continues the upload where it stopped.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct StartUpload {
+    pub hwid: String,
+    /// Without any directories
+    pub name: String,
+    /// In bytes
+    pub size: String,
+    /// SHA-256 of the whole file as hex, checked once the last chunk arrived
+    pub sha256: String,
+}
+
+/// A part of an upload, answered with the next `UploadOffset` or a `FileShared` after the last.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct UploadChunk {
+    pub hwid: String,
+    pub id: String,
+    pub offset: String,
+    /// The bytes as hex
+    pub data: String,
+}
+
+/// Asks for a part of a shared file, answered with a `FileChunk`.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ClientMessageType)]
+pub struct DownloadChunk {
+    pub hwid: String,
+    pub id: String,
+    pub offset: String,
+    /// At most this many bytes, see `utils::chunk_size`
+    pub length: String,
+}
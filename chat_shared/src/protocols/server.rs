@@ -11,6 +11,25 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 pub enum ServerMessageType {
     BroadcastMessage,
     AuthenticateToken,
+    UserJoined,
+    UserLeft,
+    SystemMessage,
+    DirectMessage,
+    UsernameChanged,
+    MessageDeleted,
+    Ping,
+    Pong,
+    RoomMembers,
+    ServerShutdown,
+    MessageEdited,
+    MessageSent,
+    ThreadMessage,
+    ReactionsUpdated,
+    Mention,
+    Welcome,
+    UploadOffset,
+    FileShared,
+    FileChunk,
     InvalidEvent,
 }
 
@@ -19,16 +38,71 @@ impl From<u8> for ServerMessageType {
         match value {
             0 => Self::BroadcastMessage,
             1 => Self::AuthenticateToken,
+            2 => Self::UserJoined,
+            3 => Self::UserLeft,
+            4 => Self::SystemMessage,
+            5 => Self::DirectMessage,
+            6 => Self::UsernameChanged,
+            7 => Self::MessageDeleted,
+            8 => Self::Ping,
+            9 => Self::Pong,
+            10 => Self::RoomMembers,
+            11 => Self::ServerShutdown,
+            12 => Self::MessageEdited,
+            13 => Self::MessageSent,
+            14 => Self::ThreadMessage,
+            15 => Self::ReactionsUpdated,
+            16 => Self::Mention,
+            17 => Self::Welcome,
+            18 => Self::UploadOffset,
+            19 => Self::FileShared,
+            20 => Self::FileChunk,
             _ => Self::InvalidEvent,
         }
     }
 }
 
+impl ServerMessageType {
+    /// e.g. `broadcast_message`, for logs and metrics.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::BroadcastMessage => "broadcast_message",
+            Self::AuthenticateToken => "authenticate_token",
+            Self::UserJoined => "user_joined",
+            Self::UserLeft => "user_left",
+            Self::SystemMessage => "system_message",
+            Self::DirectMessage => "direct_message",
+            Self::UsernameChanged => "username_changed",
+            Self::MessageDeleted => "message_deleted",
+            Self::Ping => "ping",
+            Self::Pong => "pong",
+            Self::RoomMembers => "room_members",
+            Self::ServerShutdown => "server_shutdown",
+            Self::MessageEdited => "message_edited",
+            Self::MessageSent => "message_sent",
+            Self::ThreadMessage => "thread_message",
+            Self::ReactionsUpdated => "reactions_updated",
+            Self::Mention => "mention",
+            Self::Welcome => "welcome",
+            Self::UploadOffset => "upload_offset",
+            Self::FileShared => "file_shared",
+            Self::FileChunk => "file_chunk",
+            Self::InvalidEvent => "invalid",
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
 #[Belonging(ServerMessageType)]
 pub struct BroadcastMessage {
+    /// Assigned by the server, used to edit or delete the message
+    pub id: String,
     pub username: String,
     pub content: String,
+    /// Unix timestamp of when the server received the message
+    pub timestamp: String,
+    /// The id of the message this one replies to, empty if it is none
+    pub reply_to: String,
 }
 
 #[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
@@ -36,3 +110,195 @@ pub struct BroadcastMessage {
 pub struct AuthenticateToken {
     pub token: String,
 }
+
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct UserJoined {
+    pub username: String,
+    pub room: String,
+}
+
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct UserLeft {
+    pub username: String,
+    pub room: String,
+}
+
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct SystemMessage {
+    pub content: String,
+}
+
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct DirectMessage {
+    pub username: String,
+    pub content: String,
+    /// Unix timestamp, messages sent while the recipient was offline arrive later
+    pub timestamp: String,
+}
+
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct UsernameChanged {
+    pub old_username: String,
+    pub new_username: String,
+}
+
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct MessageDeleted {
+    pub id: String,
+    pub room: String,
+}
+
+/// Sent periodically to check that the client is still alive, it answers with a `Pong`.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct Ping {
+    pub token: String,
+}
+
+/// The answer to a `Ping` of the client, echoing its token.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct Pong {
+    pub token: String,
+}
+
+/// Everyone in a room, sent to a client after it joined the room. Long lists are split over
+/// several frames.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct RoomMembers {
+    pub room: String,
+    /// Separated by spaces, which usernames can't contain
+    pub usernames: String,
+    /// How many members the room has in all frames together
+    pub total: String,
+}
+
+/// The new content of a message, sent to everyone in the room of the message.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct MessageEdited {
+    pub id: String,
+    pub room: String,
+    pub content: String,
+    /// Unix timestamp
+    pub edited_at: String,
+}
+
+/// Tells the author the id of its message, which is not sent back to it.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct MessageSent {
+    pub id: String,
+    pub room: String,
+    /// Unix timestamp
+    pub timestamp: String,
+}
+
+/// A message of a thread, either requested with `FetchThread` or a reply to a followed thread
+/// which was sent in another room.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct ThreadMessage {
+    /// The id of the first message of the thread
+    pub thread: String,
+    pub room: String,
+    pub id: String,
+    pub username: String,
+    pub content: String,
+    /// Unix timestamp
+    pub timestamp: String,
+    /// Empty for the first message
+    pub reply_to: String,
+}
+
+/// The reactions to a message changed, sent to everyone in the room of the message.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct ReactionsUpdated {
+    pub id: String,
+    pub room: String,
+    /// Separated by spaces, each as `reaction=count`. Reactions contain neither spaces nor `=`
+    pub reactions: String,
+}
+
+/// Someone mentioned the client, sent in addition to the message itself and also when the
+/// client is in another room.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct Mention {
+    pub id: String,
+    pub room: String,
+    pub username: String,
+    pub content: String,
+    /// How the client was mentioned, e.g. `@here` or `@name`
+    pub mention: String,
+    /// Unix timestamp
+    pub timestamp: String,
+}
+
+/// Sent after authenticating and joining the first room, followed by the direct messages and
+/// mentions which arrived while the client was offline. Long lists of rooms are split over
+/// several frames, only the first one counts the pending messages.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct Welcome {
+    /// Separated by spaces, each as `room=count`. Only rooms which were marked read before count
+    pub unread: String,
+    /// How many direct messages and mentions follow
+    pub pending: String,
+}
+
+/// Where an upload continues, sent after `StartUpload` and after every chunk but the last.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct UploadOffset {
+    pub id: String,
+    /// Tells the uploads of the client apart
+    pub sha256: String,
+    pub offset: String,
+    /// The most bytes the server accepts in a single chunk
+    pub chunk_size: String,
+}
+
+/// A file was uploaded completely, sent to everyone in its room including the uploader.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct FileShared {
+    pub id: String,
+    pub room: String,
+    pub username: String,
+    pub name: String,
+    pub size: String,
+    pub sha256: String,
+    /// Unix timestamp
+    pub timestamp: String,
+}
+
+/// A part of a shared file, with everything needed to save it.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct FileChunk {
+    pub id: String,
+    pub name: String,
+    pub size: String,
+    pub sha256: String,
+    pub offset: String,
+    /// The bytes as hex, empty past the end of the file
+    pub data: String,
+}
+
+/// Sent to every client before the server stops.
+#[derive(Debug, PartialEq, Eq, chat_macro::Serialize, chat_macro::Deserialize)]
+#[Belonging(ServerMessageType)]
+pub struct ServerShutdown {
+    pub reason: String,
+    /// Seconds to wait before reconnecting
+    pub reconnect_after: String,
+}
@@ -1,7 +1,8 @@
 use crate::error::DeserializerError;
-use std::io::Cursor;
-use tokio::io::AsyncReadExt;
+use std::io::{Cursor, ErrorKind};
+use tokio::io::{AsyncRead, AsyncReadExt};
 
+#[allow(unused_macros)]
 macro_rules! read_type {
     ($cursor:expr, $ty:ty) => {{
         use std::io::Read;
@@ -14,6 +15,7 @@ macro_rules! read_type {
     }};
 }
 
+#[allow(unused_macros)]
 macro_rules! read {
     ($cursor:expr, String, $length:expr) => {{
         use std::io::Read;
@@ -44,7 +46,7 @@ pub async fn read_string_from_buffer(
 ) -> Result<Option<String>, DeserializerError> {
     let length = buffer.read_u32().await?;
     let mut temp_buffer = vec![0u8; length as usize];
-    buffer.read(&mut temp_buffer).await?;
+    buffer.read_exact(&mut temp_buffer).await?;
 
     match String::from_utf8(temp_buffer) {
         Ok(b) => Ok(Some(b)),
@@ -55,7 +57,184 @@ pub async fn read_string_from_buffer(
 pub async fn prepare_inner_cursor(cursor: &mut Cursor<&[u8]>) -> std::io::Result<Cursor<Vec<u8>>> {
     let inner_length = cursor.read_u32().await?;
     let mut buffer = vec![0u8; inner_length as usize];
-    cursor.read(&mut buffer).await?;
+    cursor.read_exact(&mut buffer).await?;
 
     Ok(Cursor::new(buffer))
 }
+
+/// File names travel along with every chunk, so they are kept short.
+pub const MAX_FILE_NAME_LENGTH: usize = 128;
+
+/// Room in a frame for the fields of a file chunk besides its data.
+const CHUNK_OVERHEAD: usize = 512;
+
+/// How many bytes of a file fit into a frame of at most `buffer_size` bytes, they are sent as
+/// hex. Zero if the buffer is too small for file transfers.
+pub fn chunk_size(buffer_size: usize) -> usize {
+    buffer_size.saturating_sub(CHUNK_OVERHEAD) / 2
+}
+
+/// Room in a frame for the fields of a message besides its content, e.g. the ids, room and
+/// username of a `ThreadMessage`.
+const MESSAGE_OVERHEAD: usize = 192;
+
+/// How long the content of a message may be, so it still fits into a frame of at most
+/// `buffer_size` bytes once the server added its fields.
+pub fn max_content_length(buffer_size: usize) -> usize {
+    buffer_size.saturating_sub(MESSAGE_OVERHEAD)
+}
+
+/// Reads exactly one message (type, content length and content) from the stream.
+///
+/// The returned buffer can be passed directly into `Deserialize::deserialize`.
+/// Returns `None` if the stream was closed before a new message started.
+pub async fn read_frame<R>(
+    stream: &mut R,
+    max_length: usize,
+) -> Result<Option<Vec<u8>>, DeserializerError>
+where
+    R: AsyncRead + Unpin,
+{
+    let message_type = match stream.read_u8().await {
+        Ok(message_type) => message_type,
+        Err(why) if why.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(why) => return Err(why.into()),
+    };
+
+    let length = stream.read_u32().await?;
+    if usize::try_from(length)? > max_length {
+        return Err(DeserializerError::InvalidBufferLength);
+    }
+
+    let mut frame = vec![message_type];
+    frame.extend(length.to_be_bytes());
+    frame.resize(frame.len() + length as usize, 0);
+    stream.read_exact(&mut frame[5..]).await?;
+
+    Ok(Some(frame))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{chunk_size, max_content_length, read_frame, MAX_FILE_NAME_LENGTH};
+    use crate::{
+        config::MIN_BUFFER_SIZE,
+        error::DeserializerError,
+        protocols::server::{BroadcastMessage, FileChunk, Mention, ThreadMessage},
+        types::{Deserialize, Serialize},
+    };
+
+    #[tokio::test]
+    async fn test_read_frame_splits_messages() {
+        let first = BroadcastMessage {
+            id: "1".to_string(),
+            username: "A".to_string(),
+            content: "first".to_string(),
+            timestamp: "1700000000".to_string(),
+            reply_to: String::new(),
+        };
+        let second = BroadcastMessage {
+            id: "2".to_string(),
+            username: "B".to_string(),
+            content: "second".to_string(),
+            timestamp: "1700000001".to_string(),
+            reply_to: String::new(),
+        };
+
+        let mut stream = first.serialize().await.unwrap();
+        stream.extend(second.serialize().await.unwrap());
+        let mut stream = &stream[..];
+
+        let frame = read_frame(&mut stream, 1024).await.unwrap().unwrap();
+        assert_eq!(BroadcastMessage::deserialize(&frame).await.unwrap(), first);
+        let frame = read_frame(&mut stream, 1024).await.unwrap().unwrap();
+        assert_eq!(BroadcastMessage::deserialize(&frame).await.unwrap(), second);
+        assert!(read_frame(&mut stream, 1024).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_frame_rejects_oversized_messages() {
+        let message = BroadcastMessage {
+            id: "1".to_string(),
+            username: "A".to_string(),
+            content: "x".repeat(64),
+            timestamp: "1700000000".to_string(),
+            reply_to: String::new(),
+        };
+        let serialized = message.serialize().await.unwrap();
+
+        assert!(matches!(
+            read_frame(&mut &serialized[..], 16).await,
+            Err(DeserializerError::InvalidBufferLength)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_chunks_fit_into_frames() {
+        let buffer_size = 2048;
+        let chunk = FileChunk {
+            id: "f".repeat(32),
+            name: "n".repeat(MAX_FILE_NAME_LENGTH),
+            size: u64::MAX.to_string(),
+            sha256: "0".repeat(64),
+            offset: u64::MAX.to_string(),
+            data: "ab".repeat(chunk_size(buffer_size)),
+        };
+        let serialized = chunk.serialize().await.unwrap();
+
+        let frame = read_frame(&mut &serialized[..], buffer_size).await.unwrap();
+        assert!(frame.is_some());
+        assert_eq!(chunk_size(256), 0);
+    }
+
+    #[tokio::test]
+    async fn test_messages_fit_into_frames() {
+        for buffer_size in [MIN_BUFFER_SIZE, 2048] {
+            let content = "x".repeat(max_content_length(buffer_size));
+            let thread = ThreadMessage {
+                thread: u64::MAX.to_string(),
+                room: "r".repeat(32),
+                id: u64::MAX.to_string(),
+                username: "u".repeat(32),
+                content: content.clone(),
+                timestamp: u64::MAX.to_string(),
+                reply_to: u64::MAX.to_string(),
+            };
+            let mention = Mention {
+                id: u64::MAX.to_string(),
+                room: "r".repeat(32),
+                username: "u".repeat(32),
+                content,
+                mention: format!("@{}", "u".repeat(32)),
+                timestamp: u64::MAX.to_string(),
+            };
+
+            let serialized = thread.serialize().await.unwrap();
+            let frame = read_frame(&mut &serialized[..], buffer_size).await.unwrap();
+            assert!(frame.is_some());
+            let serialized = mention.serialize().await.unwrap();
+            let frame = read_frame(&mut &serialized[..], buffer_size).await.unwrap();
+            assert!(frame.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_utf8_is_an_error() {
+        let message = BroadcastMessage {
+            id: "1".to_string(),
+            username: "A".to_string(),
+            content: "é".to_string(),
+            timestamp: "1700000000".to_string(),
+            reply_to: String::new(),
+        };
+        let mut serialized = message.serialize().await.unwrap();
+        // Cuts the two bytes of `é` in half
+        let last = serialized.iter().rposition(|&b| b == 0xa9).unwrap();
+        serialized[last] = b'x';
+
+        assert!(matches!(
+            BroadcastMessage::deserialize(&serialized).await,
+            Err(DeserializerError::InvalidData)
+        ));
+    }
+}